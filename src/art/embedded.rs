@@ -0,0 +1,304 @@
+//! Cover art as each format stores it: `APIC` frames of the ID3 tags of MP3, WAV and AIFF
+//! files, picture blocks of FLAC files and the `covr` atom of MP4 files
+use std::path::Path;
+
+use id3::frame::PictureType;
+use id3::{TagLike, Version};
+use metaflac::block::{Block, BlockType};
+use mp4ameta::{Img, ImgFmt};
+
+use super::ArtError;
+use crate::error::FmmdError;
+use crate::metadata::{self, Format, MetadataError};
+
+/// An embedded image
+pub struct Picture {
+    /// Whether it is the front cover. MP4 files don't say, so all of their images are.
+    pub front: bool,
+    pub data: Vec<u8>,
+}
+
+/// The tag of a file holding its cover art, read so that it can be written back
+pub enum Embedded {
+    Id3(id3::Tag, Version),
+    Flac(metaflac::Tag),
+    Mp4(mp4ameta::Tag),
+}
+
+impl Embedded {
+    pub fn read(path: &Path) -> Result<Embedded, FmmdError> {
+        match metadata::detect(path)? {
+            Format::Mp3 | Format::Wav | Format::Aiff => {
+                let tag = id3::no_tag_ok(id3::Tag::read_from_path(path))
+                    .map_err(MetadataError::from)?
+                    .unwrap_or_default();
+                // ID3v2.2 can't be written, so those tags are upgraded
+                let version = match tag.version() {
+                    Version::Id3v23 => Version::Id3v23,
+                    _ => Version::Id3v24,
+                };
+                Ok(Embedded::Id3(tag, version))
+            }
+            Format::Flac => Ok(Embedded::Flac(
+                metaflac::Tag::read_from_path(path).map_err(MetadataError::from)?,
+            )),
+            Format::Mp4 => Ok(Embedded::Mp4(
+                mp4ameta::Tag::read_from_path(path).map_err(MetadataError::from)?,
+            )),
+            format => Err(ArtError::UnsupportedFormat(format).into()),
+        }
+    }
+
+    pub fn pictures(&self) -> Vec<Picture> {
+        match self {
+            Embedded::Id3(tag, _) => tag
+                .pictures()
+                .map(|picture| Picture {
+                    front: picture.picture_type == PictureType::CoverFront,
+                    data: picture.data.clone(),
+                })
+                .collect(),
+            Embedded::Flac(tag) => tag
+                .pictures()
+                .map(|picture| Picture {
+                    front: picture.picture_type == metaflac::block::PictureType::CoverFront,
+                    data: picture.data.clone(),
+                })
+                .collect(),
+            Embedded::Mp4(tag) => tag
+                .artworks()
+                .map(|image| Picture {
+                    front: true,
+                    data: image.data.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The front cover, or the first image when none is marked as the front cover
+    pub fn front(&self) -> Option<Picture> {
+        let mut pictures = self.pictures();
+        let position = pictures.iter().position(|picture| picture.front);
+        match position {
+            Some(position) => Some(pictures.swap_remove(position)),
+            None => pictures.into_iter().next(),
+        }
+    }
+
+    /// Replaces the front cover, keeping all other images
+    pub fn set_front(&mut self, mime_type: &str, data: Vec<u8>) -> Result<(), ArtError> {
+        match self {
+            Embedded::Id3(tag, _) => {
+                tag.remove_picture_by_type(PictureType::CoverFront);
+                tag.add_frame(id3::frame::Picture {
+                    mime_type: mime_type.to_string(),
+                    picture_type: PictureType::CoverFront,
+                    description: String::new(),
+                    data,
+                });
+            }
+            Embedded::Flac(tag) => {
+                tag.add_picture(mime_type, metaflac::block::PictureType::CoverFront, data)
+            }
+            Embedded::Mp4(tag) => tag.set_artwork(Img::new(image_format(mime_type)?, data)),
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_all(&mut self) {
+        match self {
+            Embedded::Id3(tag, _) => tag.remove_all_pictures(),
+            Embedded::Flac(tag) => tag.remove_blocks(BlockType::Picture),
+            Embedded::Mp4(tag) => tag.remove_artworks(),
+        }
+    }
+
+    /// Replaces the images `change` returns JPEG data for, keeping their type and order.
+    /// Returns the number of images replaced.
+    pub fn replace(
+        &mut self,
+        mut change: impl FnMut(&[u8]) -> Result<Option<Vec<u8>>, ArtError>,
+    ) -> Result<usize, ArtError> {
+        let mut replaced = 0;
+
+        match self {
+            Embedded::Id3(tag, _) => {
+                let mut pictures: Vec<id3::frame::Picture> = tag.pictures().cloned().collect();
+                for picture in &mut pictures {
+                    if let Some(data) = change(&picture.data)? {
+                        picture.mime_type = "image/jpeg".to_string();
+                        picture.data = data;
+                        replaced += 1;
+                    }
+                }
+
+                tag.remove_all_pictures();
+                for picture in pictures {
+                    tag.add_frame(picture);
+                }
+            }
+            Embedded::Flac(tag) => {
+                let mut pictures: Vec<metaflac::block::Picture> = tag.pictures().cloned().collect();
+                for picture in &mut pictures {
+                    if let Some(data) = change(&picture.data)? {
+                        let (width, height) = super::resize::dimensions(&data)?;
+                        picture.mime_type = "image/jpeg".to_string();
+                        picture.width = width;
+                        picture.height = height;
+                        picture.depth = 24;
+                        picture.num_colors = 0;
+                        picture.data = data;
+                        replaced += 1;
+                    }
+                }
+
+                tag.remove_blocks(BlockType::Picture);
+                for picture in pictures {
+                    tag.push_block(Block::Picture(picture));
+                }
+            }
+            Embedded::Mp4(tag) => {
+                let mut images: Vec<_> = tag.take_artworks().collect();
+                for image in &mut images {
+                    if let Some(data) = change(&image.data)? {
+                        *image = Img::jpeg(data);
+                        replaced += 1;
+                    }
+                }
+
+                tag.set_artworks(images);
+            }
+        }
+
+        Ok(replaced)
+    }
+
+    pub fn write(&mut self, path: &Path) -> Result<(), ArtError> {
+        let result = match self {
+            Embedded::Id3(tag, version) => tag
+                .write_to_path(path, *version)
+                .map_err(MetadataError::from),
+            Embedded::Flac(tag) => metadata::write_flac(tag, path),
+            Embedded::Mp4(tag) => tag.write_to_path(path).map_err(MetadataError::from),
+        };
+
+        result.map_err(ArtError::Write)
+    }
+}
+
+/// The MP4 image format of a MIME type, MP4 files can't hold anything else
+fn image_format(mime_type: &str) -> Result<ImgFmt, ArtError> {
+    match mime_type {
+        "image/jpeg" => Ok(ImgFmt::Jpeg),
+        "image/png" => Ok(ImgFmt::Png),
+        _ => Err(ArtError::UnsupportedImage),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::art::resize;
+    use crate::fixtures;
+
+    /// The images of `file` in the order they are stored in, along with whether they are
+    /// the front cover
+    fn pictures(file: &Path) -> Vec<(bool, Vec<u8>)> {
+        Embedded::read(file)
+            .unwrap()
+            .pictures()
+            .into_iter()
+            .map(|picture| (picture.front, picture.data))
+            .collect()
+    }
+
+    /// Embeds a back cover and a front cover, replaces the front cover and then re-encodes
+    /// it, writing and reading the file after each step
+    fn round_trip(file: &Path) {
+        let (back, front, new_front) = (
+            fixtures::png(4, 4, 1),
+            fixtures::png(4, 4, 2),
+            fixtures::png(4, 4, 3),
+        );
+
+        let mut embedded = Embedded::read(file).unwrap();
+        match &mut embedded {
+            Embedded::Id3(tag, _) => {
+                tag.add_frame(id3::frame::Picture {
+                    mime_type: "image/png".to_string(),
+                    picture_type: PictureType::CoverBack,
+                    description: String::new(),
+                    data: back.clone(),
+                });
+            }
+            Embedded::Flac(tag) => tag.add_picture(
+                "image/png",
+                metaflac::block::PictureType::CoverBack,
+                back.clone(),
+            ),
+            Embedded::Mp4(_) => unreachable!(),
+        }
+        embedded.set_front("image/png", front.clone()).unwrap();
+        embedded.write(file).unwrap();
+        assert_eq!(
+            pictures(file),
+            [(false, back.clone()), (true, front.clone())]
+        );
+
+        let mut embedded = Embedded::read(file).unwrap();
+        embedded.set_front("image/png", new_front.clone()).unwrap();
+        embedded.write(file).unwrap();
+        assert_eq!(
+            pictures(file),
+            [(false, back.clone()), (true, new_front.clone())]
+        );
+
+        let jpeg = resize::resize(&new_front, 2, usize::MAX)
+            .unwrap()
+            .unwrap()
+            .data;
+        let mut embedded = Embedded::read(file).unwrap();
+        let replaced = embedded
+            .replace(|data| Ok((data == new_front).then(|| jpeg.clone())))
+            .unwrap();
+        assert_eq!(replaced, 1);
+        embedded.write(file).unwrap();
+        assert_eq!(pictures(file), [(false, back), (true, jpeg)]);
+    }
+
+    #[test]
+    fn id3_covers_survive_being_written() {
+        let dir = fixtures::directory("embedded-id3");
+        let file = dir.join("song.mp3");
+        fs::write(&file, fixtures::mp3(1, 10)).unwrap();
+
+        round_trip(&file);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn flac_covers_survive_being_written() {
+        let dir = fixtures::directory("embedded-flac");
+        let file = dir.join("song.flac");
+        fs::write(&file, fixtures::flac(b"frames")).unwrap();
+
+        round_trip(&file);
+
+        // Picture blocks describe the image they hold
+        let Embedded::Flac(tag) = Embedded::read(&file).unwrap() else {
+            unreachable!();
+        };
+        let front = tag
+            .pictures()
+            .find(|picture| picture.picture_type == metaflac::block::PictureType::CoverFront)
+            .unwrap();
+        assert_eq!(front.mime_type, "image/jpeg");
+        assert_eq!((front.width, front.height), (2, 2));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
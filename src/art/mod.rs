@@ -0,0 +1,457 @@
+//! The `art` subcommand, moving cover art between files and their album folders.
+//!
+//! `extract` saves the front cover of an album next to its files, `embed` does the
+//! opposite, `remove` drops all embedded images and `resize` scales down and re-encodes
+//! images that are too large. Albums are the files of a directory.
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Subcommand};
+use thiserror::Error;
+
+use crate::error::FmmdError;
+use crate::input::InputArgs;
+use crate::metadata::{Format, MetadataError};
+use crate::report::{print_error, Status};
+
+mod embedded;
+mod resize;
+
+use embedded::Embedded;
+
+/// Names of folder images, in the order they are looked for
+const FOLDER_IMAGES: &[&str] = &[
+    "cover.jpg",
+    "cover.jpeg",
+    "cover.png",
+    "folder.jpg",
+    "folder.jpeg",
+    "folder.png",
+    "front.jpg",
+    "front.jpeg",
+    "front.png",
+];
+
+#[derive(Error, Debug)]
+pub enum ArtError {
+    #[error("Cover art in {0} files is not supported")]
+    UnsupportedFormat(Format),
+
+    #[error("The image is neither a JPEG nor a PNG")]
+    UnsupportedImage,
+
+    #[error("Could not convert the image: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("Could not write the cover art: {0}")]
+    Write(MetadataError),
+
+    #[error("Could not access the image: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Args)]
+pub struct ArtArgs {
+    #[command(subcommand)]
+    command: ArtCommand,
+}
+
+#[derive(Subcommand)]
+enum ArtCommand {
+    /// Save the front cover of each album as an image in its folder
+    Extract(ExtractArgs),
+
+    /// Embed the image in the folder of each album as the front cover of its files
+    Embed(EmbedArgs),
+
+    /// Remove all embedded images
+    Remove(ChangeArgs),
+
+    /// Scale down and re-encode embedded images that are too large
+    Resize(ResizeArgs),
+}
+
+/// Options shared by everything that changes files
+#[derive(Args)]
+struct ChangeArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Show the changes without making them
+    #[arg(short, long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    #[command(flatten)]
+    change: ChangeArgs,
+
+    /// Name of the image without its extension, which follows the type of the image
+    #[arg(long, default_value = "cover")]
+    name: String,
+
+    /// Write the image even if the folder has one already
+    #[arg(short, long)]
+    force: bool,
+}
+
+#[derive(Args)]
+struct EmbedArgs {
+    #[command(flatten)]
+    change: ChangeArgs,
+
+    /// Embed this image into every file instead of the one in the folder of each album
+    #[arg(long, value_name = "FILE")]
+    image: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ResizeArgs {
+    #[command(flatten)]
+    change: ChangeArgs,
+
+    /// Largest width and height images may have
+    #[arg(long, value_name = "PIXELS", default_value_t = 1000)]
+    max_dimension: u32,
+
+    /// Largest size images may have
+    #[arg(long, value_name = "KB", default_value_t = 500)]
+    max_size: usize,
+}
+
+pub fn art(args: &ArtArgs) -> Result<Status, FmmdError> {
+    match &args.command {
+        ArtCommand::Extract(args) => extract(args),
+        ArtCommand::Embed(args) => embed(args),
+        ArtCommand::Remove(args) => remove(args),
+        ArtCommand::Resize(args) => resize(args),
+    }
+}
+
+fn extract(args: &ExtractArgs) -> Result<Status, FmmdError> {
+    let (albums, mut errors) = collect_albums(&args.change.input);
+    let mut extracted = 0;
+    let mut present = 0;
+    let mut missing = 0;
+
+    for (dir, files) in &albums {
+        if !args.force && folder_image(dir).is_some() {
+            present += 1;
+            continue;
+        }
+
+        let mut front = None;
+        for file in files {
+            match Embedded::read(file) {
+                Ok(embedded) => {
+                    if let Some(picture) = embedded.front() {
+                        front = Some((file, picture));
+                        break;
+                    }
+                }
+                Err(error) => {
+                    print_error(file, error);
+                    errors += 1;
+                }
+            }
+        }
+
+        let Some((file, picture)) = front else {
+            missing += 1;
+            continue;
+        };
+
+        let Some(extension) = extension(&picture.data) else {
+            print_error(file, FmmdError::from(ArtError::UnsupportedImage));
+            errors += 1;
+            continue;
+        };
+
+        let target = dir.join(format!("{}.{}", args.name, extension));
+        println!("{} -> {}", file.display(), target.display());
+        if args.change.dry_run {
+            continue;
+        }
+
+        match fs::write(&target, &picture.data) {
+            Ok(()) => extracted += 1,
+            Err(error) => {
+                print_error(&target, FmmdError::from(ArtError::from(error)));
+                errors += 1;
+            }
+        }
+    }
+
+    println!(
+        "{} extracted, {} already in the folder, {} without cover art",
+        extracted, present, missing
+    );
+
+    Ok(Status::from_counts(errors, albums.len()))
+}
+
+fn embed(args: &EmbedArgs) -> Result<Status, FmmdError> {
+    let (albums, mut errors) = collect_albums(&args.change.input);
+    let mut changed = 0;
+    let mut unchanged = 0;
+    let mut missing = 0;
+
+    // An image given with --image is the same for every album
+    let given = match &args.image {
+        Some(image) => match read_image(image) {
+            Ok(read) => Some((image.clone(), read)),
+            Err(error) => {
+                print_error(image, error);
+                return Ok(Status::Failure);
+            }
+        },
+        None => None,
+    };
+
+    for (dir, files) in &albums {
+        let read = match &given {
+            Some(given) => Cow::Borrowed(given),
+            None => {
+                let Some(image) = folder_image(dir) else {
+                    missing += 1;
+                    continue;
+                };
+
+                match read_image(&image) {
+                    Ok(read) => Cow::Owned((image, read)),
+                    Err(error) => {
+                        print_error(&image, error);
+                        errors += files.len();
+                        continue;
+                    }
+                }
+            }
+        };
+        let (image, (mime_type, data)) = read.as_ref();
+
+        for file in files {
+            let result = change(file, args.change.dry_run, |embedded| {
+                let same = embedded
+                    .pictures()
+                    .iter()
+                    .any(|picture| picture.front && picture.data == *data);
+                if same {
+                    return Ok(false);
+                }
+
+                embedded.set_front(mime_type, data.clone())?;
+                println!("{} -> {}", image.display(), file.display());
+                Ok(true)
+            });
+
+            match result {
+                Ok(true) => changed += 1,
+                Ok(false) => unchanged += 1,
+                Err(error) => {
+                    print_error(file, error);
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    println!(
+        "{} embedded, {} unchanged, {} {} without a folder image",
+        changed,
+        unchanged,
+        missing,
+        if missing == 1 { "album" } else { "albums" }
+    );
+
+    Ok(Status::from_counts(errors, changed + unchanged))
+}
+
+fn remove(args: &ChangeArgs) -> Result<Status, FmmdError> {
+    let (albums, mut errors) = collect_albums(&args.input);
+    let mut changed = 0;
+    let mut unchanged = 0;
+
+    for file in albums.values().flatten() {
+        let result = change(file, args.dry_run, |embedded| {
+            let count = embedded.pictures().len();
+            if count == 0 {
+                return Ok(false);
+            }
+
+            embedded.remove_all();
+            println!("{}: {} removed", file.display(), images(count));
+            Ok(true)
+        });
+
+        match result {
+            Ok(true) => changed += 1,
+            Ok(false) => unchanged += 1,
+            Err(error) => {
+                print_error(file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    println!("{} changed, {} without images", changed, unchanged);
+
+    Ok(Status::from_counts(errors, changed + unchanged))
+}
+
+fn resize(args: &ResizeArgs) -> Result<Status, FmmdError> {
+    let (albums, mut errors) = collect_albums(&args.change.input);
+    let max_bytes = args.max_size * 1024;
+    let mut changed = 0;
+    let mut unchanged = 0;
+
+    for file in albums.values().flatten() {
+        let result = change(file, args.change.dry_run, |embedded| {
+            let replaced = embedded.replace(|data| {
+                let Some(resized) = resize::resize(data, args.max_dimension, max_bytes)? else {
+                    return Ok(None);
+                };
+
+                let (width, height) = resize::dimensions(data)?;
+                println!(
+                    "{}: {}x{}, {} KB -> {}x{}, {} KB",
+                    file.display(),
+                    width,
+                    height,
+                    data.len() / 1024,
+                    resized.width,
+                    resized.height,
+                    resized.data.len() / 1024
+                );
+                Ok(Some(resized.data))
+            })?;
+
+            Ok(replaced > 0)
+        });
+
+        match result {
+            Ok(true) => changed += 1,
+            Ok(false) => unchanged += 1,
+            Err(error) => {
+                print_error(file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    println!("{} resized, {} unchanged", changed, unchanged);
+
+    Ok(Status::from_counts(errors, changed + unchanged))
+}
+
+/// Reads the cover art of `file`, applies `change` and writes it back unless `change`
+/// returns `false` or this is a dry run
+fn change(
+    file: &Path,
+    dry_run: bool,
+    change: impl FnOnce(&mut Embedded) -> Result<bool, ArtError>,
+) -> Result<bool, FmmdError> {
+    let mut embedded = Embedded::read(file)?;
+    if !change(&mut embedded)? {
+        return Ok(false);
+    }
+
+    if !dry_run {
+        embedded.write(file)?;
+    }
+    Ok(true)
+}
+
+/// Groups the files given on the command line by directory, returning the number of
+/// paths that couldn't be used along with them
+fn collect_albums(input: &InputArgs) -> (BTreeMap<PathBuf, Vec<PathBuf>>, usize) {
+    let mut albums: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    let mut errors = 0;
+
+    let files = input.collect_with(|file, error| {
+        print_error(file, error);
+        errors += 1;
+    });
+
+    for file in files {
+        let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        albums.entry(dir).or_default().push(file);
+    }
+
+    (albums, errors)
+}
+
+/// The first of [`FOLDER_IMAGES`] in `dir`, whatever the case of its name
+fn folder_image(dir: &Path) -> Option<PathBuf> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+
+    let names: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+
+    FOLDER_IMAGES.iter().find_map(|wanted| {
+        names
+            .iter()
+            .find(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.eq_ignore_ascii_case(wanted))
+            })
+            .cloned()
+    })
+}
+
+/// Reads an image to embed along with its MIME type
+fn read_image(path: &Path) -> Result<(&'static str, Vec<u8>), FmmdError> {
+    let data = fs::read(path).map_err(ArtError::from)?;
+    match extension(&data) {
+        Some("jpg") => Ok(("image/jpeg", data)),
+        Some(_) => Ok(("image/png", data)),
+        None => Err(ArtError::UnsupportedImage.into()),
+    }
+}
+
+/// The file extension for an image, told by its first bytes
+fn extension(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("jpg")
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else {
+        None
+    }
+}
+
+fn images(count: usize) -> String {
+    match count {
+        1 => "1 image".to_string(),
+        count => format!("{} images", count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn images_are_told_by_their_first_bytes() {
+        let jpeg = resize::resize(&fixtures::png(2, 2, 1), 1, usize::MAX)
+            .unwrap()
+            .unwrap()
+            .data;
+
+        assert_eq!(extension(&jpeg), Some("jpg"));
+        assert_eq!(extension(&fixtures::png(1, 1, 1)), Some("png"));
+        assert_eq!(extension(b"GIF89a"), None);
+        assert_eq!(extension(&[]), None);
+    }
+}
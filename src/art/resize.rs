@@ -0,0 +1,124 @@
+//! Scaling down and re-encoding oversized images
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageReader};
+
+use super::ArtError;
+
+/// JPEG qualities tried in turn until the image is small enough
+const QUALITIES: &[u8] = &[90, 80, 70, 60, 50];
+
+/// An image that was scaled down or re-encoded
+pub struct Resized {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The width and height of an image, read from its header
+pub fn dimensions(data: &[u8]) -> Result<(u32, u32), ArtError> {
+    Ok(ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_dimensions()?)
+}
+
+/// Scales the image down to fit `max_dimension` and encodes it as JPEG, lowering the
+/// quality until it is no larger than `max_bytes`. Returns `None` when the image fits
+/// already or when re-encoding it doesn't make it any smaller.
+pub fn resize(
+    data: &[u8],
+    max_dimension: u32,
+    max_bytes: usize,
+) -> Result<Option<Resized>, ArtError> {
+    let (width, height) = dimensions(data)?;
+    let oversized = width > max_dimension || height > max_dimension;
+    if !oversized && data.len() <= max_bytes {
+        return Ok(None);
+    }
+
+    let mut image = image::load_from_memory(data)?;
+    if oversized {
+        image = image.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+    }
+    // JPEG has no alpha channel
+    let image = DynamicImage::ImageRgb8(image.to_rgb8());
+
+    let mut encoded = Vec::new();
+    for &quality in QUALITIES {
+        encoded.clear();
+        JpegEncoder::new_with_quality(&mut encoded, quality).encode_image(&image)?;
+        if encoded.len() <= max_bytes {
+            break;
+        }
+    }
+
+    if !oversized && encoded.len() >= data.len() {
+        return Ok(None);
+    }
+
+    Ok(Some(Resized {
+        data: encoded,
+        width: image.width(),
+        height: image.height(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    /// The size of the image as JPEG of the given quality
+    fn jpeg_size(data: &[u8], quality: u8) -> usize {
+        let image = image::load_from_memory(data).unwrap().to_rgb8();
+        let mut encoded = Vec::new();
+        JpegEncoder::new_with_quality(&mut encoded, quality)
+            .encode_image(&image)
+            .unwrap();
+        encoded.len()
+    }
+
+    #[test]
+    fn oversized_images_are_scaled_to_fit() {
+        let data = fixtures::png(400, 200, 1);
+        let resized = resize(&data, 100, usize::MAX).unwrap().unwrap();
+
+        assert_eq!((resized.width, resized.height), (100, 50));
+        assert_eq!(dimensions(&resized.data).unwrap(), (100, 50));
+        assert!(resized.data.starts_with(&[0xFF, 0xD8, 0xFF]));
+    }
+
+    #[test]
+    fn images_that_fit_are_left_alone() {
+        let data = fixtures::png(100, 50, 1);
+        assert!(resize(&data, 100, data.len()).unwrap().is_none());
+    }
+
+    #[test]
+    fn quality_is_lowered_until_the_image_is_small_enough() {
+        let data = fixtures::png(200, 200, 1);
+        let (best, worst) = (jpeg_size(&data, 90), jpeg_size(&data, 50));
+        assert!(worst < best && best < data.len());
+
+        // The best quality is kept when it is small enough
+        let resized = resize(&data, 1000, best).unwrap().unwrap();
+        assert_eq!(resized.data.len(), best);
+
+        let resized = resize(&data, 1000, best - 1).unwrap().unwrap();
+        assert!(resized.data.len() < best && resized.data.len() >= worst);
+
+        // The worst quality is used when nothing is small enough
+        let resized = resize(&data, 1000, 1).unwrap().unwrap();
+        assert_eq!(resized.data.len(), worst);
+        assert_eq!((resized.width, resized.height), (200, 200));
+    }
+
+    #[test]
+    fn images_are_not_replaced_by_larger_ones() {
+        // No JPEG is smaller than a PNG of a single pixel
+        let data = fixtures::png(1, 1, 1);
+        assert!(resize(&data, 1000, 1).unwrap().is_none());
+    }
+}
@@ -0,0 +1,518 @@
+//! The `check` subcommand, finding albums whose files don't agree with each other.
+//!
+//! Files are grouped into albums by the directory they are in. Fields that should be the
+//! same for every track of an album, including its title, are compared against the value
+//! most of its files have, and track numbers are checked for duplicates and gaps, per disc.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use owo_colors::OwoColorize;
+
+use crate::error::FmmdError;
+use crate::index;
+use crate::metadata::{Field, Metadata};
+use crate::report::{print_error, Status};
+use crate::tag::{self, WriteArgs};
+
+/// A file along with the metadata read from it
+type TaggedFile = (PathBuf, Box<dyn Metadata>);
+
+/// Fields every file of an album should have the same value for
+const ALBUM_FIELDS: &[Field] = &[
+    Field::Album,
+    Field::AlbumArtist,
+    Field::Year,
+    Field::Genre,
+    Field::DiscTotal,
+];
+
+#[derive(Args)]
+pub struct CheckArgs {
+    #[command(flatten)]
+    write: WriteArgs,
+
+    /// Set mismatched fields to the value most files of the album have (not in Ogg files)
+    #[arg(long)]
+    fix: bool,
+
+    /// Read files that haven't changed since `fmmd index` from the index
+    #[arg(long)]
+    index: bool,
+}
+
+/// Something wrong with the files of an album
+enum Issue {
+    /// Files that don't have the value most files of the album have
+    Mismatch {
+        field: Field,
+        majority: String,
+        count: usize,
+        files: Vec<(PathBuf, Option<String>)>,
+    },
+    /// Files split evenly between values, so there is none to fix the others with
+    NoMajority {
+        field: Field,
+        values: Vec<(String, usize)>,
+    },
+    MissingTrack(PathBuf),
+    DuplicateTrack {
+        disc: Option<u32>,
+        track: u32,
+        files: Vec<PathBuf>,
+    },
+    /// Track numbers below the highest track or the track total that no file has
+    Gaps {
+        disc: Option<u32>,
+        missing: Vec<u32>,
+    },
+    TotalTooLow {
+        disc: Option<u32>,
+        total: u32,
+        highest: u32,
+    },
+}
+
+impl Issue {
+    fn fixable(&self) -> bool {
+        matches!(self, Issue::Mismatch { .. })
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Mismatch {
+                field,
+                majority,
+                count,
+                files,
+            } => {
+                let files = files
+                    .iter()
+                    .map(|(file, value)| match value {
+                        Some(value) => format!("{} has \"{}\"", name(file), value),
+                        None => format!("{} has none", name(file)),
+                    })
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "{} is \"{}\" in {} files, but {}",
+                    field,
+                    majority,
+                    count,
+                    files.join(", ")
+                )
+            }
+            Issue::NoMajority { field, values } => {
+                let values = values
+                    .iter()
+                    .map(|(value, count)| match count {
+                        1 => format!("\"{}\" in 1 file", value),
+                        count => format!("\"{}\" in {} files", value, count),
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{} has no majority: {}", field, values.join(", "))
+            }
+            Issue::MissingTrack(file) => write!(f, "{} has no track number", name(file)),
+            Issue::DuplicateTrack { disc, track, files } => {
+                let files = files.iter().map(|file| name(file)).collect::<Vec<_>>();
+                write!(
+                    f,
+                    "{}track {} appears more than once: {}",
+                    on_disc(*disc),
+                    track,
+                    files.join(", ")
+                )
+            }
+            Issue::Gaps { disc, missing } => {
+                let missing = missing.iter().map(u32::to_string).collect::<Vec<_>>();
+                write!(
+                    f,
+                    "{}missing tracks: {}",
+                    on_disc(*disc),
+                    missing.join(", ")
+                )
+            }
+            Issue::TotalTooLow {
+                disc,
+                total,
+                highest,
+            } => write!(
+                f,
+                "{}track total is {}, but there is a track {}",
+                on_disc(*disc),
+                total,
+                highest
+            ),
+        }
+    }
+}
+
+pub fn check(args: &CheckArgs) -> Result<Status, FmmdError> {
+    let indexed = index::open(args.index)?;
+    let mut tagged = Vec::new();
+    let mut errors = 0;
+
+    let files = args.write.input.collect_with(|file, error| {
+        print_error(file, error);
+        errors += 1;
+    });
+
+    for file in files {
+        match index::read(indexed.as_ref(), &file) {
+            Ok(metadata) => tagged.push((file, metadata)),
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    let albums = albums(tagged);
+    let mut inconsistent = 0;
+    let mut unresolved = 0;
+
+    for (dir, files) in &albums {
+        let issues = find_issues(files);
+        if issues.is_empty() {
+            continue;
+        }
+
+        inconsistent += 1;
+        println!("{}", dir.display().bold());
+        for issue in &issues {
+            println!("  {}", issue.to_string().yellow());
+        }
+
+        if !args.fix {
+            unresolved += issues.len();
+            continue;
+        }
+
+        unresolved += issues.iter().filter(|issue| !issue.fixable()).count();
+        errors += fix(&issues, &args.write);
+    }
+
+    let noun = if albums.len() == 1 { "album" } else { "albums" };
+    println!(
+        "{} {} checked, {} with issues",
+        albums.len(),
+        noun,
+        inconsistent
+    );
+
+    Ok(match (albums.is_empty(), errors, unresolved) {
+        (true, 1.., _) => Status::Failure,
+        (_, 0, 0) => Status::Success,
+        _ => Status::Partial,
+    })
+}
+
+/// Groups files into albums by the directory they are in
+fn albums(files: Vec<TaggedFile>) -> BTreeMap<PathBuf, Vec<TaggedFile>> {
+    let mut albums: BTreeMap<PathBuf, Vec<TaggedFile>> = BTreeMap::new();
+    for (file, metadata) in files {
+        let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        albums.entry(dir).or_default().push((file, metadata));
+    }
+    albums
+}
+
+/// Sets the majority values of mismatched fields, returning the number of files that failed
+fn fix(issues: &[Issue], args: &WriteArgs) -> usize {
+    let mut changes: BTreeMap<&Path, Vec<(Field, String)>> = BTreeMap::new();
+
+    for issue in issues {
+        if let Issue::Mismatch {
+            field,
+            majority,
+            files,
+            ..
+        } = issue
+        {
+            for (file, _) in files {
+                let values = changes.entry(file).or_default();
+                values.push((field.clone(), majority.clone()));
+            }
+        }
+    }
+
+    let mut errors = 0;
+    for (file, values) in changes {
+        if let Err(error) = tag::set_fields(file, args, &values, args.dry_run || args.verbose) {
+            print_error(file, error);
+            errors += 1;
+        }
+    }
+
+    errors
+}
+
+fn find_issues(files: &[TaggedFile]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    for field in ALBUM_FIELDS {
+        issues.extend(mismatch(files.iter(), field));
+    }
+
+    let mut discs: BTreeMap<u32, Vec<&TaggedFile>> = BTreeMap::new();
+    for file in files {
+        let disc = number(file.1.as_ref(), &Field::Disc).unwrap_or(1);
+        discs.entry(disc).or_default().push(file);
+    }
+
+    let several = discs.len() > 1;
+    for (disc, files) in discs {
+        let disc = several.then_some(disc);
+        // Setting the total of a file without a track number would make it track 1
+        let numbered = files
+            .iter()
+            .copied()
+            .filter(|(_, metadata)| number(metadata.as_ref(), &Field::Track).is_some());
+        issues.extend(mismatch(numbered, &Field::TrackTotal));
+        issues.extend(track_issues(&files, disc));
+    }
+
+    issues
+}
+
+/// Compares the values of `field` with the value most files have.
+///
+/// Files that don't have the field at all only count when others do. When several values
+/// are the most common, there is nothing to compare with and that is the issue.
+fn mismatch<'a>(files: impl Iterator<Item = &'a TaggedFile>, field: &Field) -> Option<Issue> {
+    let values: Vec<(&PathBuf, Option<String>)> = files
+        .map(|(file, metadata)| (file, metadata.lookup(field)))
+        .collect();
+
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for value in values.iter().filter_map(|(_, value)| value.as_deref()) {
+        match counts.iter_mut().find(|(known, _)| *known == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+
+    let most = counts.iter().map(|&(_, count)| count).max()?;
+    let mut most_common = counts.iter().filter(|&&(_, count)| count == most);
+    let (majority, count) = match (most_common.next(), most_common.next()) {
+        (Some(&(majority, count)), None) => (majority.to_string(), count),
+        _ => {
+            return Some(Issue::NoMajority {
+                field: field.clone(),
+                values: counts
+                    .into_iter()
+                    .filter(|&(_, count)| count == most)
+                    .map(|(value, count)| (value.to_string(), count))
+                    .collect(),
+            })
+        }
+    };
+
+    let files: Vec<(PathBuf, Option<String>)> = values
+        .into_iter()
+        .filter(|(_, value)| value.as_ref() != Some(&majority))
+        .map(|(file, value)| (file.clone(), value))
+        .collect();
+
+    (!files.is_empty()).then(|| Issue::Mismatch {
+        field: field.clone(),
+        majority,
+        count,
+        files,
+    })
+}
+
+/// Finds missing, duplicate and skipped track numbers among the files of a disc
+fn track_issues(files: &[&TaggedFile], disc: Option<u32>) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut tracks: BTreeMap<u32, Vec<PathBuf>> = BTreeMap::new();
+
+    for (file, metadata) in files {
+        match number(metadata.as_ref(), &Field::Track) {
+            Some(track) if track > 0 => tracks.entry(track).or_default().push(file.clone()),
+            _ => issues.push(Issue::MissingTrack(file.clone())),
+        }
+    }
+
+    for (&track, files) in &tracks {
+        if files.len() > 1 {
+            issues.push(Issue::DuplicateTrack {
+                disc,
+                track,
+                files: files.clone(),
+            });
+        }
+    }
+
+    let highest = tracks.keys().next_back().copied().unwrap_or(0);
+    let total = most_common(
+        files
+            .iter()
+            .filter_map(|(_, metadata)| number(metadata.as_ref(), &Field::TrackTotal)),
+    );
+
+    if let Some(total) = total.filter(|&total| total < highest) {
+        issues.push(Issue::TotalTooLow {
+            disc,
+            total,
+            highest,
+        });
+    }
+
+    let last = highest.max(total.unwrap_or(0));
+    let missing: Vec<u32> = (1..=last)
+        .filter(|track| !tracks.contains_key(track))
+        .collect();
+    if !missing.is_empty() && !tracks.is_empty() {
+        issues.push(Issue::Gaps { disc, missing });
+    }
+
+    issues
+}
+
+fn most_common(values: impl Iterator<Item = u32>) -> Option<u32> {
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+
+    counts
+        .into_iter()
+        .max_by_key(|&(value, count)| (count, value))
+        .map(|(value, _)| value)
+}
+
+fn number(metadata: &dyn Metadata, field: &Field) -> Option<u32> {
+    metadata.lookup(field)?.trim().parse().ok()
+}
+
+fn name(file: &Path) -> String {
+    file.file_name()
+        .unwrap_or(file.as_os_str())
+        .to_string_lossy()
+        .to_string()
+}
+
+fn on_disc(disc: Option<u32>) -> String {
+    match disc {
+        Some(disc) => format!("disc {}: ", disc),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use id3::TagLike;
+
+    use super::*;
+
+    /// A file with the given album and track number
+    fn file(name: &str, album: Option<&str>, track: Option<u32>) -> TaggedFile {
+        let mut tag = id3::Tag::new();
+        if let Some(album) = album {
+            tag.set_album(album);
+        }
+        if let Some(track) = track {
+            tag.set_track(track);
+        }
+        (PathBuf::from(name), Box::new(tag))
+    }
+
+    fn album_mismatch(files: &[TaggedFile]) -> Option<Issue> {
+        mismatch(files.iter(), &Field::Album)
+    }
+
+    #[test]
+    fn the_minority_album_title_of_a_directory_is_a_mismatch() {
+        let files = vec![
+            file("one/1", Some("Album"), Some(1)),
+            file("one/2", Some("Album"), Some(2)),
+            file("one/3", Some("Albun"), Some(3)),
+            file("one/4", None, Some(4)),
+            file("two/1", Some("Other"), Some(1)),
+        ];
+
+        let albums = albums(files);
+        assert_eq!(
+            albums.keys().collect::<Vec<_>>(),
+            [Path::new("one"), Path::new("two")]
+        );
+        assert!(find_issues(&albums[Path::new("two")]).is_empty());
+
+        let issues = find_issues(&albums[Path::new("one")]);
+        let [Issue::Mismatch {
+            field,
+            majority,
+            count,
+            files,
+        }] = issues.as_slice()
+        else {
+            panic!("expected a single mismatch");
+        };
+        assert_eq!(*field, Field::Album);
+        assert_eq!(majority, "Album");
+        assert_eq!(*count, 2);
+        assert_eq!(
+            *files,
+            [
+                (PathBuf::from("one/3"), Some("Albun".to_string())),
+                (PathBuf::from("one/4"), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn agreeing_or_untagged_files_are_fine() {
+        let agreeing = [
+            file("1", Some("Album"), None),
+            file("2", Some("Album"), None),
+        ];
+        assert!(album_mismatch(&agreeing).is_none());
+
+        let untagged = [file("1", None, None), file("2", None, None)];
+        assert!(album_mismatch(&untagged).is_none());
+    }
+
+    #[test]
+    fn ties_have_no_majority() {
+        let files = [
+            file("1", Some("A"), None),
+            file("2", Some("B"), None),
+            file("3", Some("A"), None),
+            file("4", Some("B"), None),
+            file("5", Some("C"), None),
+        ];
+
+        let Some(Issue::NoMajority { values, .. }) = album_mismatch(&files) else {
+            panic!("expected no majority");
+        };
+        assert_eq!(values, [("A".to_string(), 2), ("B".to_string(), 2)]);
+    }
+
+    #[test]
+    fn track_numbers_are_checked_for_gaps_and_duplicates() {
+        let files = [
+            file("1", None, Some(1)),
+            file("3", None, Some(3)),
+            file("3b", None, Some(3)),
+            file("x", None, None),
+        ];
+        let files: Vec<&TaggedFile> = files.iter().collect();
+
+        let issues: Vec<String> = track_issues(&files, Some(2))
+            .iter()
+            .map(Issue::to_string)
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                "x has no track number",
+                "disc 2: track 3 appears more than once: 3, 3b",
+                "disc 2: missing tracks: 2",
+            ]
+        );
+    }
+}
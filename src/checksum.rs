@@ -0,0 +1,139 @@
+//! Checksums of the audio of a file that leave out its tags, so that they stay the same
+//! when tags are written and tell copies of the same audio apart from different encodings.
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::FmmdError;
+use crate::metadata::{self, MetadataError};
+
+/// The SHA-256 checksum of the audio of `path`, as hex
+pub fn audio(path: &Path) -> Result<String, FmmdError> {
+    let ranges = metadata::audio_ranges(path)?;
+    let mut reader = BufReader::new(File::open(path).map_err(MetadataError::from)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    for range in ranges {
+        reader
+            .seek(SeekFrom::Start(range.start))
+            .map_err(MetadataError::from)?;
+
+        let mut remaining = range.end - range.start;
+        while remaining > 0 {
+            let length = remaining.min(buffer.len() as u64) as usize;
+            reader
+                .read_exact(&mut buffer[..length])
+                .map_err(MetadataError::from)?;
+            hasher.update(&buffer[..length]);
+            remaining -= length as u64;
+        }
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use id3::TagLike;
+
+    use super::*;
+    use crate::fixtures;
+
+    /// Writes `data` to `name` in `dir`, returning the path along with its checksum
+    fn write(dir: &Path, name: &str, data: &[u8]) -> (PathBuf, String) {
+        let file = dir.join(name);
+        fs::write(&file, data).unwrap();
+        let checksum = audio(&file).unwrap();
+        (file, checksum)
+    }
+
+    #[test]
+    fn retagging_an_mp3_keeps_its_checksum() {
+        let dir = fixtures::directory("checksum-mp3");
+        let (file, checksum) = write(&dir, "song.mp3", &fixtures::mp3(1, 10));
+
+        let mut tag = id3::Tag::new();
+        tag.set_title("Title");
+        tag.write_to_path(&file, id3::Version::Id3v24).unwrap();
+        assert_eq!(audio(&file).unwrap(), checksum);
+
+        tag.set_album("A much longer album title that makes the tag grow");
+        tag.write_to_path(&file, id3::Version::Id3v23).unwrap();
+        assert_eq!(audio(&file).unwrap(), checksum);
+
+        let (_, other) = write(&dir, "other.mp3", &fixtures::mp3(2, 10));
+        assert_ne!(other, checksum);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn id3v1_and_ape_tags_at_the_end_of_an_mp3_are_left_out() {
+        let dir = fixtures::directory("checksum-trailers");
+        let audio = fixtures::mp3(1, 10);
+        let (_, checksum) = write(&dir, "plain.mp3", &audio);
+
+        let with_id3v1 = [audio.clone(), fixtures::id3v1("Title")].concat();
+        assert_eq!(write(&dir, "id3v1.mp3", &with_id3v1).1, checksum);
+
+        let with_ape = [audio.clone(), fixtures::ape("Title", "Title")].concat();
+        assert_eq!(write(&dir, "ape.mp3", &with_ape).1, checksum);
+
+        let with_both = [
+            audio.clone(),
+            fixtures::ape("Title", "Title"),
+            fixtures::id3v1("Title"),
+        ]
+        .concat();
+        assert_eq!(write(&dir, "both.mp3", &with_both).1, checksum);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn retagging_a_flac_keeps_its_checksum() {
+        let dir = fixtures::directory("checksum-flac");
+        let (file, checksum) = write(&dir, "song.flac", &fixtures::flac(b"frames"));
+
+        for title in ["Title", "A much longer title that makes the comments grow"] {
+            let mut tag = metaflac::Tag::read_from_path(&file).unwrap();
+            tag.set_vorbis("TITLE", vec![title]);
+            metadata::write_flac(&mut tag, &file).unwrap();
+            assert_eq!(audio(&file).unwrap(), checksum);
+        }
+
+        let (_, other) = write(&dir, "other.flac", &fixtures::flac(b"others"));
+        assert_ne!(other, checksum);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ogg_files_with_other_comments_have_the_same_checksum() {
+        let dir = fixtures::directory("checksum-ogg");
+        let (_, checksum) = write(&dir, "song.ogg", &fixtures::ogg_vorbis(&[], b"packet"));
+
+        let comments = [("TITLE", "Title"), ("ARTIST", "Artist")];
+        let (_, tagged) = write(
+            &dir,
+            "tagged.ogg",
+            &fixtures::ogg_vorbis(&comments, b"packet"),
+        );
+        assert_eq!(tagged, checksum);
+
+        let (_, other) = write(&dir, "other.ogg", &fixtures::ogg_vorbis(&[], b"others"));
+        assert_ne!(other, checksum);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
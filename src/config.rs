@@ -0,0 +1,292 @@
+//! Settings read from configuration files, so that the same flags don't have to be repeated.
+//!
+//! The user configuration (`~/.config/fmmd/config.toml` on Linux) is read first, followed
+//! by the first `.fmmd.toml` found in the directory of the files being renamed or one of
+//! its parents. A profile selected with `--profile` may be defined in either file and
+//! takes precedence over both. Flags on the command line take precedence over everything.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+use crate::discs::DiscPrefix;
+use crate::metadata::Format;
+use crate::plan::ConflictPolicy;
+use crate::sanitize::{self, Profile};
+
+/// Name of the configuration file of a library
+const LOCAL_CONFIG: &str = ".fmmd.toml";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Could not read \"{}\": {1}", .0.display())]
+    Read(PathBuf, io::Error),
+
+    #[error("Invalid configuration in \"{}\": {1}", .0.display())]
+    Parse(PathBuf, toml::de::Error),
+
+    #[error("The profile \"{0}\" isn't defined in any configuration file")]
+    UnknownProfile(String),
+}
+
+/// The settings of a configuration file, or of a profile in it
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    pub template: Option<String>,
+    pub library_root: Option<PathBuf>,
+    pub sanitize: Option<Profile>,
+    pub on_conflict: Option<ConflictPolicy>,
+    pub remove_empty_dirs: Option<bool>,
+    pub disc_prefix: Option<DiscPrefix>,
+    pub track_width: Option<usize>,
+    pub disc_folders: Option<bool>,
+    #[serde(deserialize_with = "replacements")]
+    pub replace: Vec<(String, String)>,
+    /// Settings that only apply to files of a format, e.g. `[formats.flac]`
+    pub formats: HashMap<Format, FormatSettings>,
+    /// Only read at the top level of a file
+    profiles: HashMap<String, Settings>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FormatSettings {
+    pub template: Option<String>,
+}
+
+impl Settings {
+    /// Overrides the settings with those set in `other`
+    fn merge(&mut self, other: Settings) {
+        self.template = other.template.or(self.template.take());
+        self.library_root = other.library_root.or(self.library_root.take());
+        self.sanitize = other.sanitize.or(self.sanitize);
+        self.on_conflict = other.on_conflict.or(self.on_conflict);
+        self.remove_empty_dirs = other.remove_empty_dirs.or(self.remove_empty_dirs);
+        self.disc_prefix = other.disc_prefix.or(self.disc_prefix);
+        self.track_width = other.track_width.or(self.track_width);
+        self.disc_folders = other.disc_folders.or(self.disc_folders);
+        add_replacements(&mut self.replace, other.replace);
+
+        for (format, settings) in other.formats {
+            let merged = self.formats.entry(format).or_default();
+            merged.template = settings.template.or(merged.template.take());
+        }
+    }
+
+    /// Makes a relative `library-root` relative to the directory of the file it is set in
+    fn resolve_paths(&mut self, dir: &Path) {
+        if let Some(library_root) = &self.library_root {
+            self.library_root = Some(resolve_path(library_root, dir));
+        }
+
+        for profile in self.profiles.values_mut() {
+            profile.resolve_paths(dir);
+        }
+    }
+}
+
+/// Adds replacements, dropping earlier ones for the same text so that the later ones apply
+pub fn add_replacements(replace: &mut Vec<(String, String)>, added: Vec<(String, String)>) {
+    for (from, to) in added {
+        replace.retain(|(existing, _)| *existing != from);
+        replace.push((from, to));
+    }
+}
+
+/// Reads the user configuration and the configuration of the library `path` is in,
+/// merging in the settings of `profile` when one is given
+pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Settings, ConfigError> {
+    let user = dirs::config_dir().map(|dir| dir.join("fmmd").join("config.toml"));
+    let local = path.and_then(find_local);
+
+    let mut settings = Settings::default();
+    let mut profiles = Vec::new();
+
+    for file in user.into_iter().chain(local) {
+        if !file.is_file() {
+            continue;
+        }
+
+        let mut config = read(&file)?;
+        if let Some(selected) = profile.and_then(|name| config.profiles.remove(name)) {
+            profiles.push(selected);
+        }
+        settings.merge(config);
+    }
+
+    match profile {
+        Some(name) if profiles.is_empty() => Err(ConfigError::UnknownProfile(name.to_string())),
+        _ => {
+            for selected in profiles {
+                settings.merge(selected);
+            }
+            Ok(settings)
+        }
+    }
+}
+
+fn read(file: &Path) -> Result<Settings, ConfigError> {
+    let contents =
+        fs::read_to_string(file).map_err(|error| ConfigError::Read(file.to_path_buf(), error))?;
+    let mut settings: Settings =
+        toml::from_str(&contents).map_err(|error| ConfigError::Parse(file.to_path_buf(), error))?;
+
+    settings.resolve_paths(file.parent().unwrap_or(Path::new("")));
+    Ok(settings)
+}
+
+/// Looks for the configuration of a library in the directory of `path` and its parents
+fn find_local(path: &Path) -> Option<PathBuf> {
+    let path = std::path::absolute(path).ok()?;
+    let dir = match path.is_dir() {
+        true => path.as_path(),
+        false => path.parent()?,
+    };
+
+    dir.ancestors()
+        .map(|dir| dir.join(LOCAL_CONFIG))
+        .find(|file| file.is_file())
+}
+
+/// Expands a leading `~` to the home directory and joins relative paths to `dir`
+fn resolve_path(path: &Path, dir: &Path) -> PathBuf {
+    let path = match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    };
+
+    dir.join(path)
+}
+
+/// Reads `replace = [":= -", "?="]` like the values of `--replace`
+fn replacements<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(String, String)>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|replacement| {
+            sanitize::parse_replacement(replacement).map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test
+    fn directory(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fmmd-config-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn pairs(replacements: &[(&str, &str)]) -> Vec<(String, String)> {
+        replacements
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn later_replacements_win() {
+        let mut replace = pairs(&[(":", " -"), ("?", "")]);
+        add_replacements(&mut replace, pairs(&[(":", "_"), ("*", "")]));
+        assert_eq!(replace, pairs(&[("?", ""), (":", "_"), ("*", "")]));
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_configuration() {
+        let dir = Path::new("/music/.config");
+        assert_eq!(resolve_path(Path::new("Library"), dir), dir.join("Library"));
+        assert_eq!(
+            resolve_path(Path::new("/srv/music"), dir),
+            Path::new("/srv/music")
+        );
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(resolve_path(Path::new("~/Music"), dir), home.join("Music"));
+        }
+    }
+
+    #[test]
+    fn files_are_read_with_their_profiles() {
+        let dir = directory("read");
+        let file = dir.join(LOCAL_CONFIG);
+        fs::write(
+            &file,
+            r#"
+            template = "{artist}/{title}"
+            library-root = "Library"
+            sanitize = "windows"
+            replace = [":= -"]
+
+            [formats.ogg-vorbis]
+            template = "{title}"
+
+            [profiles.car]
+            sanitize = "fat32"
+            library-root = "/media/car"
+            replace = [":=_"]
+            "#,
+        )
+        .unwrap();
+
+        let mut settings = read(&file).unwrap();
+        assert_eq!(settings.library_root, Some(dir.join("Library")));
+        assert_eq!(settings.sanitize, Some(Profile::Windows));
+        assert_eq!(
+            settings.formats[&Format::OggVorbis].template.as_deref(),
+            Some("{title}")
+        );
+
+        let car = settings.profiles.remove("car").unwrap();
+        settings.merge(car);
+        assert_eq!(settings.template.as_deref(), Some("{artist}/{title}"));
+        assert_eq!(settings.library_root, Some(PathBuf::from("/media/car")));
+        assert_eq!(settings.sanitize, Some(Profile::Fat32));
+        assert_eq!(settings.replace, pairs(&[(":", "_")]));
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_the_file() {
+        let dir = directory("mistakes");
+        let file = dir.join(LOCAL_CONFIG);
+
+        fs::write(&file, "tempalte = \"{title}\"").unwrap();
+        assert!(matches!(read(&file), Err(ConfigError::Parse(path, _)) if path == file));
+
+        fs::write(&file, "replace = [\"no equals sign\"]").unwrap();
+        assert!(matches!(read(&file), Err(ConfigError::Parse(..))));
+
+        assert!(matches!(
+            read(&dir.join("missing.toml")),
+            Err(ConfigError::Read(..))
+        ));
+    }
+
+    #[test]
+    fn the_nearest_library_configuration_is_found() {
+        let dir = directory("find");
+        let album = dir.join("Artist").join("Album");
+        fs::create_dir_all(&album).unwrap();
+        fs::write(album.join("01.mp3"), "").unwrap();
+        fs::write(dir.join(LOCAL_CONFIG), "").unwrap();
+
+        assert_eq!(
+            find_local(&album.join("01.mp3")),
+            Some(dir.join(LOCAL_CONFIG))
+        );
+        assert_eq!(find_local(&album), Some(dir.join(LOCAL_CONFIG)));
+
+        fs::write(album.join(LOCAL_CONFIG), "").unwrap();
+        assert_eq!(
+            find_local(&album.join("01.mp3")),
+            Some(album.join(LOCAL_CONFIG))
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Keeping the discs of an album apart in new names.
+//!
+//! Track numbers start over on every disc, so the tracks of a multi-disc album get a disc
+//! prefix or folder. Many files only store their disc number, so the number of discs of
+//! an album is inferred from all files of it in the same run.
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::metadata::{self, Field, Format, Metadata, Properties};
+use crate::template::{Lookup, Template};
+
+/// When to put the disc number in front of file names
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiscPrefix {
+    /// For albums of more than one disc, unless the template uses {disc} already
+    #[default]
+    Auto,
+    /// For every file with a disc number, unless the template uses {disc} already
+    Always,
+    Never,
+}
+
+/// Added in front of file names, e.g. `2-01-Title.mp3`
+const PREFIX_TEMPLATE: &str = "[{disc}-]";
+
+/// Added in front of file names with `--disc-folders`, e.g. `Disc 2/01-Title.mp3`
+const FOLDER_TEMPLATE: &str = "[Disc {disc}/]";
+
+/// Metadata with the number of discs filled in when the file doesn't store it
+struct WithDiscTotal {
+    metadata: Box<dyn Metadata>,
+    total: u32,
+}
+
+impl Lookup for WithDiscTotal {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        match field {
+            Field::DiscTotal => Some(self.total.to_string()),
+            _ => self.metadata.lookup(field),
+        }
+    }
+}
+
+impl Metadata for WithDiscTotal {
+    fn format(&self) -> Format {
+        self.metadata.format()
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        self.metadata.entries()
+    }
+
+    fn properties(&self) -> Properties {
+        self.metadata.properties()
+    }
+}
+
+/// Fills in the number of discs of files that don't store it, using the highest disc
+/// number of all files of the same album
+pub fn infer_totals(files: Vec<(PathBuf, Box<dyn Metadata>)>) -> Vec<(PathBuf, Box<dyn Metadata>)> {
+    let mut totals: HashMap<(String, String), u32> = HashMap::new();
+
+    for (_, metadata) in &files {
+        if let (Some(album), Some(disc)) = (
+            metadata::album(metadata.as_ref()),
+            number(metadata.as_ref(), Field::Disc),
+        ) {
+            let total = totals.entry(album).or_default();
+            *total = (*total).max(disc);
+        }
+    }
+
+    files
+        .into_iter()
+        .map(|(file, metadata)| {
+            if metadata.lookup(&Field::DiscTotal).is_some() {
+                return (file, metadata);
+            }
+
+            let inferred =
+                metadata::album(metadata.as_ref()).and_then(|album| totals.get(&album).copied());
+            let metadata: Box<dyn Metadata> = match inferred {
+                Some(total) => Box::new(WithDiscTotal { metadata, total }),
+                None => metadata,
+            };
+
+            (file, metadata)
+        })
+        .collect()
+}
+
+/// Adds the disc prefix or folder to `template` where the file calls for one
+pub fn apply(
+    template: &Template,
+    metadata: &dyn Metadata,
+    prefix: DiscPrefix,
+    folders: bool,
+) -> Template {
+    let multi_disc = number(metadata, Field::DiscTotal).is_some_and(|total| total > 1)
+        || number(metadata, Field::Disc).is_some_and(|disc| disc > 1);
+
+    let folder = folders && multi_disc;
+    let prefix = !template.uses(&Field::Disc)
+        && match prefix {
+            DiscPrefix::Auto => multi_disc && !folder,
+            DiscPrefix::Always => true,
+            DiscPrefix::Never => false,
+        };
+
+    let mut template = template.clone();
+    if prefix {
+        template.insert_before_name(Template::parse(PREFIX_TEMPLATE).unwrap());
+    }
+    if folder {
+        template.insert_before_name(Template::parse(FOLDER_TEMPLATE).unwrap());
+    }
+
+    template
+}
+
+fn number(metadata: &dyn Metadata, field: Field) -> Option<u32> {
+    metadata.lookup(&field)?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use id3::TagLike;
+
+    use super::*;
+
+    /// A file of `album` with the given disc number, e.g. `2` or `1/3`
+    fn file(album: &str, disc: Option<&str>) -> (PathBuf, Box<dyn Metadata>) {
+        let mut tag = id3::Tag::new();
+        tag.set_artist("Artist");
+        tag.set_album(album);
+        tag.set_title("Title");
+        if let Some(disc) = disc {
+            tag.set_text("TPOS", disc);
+        }
+        (PathBuf::from(album), Box::new(tag))
+    }
+
+    fn render(template: &str, disc: Option<&str>, prefix: DiscPrefix, folders: bool) -> String {
+        let (_, metadata) = file("Album", disc);
+        let template = Template::parse(template).unwrap();
+        apply(&template, metadata.as_ref(), prefix, folders)
+            .render(metadata.as_ref())
+            .components
+            .join("/")
+    }
+
+    #[test]
+    fn totals_are_the_highest_disc_of_the_album() {
+        let files = vec![
+            file("Album", Some("1")),
+            file("Album", Some("2")),
+            file("Album", Some("1/3")),
+            file("Album", None),
+            file("Other", Some("1")),
+        ];
+
+        let totals: Vec<Option<String>> = infer_totals(files)
+            .iter()
+            .map(|(_, metadata)| metadata.lookup(&Field::DiscTotal))
+            .collect();
+        let expected = [Some("2"), Some("2"), Some("3"), Some("2"), Some("1")];
+        assert_eq!(totals, expected.map(|total| total.map(String::from)));
+    }
+
+    #[test]
+    fn albums_without_disc_numbers_get_no_total() {
+        let files = infer_totals(vec![file("Album", None)]);
+        assert_eq!(files[0].1.lookup(&Field::DiscTotal), None);
+    }
+
+    #[test]
+    fn only_multi_disc_albums_get_a_prefix_by_default() {
+        assert_eq!(
+            render("{title}", Some("1/2"), DiscPrefix::Auto, false),
+            "1-Title"
+        );
+        assert_eq!(
+            render("{title}", Some("2"), DiscPrefix::Auto, false),
+            "2-Title"
+        );
+        assert_eq!(
+            render("{title}", Some("1"), DiscPrefix::Auto, false),
+            "Title"
+        );
+        assert_eq!(
+            render("{title}", Some("1/1"), DiscPrefix::Auto, false),
+            "Title"
+        );
+    }
+
+    #[test]
+    fn prefixes_can_be_forced_or_left_out() {
+        assert_eq!(
+            render("{title}", Some("1"), DiscPrefix::Always, false),
+            "1-Title"
+        );
+        assert_eq!(render("{title}", None, DiscPrefix::Always, false), "Title");
+        assert_eq!(
+            render("{title}", Some("1/2"), DiscPrefix::Never, false),
+            "Title"
+        );
+    }
+
+    #[test]
+    fn templates_with_the_disc_get_no_prefix() {
+        let rendered = render("{disc}.{title}", Some("2/2"), DiscPrefix::Always, false);
+        assert_eq!(rendered, "2.Title");
+    }
+
+    #[test]
+    fn folders_replace_the_prefix_of_multi_disc_albums() {
+        let template = "{album}/{title}";
+        assert_eq!(
+            render(template, Some("2/2"), DiscPrefix::Auto, true),
+            "Album/Disc 2/Title"
+        );
+        assert_eq!(
+            render(template, Some("1"), DiscPrefix::Auto, true),
+            "Album/Title"
+        );
+        assert_eq!(
+            render(template, Some("2/2"), DiscPrefix::Always, true),
+            "Album/Disc 2/2-Title"
+        );
+    }
+}
@@ -0,0 +1,511 @@
+//! The `dupes` subcommand, finding copies of the same track.
+//!
+//! Files are copies when their artist and title are the same once case, accents and
+//! punctuation are ignored and their durations are known to be close, or, with `--audio`,
+//! when their audio is exactly the same whatever their tags say. The copies of each track are ranked
+//! by quality so that the worse ones can be moved out of the way.
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use owo_colors::OwoColorize;
+
+use crate::checksum;
+use crate::error::FmmdError;
+use crate::files::{create_dirs, move_file};
+use crate::index;
+use crate::input::InputArgs;
+use crate::journal::Journal;
+use crate::metadata::{Field, Format, Metadata};
+use crate::plan::{file_id, normalize, FileId};
+use crate::report::{print_error, Status};
+
+#[derive(Args)]
+pub struct DupesArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Seconds the durations of copies may differ by
+    #[arg(long, value_name = "SECONDS", default_value_t = 3)]
+    tolerance: u64,
+
+    /// Also treat files with the same audio as copies, whatever their tags
+    #[arg(long)]
+    audio: bool,
+
+    /// Move all but the best copy of each track into this directory
+    #[arg(long, value_name = "DIR")]
+    quarantine: Option<PathBuf>,
+
+    /// Show what would be moved without moving anything
+    #[arg(short, long)]
+    dry_run: bool,
+
+    /// Read files that haven't changed since `fmmd index` from the index
+    #[arg(long)]
+    index: bool,
+}
+
+/// A file along with what it is compared by
+struct Candidate {
+    file: PathBuf,
+    metadata: Box<dyn Metadata>,
+    key: Key,
+}
+
+/// What copies of a track are recognized by
+#[derive(Default)]
+struct Key {
+    /// Set when the file can be told apart from others reached through a different path
+    id: Option<FileId>,
+    /// Normalized artist and title
+    identity: Option<(String, String)>,
+    duration_ms: Option<u64>,
+    /// The checksum of the audio, with `--audio`
+    checksum: Option<String>,
+}
+
+impl Key {
+    /// Whether both are the same file reached through different paths
+    fn same_file(&self, other: &Key) -> bool {
+        self.id.is_some() && self.id == other.id
+    }
+
+    /// Whether both have the same artist and title and durations known to be close
+    fn same_track(&self, other: &Key, tolerance_ms: u64) -> bool {
+        let close = match (self.duration_ms, other.duration_ms) {
+            (Some(first), Some(second)) => first.abs_diff(second) <= tolerance_ms,
+            _ => false,
+        };
+
+        close && self.identity.is_some() && self.identity == other.identity
+    }
+}
+
+pub fn dupes(args: &DupesArgs) -> Result<Status, FmmdError> {
+    let indexed = index::open(args.index)?;
+    let mut candidates = Vec::new();
+    let mut errors = 0;
+
+    let files = args.input.collect_with(|file, error| {
+        print_error(file, error);
+        errors += 1;
+    });
+
+    // A file reached through a hard link or symbolic link as well isn't a copy of itself
+    let mut seen: HashSet<FileId> = HashSet::new();
+    for file in files {
+        let id = file_id(&file);
+        if id.as_ref().is_some_and(|id| !seen.insert(id.clone())) {
+            continue;
+        }
+
+        match index::read(indexed.as_ref(), &file) {
+            Ok(metadata) => candidates.push(Candidate {
+                key: Key {
+                    id,
+                    identity: identity(metadata.as_ref()),
+                    duration_ms: metadata.properties().duration_ms,
+                    checksum: None,
+                },
+                file,
+                metadata,
+            }),
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    if args.audio {
+        for candidate in &mut candidates {
+            match checksum::audio(&candidate.file) {
+                Ok(checksum) => candidate.key.checksum = Some(checksum),
+                Err(error) => {
+                    print_error(&candidate.file, error);
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    let keys: Vec<&Key> = candidates.iter().map(|candidate| &candidate.key).collect();
+    let mut copies: Vec<Vec<usize>> = group(&keys, args.tolerance * 1000)
+        .into_iter()
+        .filter(|set| set.len() > 1)
+        .collect();
+    for set in &mut copies {
+        // Stable, so equally good copies keep the order they were found in
+        set.sort_by_key(|&position| {
+            std::cmp::Reverse(quality(candidates[position].metadata.as_ref()))
+        });
+    }
+    copies.sort_by(|a, b| candidates[a[0]].file.cmp(&candidates[b[0]].file));
+
+    let root = common_root(candidates.iter().map(|candidate| candidate.file.as_path()));
+    let mut journal = None;
+    let mut moved = 0;
+
+    for set in &copies {
+        let best = &candidates[set[0]];
+        println!("{}", describe(best).bold());
+
+        for (rank, &position) in set.iter().enumerate() {
+            let candidate = &candidates[position];
+            let line = format!(
+                "{}  {}",
+                candidate.file.display(),
+                summary(candidate.metadata.as_ref())
+            );
+
+            if rank == 0 {
+                println!("  {} {}", "keep".green(), line);
+                continue;
+            }
+            println!("  {} {}", "copy".yellow(), line);
+
+            let Some(quarantine) = &args.quarantine else {
+                continue;
+            };
+
+            let target = quarantine.join(relative(&candidate.file, &root));
+            println!("    -> {}", target.display());
+            if args.dry_run {
+                continue;
+            }
+
+            let journal = match &mut journal {
+                Some(journal) => journal,
+                None => journal.insert(Journal::new()?),
+            };
+
+            match quarantine_file(&candidate.file, &target, journal) {
+                Ok(()) => moved += 1,
+                Err(error) => {
+                    print_error(&candidate.file, error);
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    let duplicates: usize = copies.iter().map(|set| set.len() - 1).sum();
+    let noun = if copies.len() == 1 { "track" } else { "tracks" };
+    match args.quarantine.is_some() && !args.dry_run {
+        true => println!(
+            "{} {} with copies, {} copies, {} moved",
+            copies.len(),
+            noun,
+            duplicates,
+            moved
+        ),
+        false => println!(
+            "{} {} with copies, {} copies",
+            copies.len(),
+            noun,
+            duplicates
+        ),
+    }
+
+    Ok(Status::from_counts(errors, candidates.len()))
+}
+
+/// The artist and title of a file in lower case without accents and punctuation, so that
+/// `Björk - Jóga` and `bjork - joga` are the same track
+fn identity(metadata: &dyn Metadata) -> Option<(String, String)> {
+    let normalize = |value: String| {
+        let value = deunicode::deunicode(&value).to_lowercase();
+        let words: Vec<&str> = value
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect();
+        Some(words.join(" ")).filter(|value| !value.is_empty())
+    };
+
+    let artist = metadata
+        .lookup(&Field::Artist)
+        .or_else(|| metadata.lookup(&Field::AlbumArtist))?;
+    let title = metadata.lookup(&Field::Title)?;
+
+    Some((normalize(artist)?, normalize(title)?))
+}
+
+/// Orders copies from worst to best: lossy before lossless, then by bit rate and by the
+/// number of fields that are set
+fn quality(metadata: &dyn Metadata) -> (bool, u32, usize) {
+    let lossless = matches!(metadata.format(), Format::Flac | Format::Wav | Format::Aiff);
+    let bitrate = metadata.properties().bitrate.unwrap_or(0);
+    let fields = Field::STANDARD
+        .iter()
+        .filter(|field| metadata.lookup(field).is_some())
+        .count();
+
+    (lossless, bitrate, fields)
+}
+
+/// Names a track as `"Artist - Title"`
+fn describe(candidate: &Candidate) -> String {
+    let metadata = candidate.metadata.as_ref();
+    match (
+        metadata.lookup(&Field::Artist),
+        metadata.lookup(&Field::Title),
+    ) {
+        (Some(artist), Some(title)) => format!("\"{} - {}\"", artist, title),
+        _ => format!("\"{}\"", candidate.file.display()),
+    }
+}
+
+/// Lists what copies are ranked by, e.g. `MP3, 320 kbit/s, 9 fields`
+fn summary(metadata: &dyn Metadata) -> String {
+    let (_, bitrate, fields) = quality(metadata);
+    let fields = match fields {
+        1 => "1 field".to_string(),
+        count => format!("{} fields", count),
+    };
+
+    match bitrate {
+        0 => format!("{}, {}", metadata.format(), fields),
+        bitrate => format!(
+            "{}, {} kbit/s, {}",
+            metadata.format(),
+            bitrate / 1000,
+            fields
+        ),
+    }
+}
+
+/// Moves a copy into quarantine, recording it so that `fmmd undo` can bring it back
+fn quarantine_file(file: &Path, target: &Path, journal: &mut Journal) -> Result<(), FmmdError> {
+    if target.exists() {
+        return Err(FmmdError::TargetExists);
+    }
+
+    let created = match target.parent() {
+        Some(parent) => create_dirs(parent)?,
+        None => Vec::new(),
+    };
+    move_file(file, target)?;
+    journal.record(file, target, None, created)?;
+
+    Ok(())
+}
+
+/// The directory all files are in, so that copies keep their place in the library when
+/// they are moved into quarantine
+fn common_root<'a>(files: impl Iterator<Item = &'a Path>) -> PathBuf {
+    let mut root: Option<PathBuf> = None;
+
+    for file in files {
+        let dir = normalize(file.parent().unwrap_or(Path::new("")));
+        let root = root.get_or_insert_with(|| dir.clone());
+        while !dir.starts_with(&*root) {
+            if !root.pop() {
+                break;
+            }
+        }
+    }
+
+    root.unwrap_or_default()
+}
+
+/// `file` below `root`, or its name when it isn't
+fn relative(file: &Path, root: &Path) -> PathBuf {
+    let file = normalize(file);
+    match file.strip_prefix(root) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => file.file_name().map(PathBuf::from).unwrap_or_default(),
+    }
+}
+
+/// Groups the positions of `keys` that are copies of the same track.
+///
+/// Files are copies when they have the same artist, title and a close duration, or the
+/// same audio, and copies of copies end up in the same group. Files are compared by their
+/// tags with the first file of each group of the same track rather than with all of its
+/// members, so that a chain of files whose durations are each a little apart doesn't end up
+/// as one group. Files whose duration isn't known only end up with others through their
+/// audio.
+fn group(keys: &[&Key], tolerance_ms: u64) -> Vec<Vec<usize>> {
+    // The position of another file in the same group, or the position itself for the first
+    let mut parents: Vec<usize> = (0..keys.len()).collect();
+
+    let mut firsts: HashMap<&(String, String), Vec<usize>> = HashMap::new();
+    for (position, key) in keys.iter().enumerate() {
+        let Some(identity) = &key.identity else {
+            continue;
+        };
+
+        let candidates = firsts.entry(identity).or_default();
+        let found = candidates.iter().copied().find(|&first| {
+            keys[first].same_track(key, tolerance_ms) && !keys[first].same_file(key)
+        });
+        match found {
+            Some(first) => join(&mut parents, first, position),
+            None => candidates.push(position),
+        }
+    }
+
+    let mut by_checksum: HashMap<&str, usize> = HashMap::new();
+    for (position, key) in keys.iter().enumerate() {
+        let Some(checksum) = &key.checksum else {
+            continue;
+        };
+
+        match by_checksum.get(checksum.as_str()) {
+            Some(&first) if !keys[first].same_file(key) => join(&mut parents, first, position),
+            Some(_) => {}
+            None => {
+                by_checksum.insert(checksum, position);
+            }
+        }
+    }
+
+    // Keeps the order files were found in within and between groups
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for position in 0..keys.len() {
+        let first = first_of(&mut parents, position);
+        groups.entry(first).or_default().push(position);
+    }
+    let mut groups: Vec<Vec<usize>> = groups.into_values().collect();
+    groups.sort_by_key(|group| group[0]);
+    groups
+}
+
+/// The first position of the group `position` is in
+fn first_of(parents: &mut [usize], position: usize) -> usize {
+    let mut first = position;
+    while parents[first] != first {
+        first = parents[first];
+    }
+
+    // Points the positions on the way straight at the first so that later lookups are short
+    let mut position = position;
+    while parents[position] != first {
+        position = std::mem::replace(&mut parents[position], first);
+    }
+
+    first
+}
+
+/// Puts the groups of both positions together
+fn join(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (first_of(parents, a), first_of(parents, b));
+    parents[a.max(b)] = a.min(b);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(artist: &str, title: &str, duration_ms: Option<u64>) -> Key {
+        Key {
+            identity: Some((artist.to_string(), title.to_string())),
+            duration_ms,
+            ..Key::default()
+        }
+    }
+
+    fn groups(keys: &[Key], tolerance_ms: u64) -> Vec<Vec<usize>> {
+        let keys: Vec<&Key> = keys.iter().collect();
+        group(&keys, tolerance_ms)
+    }
+
+    #[test]
+    fn copies_need_the_same_track_and_a_close_duration() {
+        let keys = [
+            key("artist", "title", Some(200_000)),
+            key("artist", "other", Some(200_000)),
+            key("artist", "title", Some(202_000)),
+            key("artist", "title", Some(260_000)),
+            key("artist", "title", None),
+        ];
+
+        assert_eq!(groups(&keys, 3000), [vec![0, 2], vec![1], vec![3], vec![4]]);
+    }
+
+    #[test]
+    fn durations_are_compared_with_the_first_copy() {
+        // Each is within the tolerance of the one before, but not of the first
+        let keys = [
+            key("artist", "title", Some(200_000)),
+            key("artist", "title", Some(202_500)),
+            key("artist", "title", Some(205_000)),
+        ];
+
+        assert_eq!(groups(&keys, 3000), [vec![0, 1], vec![2]]);
+    }
+
+    #[test]
+    fn the_same_audio_is_a_copy_whatever_the_tags() {
+        let mut keys = [
+            key("artist", "title", Some(200_000)),
+            Key::default(),
+            key("artist", "title", Some(200_000)),
+        ];
+        keys[0].checksum = Some("sum".to_string());
+        keys[1].checksum = Some("sum".to_string());
+
+        // Files joined by their audio take others of the same track along
+        assert_eq!(groups(&keys, 3000), [vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn different_encodings_of_a_track_are_copies_with_checksums() {
+        let mut keys = [
+            key("artist", "title", Some(200_000)),
+            key("artist", "title", Some(200_500)),
+            Key::default(),
+            key("artist", "other", Some(200_000)),
+        ];
+        for (key, checksum) in keys.iter_mut().zip(["128k", "320k", "128k", "other"]) {
+            key.checksum = Some(checksum.to_string());
+        }
+
+        assert_eq!(groups(&keys, 3000), [vec![0, 1, 2], vec![3]]);
+    }
+
+    #[test]
+    fn a_file_is_not_a_copy_of_itself() {
+        let mut keys = [
+            key("artist", "title", Some(200_000)),
+            key("artist", "title", Some(200_000)),
+        ];
+        keys[0].id = file_id(Path::new("."));
+        keys[1].id = file_id(Path::new("."));
+
+        assert_eq!(groups(&keys, 3000), [vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn copies_are_moved_below_the_common_root() {
+        let files = [
+            Path::new("/music/a/one.mp3"),
+            Path::new("/music/b/c/two.mp3"),
+        ];
+        let root = common_root(files.into_iter());
+
+        assert_eq!(root, Path::new("/music"));
+        assert_eq!(relative(files[1], &root), Path::new("b/c/two.mp3"));
+        assert_eq!(
+            relative(Path::new("/elsewhere/three.mp3"), &root),
+            Path::new("three.mp3")
+        );
+    }
+
+    #[test]
+    fn identities_ignore_case_accents_and_punctuation() {
+        use id3::TagLike;
+
+        let mut tag = id3::Tag::new();
+        tag.set_album_artist("Björk");
+        tag.set_title("Jóga (Live!)");
+        assert_eq!(
+            identity(&tag),
+            Some(("bjork".to_string(), "joga live".to_string()))
+        );
+
+        tag.set_title("?!");
+        assert_eq!(identity(&tag), None);
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::art::ArtError;
+use crate::config::ConfigError;
+use crate::fingerprint::FingerprintError;
+use crate::index::IndexError;
+use crate::journal::JournalError;
+use crate::lookup::LookupError;
+use crate::metadata::MetadataError;
+use crate::show::ShowError;
+use crate::tag::TagError;
+use crate::template::TemplateError;
+
+#[derive(Error, Debug)]
+pub enum FmmdError {
+    #[error("File does not exist")]
+    NotFound,
+
+    #[error("Is a directory, use --recursive to include the files in it")]
+    IsDirectory,
+
+    #[error("Could not search the directory: {0}")]
+    Walk(#[from] walkdir::Error),
+
+    #[error("Could not parse the file")]
+    FileParse(#[from] MetadataError),
+
+    #[error("Could not rename the file")]
+    FileRename(#[from] std::io::Error),
+
+    #[error("Could not find enough information in the file to rename it")]
+    NotEnoughMetadata,
+
+    #[error("The new name is already taken")]
+    TargetExists,
+
+    #[error("Could not finish renaming the file, it was left at \"{}\"", .0.display())]
+    LeftAtTemporaryName(PathBuf),
+
+    #[error("Found {0} conflicting new names, nothing was renamed")]
+    Conflicts(usize),
+
+    #[error("The file format is not supported")]
+    UnsupportedFormat,
+
+    #[error("Could not read the answer: {0}")]
+    Prompt(#[from] rustyline::error::ReadlineError),
+
+    #[error("Could not use the terminal: {0}")]
+    Terminal(std::io::Error),
+
+    #[error(transparent)]
+    Template(#[from] TemplateError),
+
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error(transparent)]
+    Journal(#[from] JournalError),
+
+    #[error(transparent)]
+    Index(#[from] IndexError),
+
+    #[error(transparent)]
+    Lookup(#[from] LookupError),
+
+    #[error(transparent)]
+    Fingerprint(#[from] FingerprintError),
+
+    #[error(transparent)]
+    Tag(#[from] TagError),
+
+    #[error(transparent)]
+    Show(#[from] ShowError),
+
+    #[error(transparent)]
+    Art(#[from] ArtError),
+}
+
+impl FmmdError {
+    /// A stable name for the kind of error, for machine-readable output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FmmdError::NotFound => "not_found",
+            FmmdError::IsDirectory => "is_directory",
+            FmmdError::Walk(_) => "walk",
+            FmmdError::FileParse(_) => "file_parse",
+            FmmdError::FileRename(_) => "file_rename",
+            FmmdError::NotEnoughMetadata => "not_enough_metadata",
+            FmmdError::TargetExists => "target_exists",
+            FmmdError::LeftAtTemporaryName(_) => "left_at_temporary_name",
+            FmmdError::Conflicts(_) => "conflicts",
+            FmmdError::UnsupportedFormat => "unsupported_format",
+            FmmdError::Prompt(_) => "prompt",
+            FmmdError::Terminal(_) => "terminal",
+            FmmdError::Template(_) => "template",
+            FmmdError::Config(_) => "config",
+            FmmdError::Journal(_) => "journal",
+            FmmdError::Index(_) => "index",
+            FmmdError::Lookup(_) => "lookup",
+            FmmdError::Fingerprint(_) => "fingerprint",
+            FmmdError::Tag(_) => "tag",
+            FmmdError::Show(_) => "show",
+            FmmdError::Art(_) => "art",
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Renames `from` to `to`, falling back to copying when they are on different file systems
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(error) if error.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Creates `dir` along with its missing parents, returning the directories that were
+/// created, outermost first
+pub fn create_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut created: Vec<PathBuf> = dir
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    created.reverse();
+
+    fs::create_dir_all(dir)?;
+    Ok(created)
+}
+
+/// Removes `dir` and its parents for as long as they are empty, never touching `stop`
+/// or anything above it
+pub fn remove_empty_dirs(dir: &Path, stop: Option<&Path>) {
+    let stop = stop.and_then(|stop| stop.canonicalize().ok());
+
+    for dir in dir.ancestors() {
+        if let Some(stop) = &stop {
+            if stop.starts_with(dir) {
+                break;
+            }
+        }
+
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
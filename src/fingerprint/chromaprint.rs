@@ -0,0 +1,247 @@
+//! Chromaprint fingerprints of decoded audio, compressed and encoded the way `fpcalc` and
+//! AcoustID store them, e.g. `AQADtEmU...`
+use std::fs::File;
+use std::path::Path;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rusty_chromaprint::{match_fingerprints, Configuration, FingerprintCompressor, Fingerprinter};
+use symphonia::core::codecs::audio::AudioDecoderOptions;
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::probe::Hint;
+use symphonia::core::formats::{FormatOptions, TrackType};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+
+use super::FingerprintError;
+
+/// Like `fpcalc`, only the start of a track is fingerprinted
+const MAX_SECONDS: u64 = 120;
+
+/// Bits per value in the two parts of a compressed fingerprint
+const NORMAL_BITS: usize = 3;
+const EXCEPTIONAL_BITS: usize = 5;
+
+/// Decodes the audio of `path` and calculates its fingerprint
+pub fn calculate(path: &Path) -> Result<Vec<u32>, FingerprintError> {
+    let file = File::open(path).map_err(DecodeError::IoError)?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut format = symphonia::default::get_probe().probe(
+        &Hint::new(),
+        source,
+        FormatOptions::default(),
+        MetadataOptions::default(),
+    )?;
+
+    let track = format
+        .default_track(TrackType::Audio)
+        .ok_or(FingerprintError::NoAudio)?;
+    let params = track
+        .codec_params
+        .as_ref()
+        .and_then(|params| params.audio())
+        .ok_or(FingerprintError::NoAudio)?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make_audio_decoder(params, &AudioDecoderOptions::default())?;
+
+    let config = Configuration::default();
+    let mut fingerprinter = Fingerprinter::new(&config);
+    let mut samples: Vec<i16> = Vec::new();
+    let mut started = false;
+    let mut remaining = 0;
+
+    while let Some(packet) = format.next_packet()? {
+        if packet.track_id != track_id {
+            continue;
+        }
+
+        let buffer = match decoder.decode(&packet) {
+            Ok(buffer) => buffer,
+            // Damaged frames are skipped like players do
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(error) => return Err(error.into()),
+        };
+
+        let channels = buffer.spec().channels().count();
+        if !started {
+            let rate = buffer.spec().rate();
+            fingerprinter
+                .start(rate, channels as u32)
+                .map_err(|error| FingerprintError::Audio(error.to_string().trim().to_string()))?;
+            remaining = (MAX_SECONDS * rate as u64) as usize * channels;
+            started = true;
+        }
+
+        buffer.copy_to_vec_interleaved(&mut samples);
+        let take = samples.len().min(remaining);
+        fingerprinter.consume(&samples[..take]);
+        remaining -= take;
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    fingerprinter.finish();
+    let fingerprint = fingerprinter.fingerprint().to_vec();
+    if fingerprint.is_empty() {
+        return Err(FingerprintError::TooShort);
+    }
+
+    Ok(fingerprint)
+}
+
+/// Compresses and encodes a fingerprint as text
+pub fn encode(fingerprint: &[u32]) -> String {
+    let config = Configuration::default();
+    URL_SAFE_NO_PAD.encode(FingerprintCompressor::from(&config).compress(fingerprint))
+}
+
+/// Reverses [`encode`], returning `None` for text that isn't a fingerprint of the
+/// algorithm [`calculate`] uses
+pub fn decode(text: &str) -> Option<Vec<u32>> {
+    let bytes = URL_SAFE_NO_PAD.decode(text.trim()).ok()?;
+    let (header, body) = bytes.split_at_checked(4)?;
+    if header[0] != Configuration::default().id() {
+        return None;
+    }
+    let count = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+
+    // Each sub-fingerprint is a list of the distances between its set bits, ended by a
+    // zero. Distances of 7 and more continue in the exceptional part.
+    let mut normal = Vec::new();
+    let mut ends = 0;
+    let mut position = 0;
+    while ends < count {
+        let value = unpack(body, NORMAL_BITS, position)?;
+        ends += (value == 0) as usize;
+        normal.push(value);
+        position += 1;
+    }
+
+    let exceptional_start = (normal.len() * NORMAL_BITS).div_ceil(8);
+    let mut exceptional =
+        (0..).map(|position| unpack(&body[exceptional_start..], EXCEPTIONAL_BITS, position));
+
+    let mut fingerprint = Vec::with_capacity(count);
+    let mut previous = 0;
+    let mut current = 0u32;
+    let mut bit = 0;
+    for value in normal {
+        if value == 0 {
+            // Sub-fingerprints are stored as the difference to the one before
+            previous ^= current;
+            fingerprint.push(previous);
+            current = 0;
+            bit = 0;
+            continue;
+        }
+
+        let mut distance = value as u32;
+        if value == (1 << NORMAL_BITS) - 1 {
+            distance += exceptional.next()?? as u32;
+        }
+        bit += distance;
+        current |= 1u32.checked_shl(bit - 1)?;
+    }
+
+    Some(fingerprint)
+}
+
+/// Reads the `position`th value of `bits` bits from a little-endian bit stream
+fn unpack(bytes: &[u8], bits: usize, position: usize) -> Option<u8> {
+    let start = position * bits;
+    let (index, shift) = (start / 8, start % 8);
+
+    let mut value = (*bytes.get(index)? as u16) >> shift;
+    if shift + bits > 8 {
+        value |= (*bytes.get(index + 1)? as u16) << (8 - shift);
+    }
+
+    Some((value & ((1 << bits) - 1)) as u8)
+}
+
+/// How much of two fingerprints matches, in percent of the longer one
+pub fn similarity(first: &[u32], second: &[u32]) -> u32 {
+    let config = Configuration::default();
+    let Ok(segments) = match_fingerprints(first, second, &config) else {
+        return 0;
+    };
+
+    let matched: usize = segments.iter().map(|segment| segment.items_count).sum();
+    let longer = first.len().max(second.len()).max(1);
+
+    (matched * 100 / longer).min(100) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fingerprints along with their compressed form after the header, from the tests of
+    /// Chromaprint's compressor
+    const COMPRESSED: &[(&[u32], &[u8])] = &[
+        (&[1], &[0x01]),
+        (&[7], &[0x49, 0x00]),
+        (&[1 << 6], &[0x07, 0x00]),
+        (&[1 << 8], &[0x07, 0x02]),
+        (&[1, 0], &[0x41, 0x00]),
+        (&[1, 1], &[0x01, 0x00]),
+    ];
+
+    /// A made up fingerprint, each value following from the one before
+    fn fingerprint(seed: u32, length: usize) -> Vec<u32> {
+        let mut value = seed;
+        (0..length)
+            .map(|_| {
+                value = value.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                value
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fingerprints_are_compressed_like_chromaprint_does() {
+        for (fingerprint, body) in COMPRESSED {
+            let mut expected = vec![Configuration::default().id(), 0, 0, fingerprint.len() as u8];
+            expected.extend(*body);
+
+            let encoded = encode(fingerprint);
+            assert_eq!(URL_SAFE_NO_PAD.decode(&encoded).unwrap(), expected);
+            assert_eq!(decode(&encoded).as_deref(), Some(*fingerprint));
+        }
+    }
+
+    #[test]
+    fn fingerprints_survive_encoding() {
+        let fingerprint = fingerprint(1, 1000);
+        assert_eq!(decode(&encode(&fingerprint)), Some(fingerprint));
+        assert_eq!(decode(&encode(&[])), Some(Vec::new()));
+    }
+
+    #[test]
+    fn text_that_isnt_a_fingerprint_is_not_decoded() {
+        let encoded = encode(&fingerprint(1, 100));
+        let mut bytes = URL_SAFE_NO_PAD.decode(&encoded).unwrap();
+
+        assert_eq!(decode("not a fingerprint!"), None);
+        assert_eq!(decode(&encoded[..encoded.len() / 2]), None);
+
+        // Fingerprints of other algorithms can't be compared
+        bytes[0] = Configuration::default().id().wrapping_add(1);
+        assert_eq!(decode(&URL_SAFE_NO_PAD.encode(&bytes)), None);
+    }
+
+    #[test]
+    fn fingerprints_are_compared_by_how_much_of_them_matches() {
+        let first = fingerprint(1, 1000);
+        let second = fingerprint(2, 1000);
+
+        assert_eq!(similarity(&first, &first), 100);
+        assert_eq!(similarity(&first, &second), 0);
+        assert_eq!(similarity(&first, &first[..500]), 50);
+        // A track that starts later still matches where it overlaps
+        assert_eq!(similarity(&first[200..], &first), 80);
+        assert_eq!(similarity(&[], &first), 0);
+    }
+}
@@ -0,0 +1,124 @@
+//! The database of known tracks files are identified by, in the data directory
+//! (`~/.local/share/fmmd/fingerprints.sqlite` on Linux)
+use std::fs;
+
+use rusqlite::{params, Connection};
+
+use super::chromaprint;
+use super::FingerprintError;
+use crate::metadata::Field;
+
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+
+    CREATE TABLE IF NOT EXISTS tracks (
+        path TEXT PRIMARY KEY,
+        fingerprint TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS fields (
+        path TEXT NOT NULL REFERENCES tracks (path) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS fields_path ON fields (path);
+";
+
+/// A track of the database, with the fields it was added with
+pub struct Known {
+    pub fields: Vec<(Field, String)>,
+}
+
+impl Known {
+    pub fn get(&self, field: &Field) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(known, _)| known == field)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Database {
+    connection: Connection,
+}
+
+impl Database {
+    pub fn open() -> Result<Database, FingerprintError> {
+        let dir = dirs::data_dir()
+            .ok_or(FingerprintError::NoDataDir)?
+            .join("fmmd");
+        fs::create_dir_all(&dir)?;
+
+        let connection = Connection::open(dir.join("fingerprints.sqlite"))?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Database { connection })
+    }
+
+    /// Adds the track at `path`, replacing what was stored for it before
+    pub fn add(
+        &mut self,
+        path: &str,
+        fingerprint: &str,
+        fields: &[(Field, String)],
+    ) -> Result<(), FingerprintError> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM tracks WHERE path = ?1", [path])?;
+        transaction.execute(
+            "INSERT INTO tracks (path, fingerprint) VALUES (?1, ?2)",
+            [path, fingerprint],
+        )?;
+
+        {
+            let mut insert = transaction
+                .prepare_cached("INSERT INTO fields (path, name, value) VALUES (?1, ?2, ?3)")?;
+            for (field, value) in fields {
+                insert.execute(params![path, field.to_string(), value])?;
+            }
+        }
+
+        Ok(transaction.commit()?)
+    }
+
+    /// The known track that matches `fingerprint` best, along with its similarity in percent
+    pub fn best_match(
+        &self,
+        fingerprint: &[u32],
+    ) -> Result<Option<(u32, Known)>, FingerprintError> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT path, fingerprint FROM tracks")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?;
+
+        let mut best: Option<(u32, String)> = None;
+        for row in rows {
+            let (path, stored): (String, String) = row?;
+            // Fingerprints that can't be decoded are left out rather than failing every search
+            let Some(stored) = chromaprint::decode(&stored) else {
+                continue;
+            };
+
+            let score = chromaprint::similarity(fingerprint, &stored);
+            if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                best = Some((score, path));
+            }
+        }
+
+        let Some((score, path)) = best else {
+            return Ok(None);
+        };
+
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT name, value FROM fields WHERE path = ?1")?;
+        let fields = statement
+            .query_map([&path], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>, _>>()?
+            .into_iter()
+            .filter_map(|(name, value)| Some((Field::parse(&name)?, value)))
+            .collect();
+
+        Ok(Some((score, Known { fields })))
+    }
+}
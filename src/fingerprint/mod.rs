@@ -0,0 +1,289 @@
+//! The `fingerprint` subcommand, identifying files by how they sound rather than by their
+//! tags.
+//!
+//! The audio of a file is decoded and turned into a Chromaprint fingerprint, which is
+//! stored in the file as `ACOUSTID_FINGERPRINT` so that it doesn't have to be calculated
+//! again. Tagged files can be added to a local database of known tracks, and files without
+//! tags get the tags of the known track whose fingerprint matches theirs.
+use std::io;
+use std::path::Path;
+
+use clap::{Args, Subcommand};
+use owo_colors::OwoColorize;
+use thiserror::Error;
+
+use crate::error::FmmdError;
+use crate::input::InputArgs;
+use crate::metadata::{self, Field, Metadata};
+use crate::report::{print_error, Status};
+use crate::tag::{self, WriteArgs};
+
+mod chromaprint;
+mod database;
+
+use database::{Database, Known};
+
+#[derive(Error, Debug)]
+pub enum FingerprintError {
+    #[error("Could not decode the audio: {0}")]
+    Decode(#[from] symphonia::core::errors::Error),
+
+    #[error("The file has no audio that can be decoded")]
+    NoAudio,
+
+    #[error("Could not fingerprint the audio: {0}")]
+    Audio(String),
+
+    #[error("The audio is too short to fingerprint")]
+    TooShort,
+
+    #[error("The file has no title to identify other files by")]
+    Untagged,
+
+    #[error("Could not find a data directory for the fingerprint database")]
+    NoDataDir,
+
+    #[error("Could not access the fingerprint database: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Could not use the fingerprint database: {0}")]
+    Database(#[from] rusqlite::Error),
+}
+
+#[derive(Args)]
+pub struct FingerprintArgs {
+    #[command(subcommand)]
+    command: FingerprintCommand,
+}
+
+#[derive(Subcommand)]
+enum FingerprintCommand {
+    /// Calculate the fingerprints of files and store them in the files
+    Compute(ComputeArgs),
+
+    /// Add tagged files to the database of known tracks
+    Add(InputArgs),
+
+    /// Give files the tags of the known tracks they sound like
+    Identify(IdentifyArgs),
+}
+
+#[derive(Args)]
+struct ComputeArgs {
+    #[command(flatten)]
+    write: WriteArgs,
+
+    /// Calculate the fingerprint again for files that already store one
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Args)]
+struct IdentifyArgs {
+    #[command(flatten)]
+    write: WriteArgs,
+
+    /// How much of a file has to match a known track, in percent
+    #[arg(long, value_name = "PERCENT", default_value_t = 50)]
+    min_score: u32,
+
+    /// Write the tags without asking first
+    #[arg(short, long)]
+    yes: bool,
+}
+
+pub fn fingerprint(args: &FingerprintArgs) -> Result<Status, FmmdError> {
+    match &args.command {
+        FingerprintCommand::Compute(args) => compute(args),
+        FingerprintCommand::Add(input) => add(input),
+        FingerprintCommand::Identify(args) => identify(args),
+    }
+}
+
+/// The field fingerprints are stored in: a `TXXX` frame in ID3 tags and a comment of the
+/// same name in other formats, as AcoustID taggers write it
+fn field() -> Field {
+    Field::Raw {
+        key: "TXXX".to_string(),
+        description: Some("ACOUSTID_FINGERPRINT".to_string()),
+    }
+}
+
+/// The fingerprint stored in a file, if it has one that can be used
+fn stored(metadata: &dyn Metadata) -> Option<Vec<u32>> {
+    chromaprint::decode(&metadata.lookup(&field())?)
+}
+
+/// The fingerprint stored in a file, or else the one calculated from its audio
+fn fingerprint_of(file: &Path, metadata: &dyn Metadata) -> Result<Vec<u32>, FmmdError> {
+    match stored(metadata) {
+        Some(fingerprint) => Ok(fingerprint),
+        None => Ok(chromaprint::calculate(file)?),
+    }
+}
+
+fn compute(args: &ComputeArgs) -> Result<Status, FmmdError> {
+    let mut errors = 0;
+    let mut computed = 0;
+    let mut unchanged = 0;
+
+    let files = args.write.input.collect_with(|file, error| {
+        print_error(file, error);
+        errors += 1;
+    });
+
+    for file in files {
+        let result = metadata::read(&file).and_then(|metadata| {
+            tag::ensure_writable(metadata.format())?;
+            if !args.force && stored(metadata.as_ref()).is_some() {
+                return Ok(false);
+            }
+
+            let fingerprint = chromaprint::calculate(&file)?;
+            let values = [(field(), chromaprint::encode(&fingerprint))];
+            let print = args.write.dry_run || args.write.verbose;
+            tag::set_fields(&file, &args.write, &values, print)?;
+            Ok(true)
+        });
+
+        match result {
+            Ok(true) => computed += 1,
+            Ok(false) => unchanged += 1,
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    println!("{} fingerprinted, {} unchanged", computed, unchanged);
+
+    Ok(Status::from_counts(errors, computed + unchanged))
+}
+
+fn add(input: &InputArgs) -> Result<Status, FmmdError> {
+    let mut database = Database::open()?;
+    let mut errors = 0;
+    let mut added = 0;
+
+    let files = input.collect_with(|file, error| {
+        print_error(file, error);
+        errors += 1;
+    });
+
+    for file in files {
+        let result = metadata::read(&file).and_then(|metadata| {
+            if metadata.lookup(&Field::Title).is_none() {
+                return Err(FingerprintError::Untagged.into());
+            }
+
+            let fingerprint = fingerprint_of(&file, metadata.as_ref())?;
+            let fields: Vec<(Field, String)> = Field::STANDARD
+                .iter()
+                .filter_map(|field| Some((field.clone(), metadata.lookup(field)?)))
+                .collect();
+
+            // Known tracks are stored under their absolute path, so adding them again
+            // replaces them
+            let path = std::path::absolute(&file).unwrap_or_else(|_| file.clone());
+            database.add(
+                &path.to_string_lossy(),
+                &chromaprint::encode(&fingerprint),
+                &fields,
+            )?;
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => added += 1,
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    println!("{} added", added);
+
+    Ok(Status::from_counts(errors, added))
+}
+
+fn identify(args: &IdentifyArgs) -> Result<Status, FmmdError> {
+    let database = Database::open()?;
+    let mut errors = 0;
+    let mut matches = Vec::new();
+    let mut unmatched = 0;
+
+    for file in args.write.input.collect() {
+        let result = metadata::read(&file).and_then(|metadata| {
+            tag::ensure_writable(metadata.format())?;
+            let fingerprint = fingerprint_of(&file, metadata.as_ref())?;
+            let best = database.best_match(&fingerprint)?;
+            Ok((fingerprint, best))
+        });
+
+        let (fingerprint, best) = match result {
+            Ok(found) => found,
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+                continue;
+            }
+        };
+
+        match best {
+            Some((score, known)) if score >= args.min_score => {
+                let message = format!("{}, {}%", describe(&known), score);
+                println!("{}: {}", file.display(), message.green());
+
+                let mut values = known.fields;
+                values.push((field(), chromaprint::encode(&fingerprint)));
+                matches.push((file, values));
+            }
+            Some((score, known)) => {
+                let message = format!("The best match was {} with {}%", describe(&known), score);
+                println!("{}: {}", file.display(), message.yellow());
+                unmatched += 1;
+            }
+            None => {
+                println!("{}: {}", file.display(), "No known tracks".yellow());
+                unmatched += 1;
+            }
+        }
+    }
+
+    let mut pending = Vec::new();
+    for (file, values) in &matches {
+        match tag::prepare_fields(file, &args.write, values) {
+            Ok(Some(changed)) => pending.push(changed),
+            Ok(None) => {}
+            Err(error) => {
+                print_error(file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    if !args.write.dry_run
+        && !pending.is_empty()
+        && (args.yes || tag::confirm(&format!("Write tags to {} files?", pending.len()))?)
+    {
+        for mut changed in pending {
+            if let Err(error) = changed.write() {
+                print_error(&changed.file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    Ok(Status::from_counts(errors + unmatched, matches.len()))
+}
+
+/// Describes a known track as `"Artist - Title"`
+fn describe(known: &Known) -> String {
+    format!(
+        "\"{} - {}\"",
+        known.get(&Field::Artist).unwrap_or_default(),
+        known.get(&Field::Title).unwrap_or_default()
+    )
+}
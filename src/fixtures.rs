@@ -0,0 +1,131 @@
+//! Small audio files for tests, just valid enough for fmmd to read their tags and find
+//! their audio
+use std::fs;
+use std::path::PathBuf;
+
+/// An empty directory of its own for each test
+pub fn directory(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fmmd-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// MPEG 1 layer III frames at 128 kbit/s and 44.1 kHz, their contents made up from `seed`
+pub fn mp3(seed: u8, frames: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    for frame in 0..frames {
+        data.extend([0xFF, 0xFB, 0x90, 0x44]);
+        data.extend((0..413).map(|byte| seed.wrapping_add((frame + byte) as u8)));
+    }
+    data
+}
+
+/// An ID3v1 tag with a title
+pub fn id3v1(title: &str) -> Vec<u8> {
+    let mut tag = b"TAG".to_vec();
+    tag.extend(title.bytes().take(30));
+    tag.resize(128, 0);
+    tag
+}
+
+/// An APEv2 tag with a header and a single item
+pub fn ape(key: &str, value: &str) -> Vec<u8> {
+    let mut item = Vec::new();
+    item.extend((value.len() as u32).to_le_bytes());
+    item.extend(0u32.to_le_bytes());
+    item.extend(key.bytes());
+    item.push(0);
+    item.extend(value.bytes());
+
+    let frame = |flags: u32| {
+        let mut frame = b"APETAGEX".to_vec();
+        frame.extend(2000u32.to_le_bytes());
+        frame.extend((item.len() as u32 + 32).to_le_bytes());
+        frame.extend(1u32.to_le_bytes());
+        frame.extend(flags.to_le_bytes());
+        frame.extend([0; 8]);
+        frame
+    };
+
+    let mut tag = frame(1 << 31 | 1 << 29);
+    tag.extend(&item);
+    tag.extend(frame(1 << 31));
+    tag
+}
+
+/// A FLAC file of 16 bit stereo at 44.1 kHz holding only a stream info block, followed by
+/// `audio`
+pub fn flac(audio: &[u8]) -> Vec<u8> {
+    let mut data = b"fLaC".to_vec();
+    data.extend([0x80, 0, 0, 34]);
+    data.extend([0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+    let samples = 44100u64;
+    data.extend((44100u64 << 44 | 1 << 41 | 15 << 36 | samples).to_be_bytes());
+    data.extend([0; 16]);
+    data.extend(audio);
+    data
+}
+
+/// An Ogg Vorbis file with the identification header, a comment header holding `comments`
+/// and `audio` as a single packet, each on a page of its own
+pub fn ogg_vorbis(comments: &[(&str, &str)], audio: &[u8]) -> Vec<u8> {
+    let mut identification = b"\x01vorbis".to_vec();
+    identification.extend(0u32.to_le_bytes());
+    identification.push(2);
+    identification.extend(44100u32.to_le_bytes());
+    identification.extend([0; 12]);
+    identification.extend([0xB8, 1]);
+
+    let mut comment = b"\x03vorbis".to_vec();
+    comment.extend(4u32.to_le_bytes());
+    comment.extend(b"fmmd");
+    comment.extend((comments.len() as u32).to_le_bytes());
+    for (key, value) in comments {
+        let pair = format!("{}={}", key, value);
+        comment.extend((pair.len() as u32).to_le_bytes());
+        comment.extend(pair.bytes());
+    }
+    comment.push(1);
+
+    let mut data = Vec::new();
+    for (sequence, packet) in [identification, comment, audio.to_vec()].iter().enumerate() {
+        data.extend(ogg_page(sequence as u32, packet));
+    }
+    data
+}
+
+/// A page of the stream with the serial number 1 holding a single packet
+fn ogg_page(sequence: u32, packet: &[u8]) -> Vec<u8> {
+    let mut lacing = vec![255; packet.len() / 255];
+    lacing.push((packet.len() % 255) as u8);
+
+    let mut page = b"OggS\0".to_vec();
+    page.push(if sequence == 0 { 2 } else { 0 });
+    page.extend(0u64.to_le_bytes());
+    page.extend(1u32.to_le_bytes());
+    page.extend(sequence.to_le_bytes());
+    page.extend(0u32.to_le_bytes());
+    page.push(lacing.len() as u8);
+    page.extend(lacing);
+    page.extend(packet);
+    page
+}
+
+/// A PNG image of noise made up from `seed`, which doesn't compress well
+pub fn png(width: u32, height: u32, seed: u8) -> Vec<u8> {
+    let image = image::RgbImage::from_fn(width, height, |x, y| {
+        let value = (x.wrapping_mul(7919) ^ y.wrapping_mul(104_729)).wrapping_mul(2_654_435_761);
+        let [a, b, c, _] = value.to_le_bytes();
+        image::Rgb([a ^ seed, b, c])
+    });
+
+    let mut data = Vec::new();
+    image
+        .write_to(
+            &mut std::io::Cursor::new(&mut data),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    data
+}
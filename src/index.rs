@@ -0,0 +1,734 @@
+//! A SQLite index of the metadata of a library, so that its files don't have to be parsed
+//! again on every run.
+//!
+//! The index lives in the data directory (`~/.local/share/fmmd/index.sqlite` on Linux) and
+//! stores the fields, tag entries and audio properties of each file along with its size and
+//! modification time. Commands given `--index` use what is stored for files that haven't
+//! changed since they were indexed and read every other file as usual.
+//!
+//! With `--checksums` the index also records a checksum of the audio of each file for
+//! `fmmd verify`. These are kept when a file is indexed again after its tags changed, as
+//! they are what the audio is compared against, and follow files that fmmd moves.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use clap::{Args, Subcommand};
+use owo_colors::OwoColorize;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use thiserror::Error;
+
+use crate::checksum;
+use crate::error::FmmdError;
+use crate::input::InputArgs;
+use crate::metadata::{self, Field, Format, Metadata, Properties};
+use crate::report::{print_error, Status};
+use crate::template::Lookup;
+
+/// Names formats are stored under
+const FORMATS: &[(Format, &str)] = &[
+    (Format::Mp3, "mp3"),
+    (Format::Flac, "flac"),
+    (Format::OggVorbis, "ogg-vorbis"),
+    (Format::Opus, "opus"),
+    (Format::Mp4, "mp4"),
+    (Format::Wav, "wav"),
+    (Format::Aiff, "aiff"),
+];
+
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        mtime INTEGER NOT NULL,
+        format TEXT NOT NULL,
+        duration_ms INTEGER,
+        sample_rate INTEGER,
+        channels INTEGER,
+        bitrate INTEGER
+    );
+
+    CREATE TABLE IF NOT EXISTS fields (
+        path TEXT NOT NULL REFERENCES files (path) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS entries (
+        path TEXT NOT NULL REFERENCES files (path) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS checksums (
+        path TEXT PRIMARY KEY,
+        checksum TEXT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS fields_path ON fields (path);
+    CREATE INDEX IF NOT EXISTS entries_path ON entries (path);
+";
+
+#[derive(Error, Debug)]
+pub enum IndexError {
+    #[error("Could not find a data directory for the index")]
+    NoDataDir,
+
+    #[error("Could not access the index: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Could not use the index: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[derive(Args)]
+pub struct IndexArgs {
+    #[command(subcommand)]
+    command: IndexCommand,
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Read all files into the index, replacing what it holds for them
+    Build(SourceArgs),
+
+    /// Read the files that changed since they were indexed and forget the ones that are gone
+    Update(SourceArgs),
+}
+
+#[derive(Args)]
+struct SourceArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Record a checksum of the audio of each file for `fmmd verify`. `update` only records
+    /// files that don't have one yet, `build` replaces them
+    #[arg(long)]
+    checksums: bool,
+}
+
+/// The size and modification time a file had when it was read
+type Stamp = (i64, i64);
+
+pub struct Index {
+    connection: Connection,
+}
+
+impl Index {
+    pub fn open() -> Result<Index, IndexError> {
+        Index::open_at(&index_path()?)
+    }
+
+    /// Opens the index stored in `path`, creating it if needed
+    fn open_at(path: &Path) -> Result<Index, IndexError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Index { connection })
+    }
+
+    /// Opens the index only if there is one already
+    pub fn open_existing() -> Result<Option<Index>, IndexError> {
+        match index_path()?.exists() {
+            true => Ok(Some(Index::open()?)),
+            false => Ok(None),
+        }
+    }
+
+    /// The stored metadata of `file`, unless it isn't indexed or changed since
+    pub fn get(&self, file: &Path) -> Result<Option<Box<dyn Metadata>>, IndexError> {
+        let (Some(key), Ok(stamp)) = (key(file), stamp(file)) else {
+            return Ok(None);
+        };
+
+        let row = self
+            .connection
+            .query_row(
+                "SELECT size, mtime, format, duration_ms, sample_rate, channels, bitrate
+                 FROM files WHERE path = ?1",
+                [&key],
+                |row| {
+                    let stored: Stamp = (row.get(0)?, row.get(1)?);
+                    let format: String = row.get(2)?;
+                    let properties = Properties {
+                        duration_ms: row.get::<_, Option<i64>>(3)?.map(|ms| ms as u64),
+                        sample_rate: row.get(4)?,
+                        channels: row.get(5)?,
+                        bitrate: row.get(6)?,
+                    };
+                    Ok((stored, format, properties))
+                },
+            )
+            .optional()?;
+
+        let Some((stored, format, properties)) = row else {
+            return Ok(None);
+        };
+
+        let format = FORMATS.iter().find(|(_, name)| *name == format);
+        let (Some((format, _)), true) = (format, stored == stamp) else {
+            return Ok(None);
+        };
+
+        let fields = self.pairs("SELECT name, value FROM fields WHERE path = ?1", &key)?;
+        let entries = self.pairs(
+            "SELECT key, value FROM entries WHERE path = ?1 ORDER BY position",
+            &key,
+        )?;
+
+        Ok(Some(Box::new(Indexed {
+            format: *format,
+            fields,
+            entries,
+            properties,
+        })))
+    }
+
+    /// The audio checksum recorded for `file`, whether or not it changed since
+    pub fn checksum(&self, file: &Path) -> Result<Option<String>, IndexError> {
+        let Some(key) = key(file) else {
+            return Ok(None);
+        };
+
+        let checksum = self
+            .connection
+            .query_row(
+                "SELECT checksum FROM checksums WHERE path = ?1",
+                [&key],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(checksum)
+    }
+
+    fn pairs(&self, query: &str, key: &str) -> Result<Vec<(String, String)>, IndexError> {
+        let mut statement = self.connection.prepare_cached(query)?;
+        let rows = statement.query_map([key], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+/// Files moved during a run, whose recorded checksums have to follow them.
+///
+/// The checksums are looked up as files are moved but only stored under their new paths
+/// once the run is over, as files swapping names would otherwise overwrite each other's.
+#[derive(Default)]
+pub struct ChecksumMoves {
+    /// Opened with the first move, so that runs that don't move anything leave it alone.
+    /// Holds `None` once opened if there is no index.
+    index: Option<Option<Index>>,
+    /// Old and new path of each file along with its checksum
+    moves: Vec<(String, String, Option<String>)>,
+}
+
+impl ChecksumMoves {
+    /// Moves checksums in `index` rather than the one in the data directory
+    #[cfg(test)]
+    pub fn with_index(index: Option<Index>) -> ChecksumMoves {
+        ChecksumMoves {
+            index: Some(index),
+            moves: Vec::new(),
+        }
+    }
+
+    /// Notes that `from` was moved to `to`, returning the checksum recorded for it
+    pub fn moved(&mut self, from: &Path, to: &Path) -> Result<Option<String>, IndexError> {
+        if self.index.is_none() {
+            self.index = Some(Index::open_existing()?);
+        }
+
+        let (Some(Some(index)), Some(from), Some(to)) = (&self.index, key(from), key(to)) else {
+            return Ok(None);
+        };
+
+        let checksum = index
+            .connection
+            .query_row(
+                "SELECT checksum FROM checksums WHERE path = ?1",
+                [&from],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        self.moves.push((from, to, checksum.clone()));
+        Ok(checksum)
+    }
+
+    /// Stores the checksums of the files moved so far under their new paths
+    pub fn apply(&mut self) -> Result<(), IndexError> {
+        let Some(Some(index)) = &mut self.index else {
+            return Ok(());
+        };
+
+        let transaction = index.connection.transaction()?;
+        for (from, to, _) in &self.moves {
+            transaction.execute("DELETE FROM checksums WHERE path IN (?1, ?2)", [from, to])?;
+        }
+        for (_, to, checksum) in &self.moves {
+            if let Some(checksum) = checksum {
+                transaction.execute(
+                    "INSERT OR REPLACE INTO checksums (path, checksum) VALUES (?1, ?2)",
+                    [to, checksum],
+                )?;
+            }
+        }
+        transaction.commit()?;
+
+        self.moves.clear();
+        Ok(())
+    }
+}
+
+impl Drop for ChecksumMoves {
+    fn drop(&mut self) {
+        if let Err(error) = self.apply() {
+            eprintln!(
+                "{}",
+                format!("Could not move checksums in the index: {}", error).yellow()
+            );
+        }
+    }
+}
+
+/// Reads the metadata of `file` from the index when it is up to date, or from the file
+pub fn read(index: Option<&Index>, file: &Path) -> Result<Box<dyn Metadata>, FmmdError> {
+    if let Some(metadata) = index.map(|index| index.get(file)).transpose()?.flatten() {
+        return Ok(metadata);
+    }
+
+    metadata::read(file)
+}
+
+/// Opens the index when `use_index` is set
+pub fn open(use_index: bool) -> Result<Option<Index>, FmmdError> {
+    Ok(use_index.then(Index::open).transpose()?)
+}
+
+pub fn index(args: &IndexArgs) -> Result<Status, FmmdError> {
+    let (source, rebuild) = match &args.command {
+        IndexCommand::Build(source) => (source, true),
+        IndexCommand::Update(source) => (source, false),
+    };
+    let input = &source.input;
+
+    let mut index = Index::open()?;
+    let mut errors = 0;
+
+    let files = input.collect_with(|file, error| {
+        print_error(file, error);
+        errors += 1;
+    });
+
+    let scan = scan(&mut index, &files, input.paths(), source.checksums, rebuild)?;
+
+    match source.checksums {
+        true => println!(
+            "{} indexed, {} unchanged, {} removed, {} checksums recorded",
+            scan.indexed, scan.unchanged, scan.removed, scan.recorded
+        ),
+        false => println!(
+            "{} indexed, {} unchanged, {} removed",
+            scan.indexed, scan.unchanged, scan.removed
+        ),
+    }
+
+    Ok(Status::from_counts(
+        errors + scan.errors,
+        scan.indexed + scan.unchanged,
+    ))
+}
+
+/// What a scan did to the index, by number of files
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Scan {
+    indexed: usize,
+    unchanged: usize,
+    removed: usize,
+    recorded: usize,
+    errors: usize,
+}
+
+/// Stores the metadata of `files`, skipping the ones that didn't change unless `rebuild`
+/// is set, and forgets files below the directories in `paths` that are gone. Files that
+/// can't be read are printed and counted.
+fn scan(
+    index: &mut Index,
+    files: &[PathBuf],
+    paths: &[PathBuf],
+    checksums: bool,
+    rebuild: bool,
+) -> Result<Scan, FmmdError> {
+    let mut scan = Scan::default();
+
+    let transaction = index.connection.transaction().map_err(IndexError::from)?;
+    for file in files {
+        let (Some(key), Ok(stamp)) = (key(file), stamp(file)) else {
+            print_error(file, "Could not index the file");
+            scan.errors += 1;
+            continue;
+        };
+
+        if checksums && (rebuild || !has_checksum(&transaction, &key)?) {
+            match checksum::audio(file) {
+                Ok(checksum) => {
+                    transaction
+                        .execute(
+                            "INSERT OR REPLACE INTO checksums (path, checksum) VALUES (?1, ?2)",
+                            [&key, &checksum],
+                        )
+                        .map_err(IndexError::from)?;
+                    scan.recorded += 1;
+                }
+                Err(error) => {
+                    print_error(file, error);
+                    scan.errors += 1;
+                }
+            }
+        }
+
+        if !rebuild && stored_stamp(&transaction, &key)? == Some(stamp) {
+            scan.unchanged += 1;
+            continue;
+        }
+
+        match metadata::read(file) {
+            Ok(metadata) => {
+                store(&transaction, &key, stamp, metadata.as_ref())?;
+                scan.indexed += 1;
+            }
+            Err(error) => {
+                print_error(file, error);
+                scan.errors += 1;
+            }
+        }
+    }
+
+    scan.removed = forget_missing(&transaction, paths)?;
+    transaction.commit().map_err(IndexError::from)?;
+
+    Ok(scan)
+}
+
+fn stored_stamp(connection: &Connection, key: &str) -> Result<Option<Stamp>, IndexError> {
+    let stamp = connection
+        .query_row(
+            "SELECT size, mtime FROM files WHERE path = ?1",
+            [key],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    Ok(stamp)
+}
+
+fn has_checksum(connection: &Connection, key: &str) -> Result<bool, IndexError> {
+    let found = connection
+        .query_row("SELECT 1 FROM checksums WHERE path = ?1", [key], |_| Ok(()))
+        .optional()?;
+
+    Ok(found.is_some())
+}
+
+fn store(
+    transaction: &Transaction,
+    key: &str,
+    (size, mtime): Stamp,
+    metadata: &dyn Metadata,
+) -> Result<(), IndexError> {
+    let format = FORMATS
+        .iter()
+        .find(|(format, _)| *format == metadata.format())
+        .map(|(_, name)| *name)
+        .unwrap();
+    let properties = metadata.properties();
+
+    transaction.execute("DELETE FROM files WHERE path = ?1", [key])?;
+    transaction.execute(
+        "INSERT INTO files (path, size, mtime, format, duration_ms, sample_rate, channels, bitrate)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            key,
+            size,
+            mtime,
+            format,
+            properties.duration_ms.map(|ms| ms as i64),
+            properties.sample_rate,
+            properties.channels,
+            properties.bitrate,
+        ],
+    )?;
+
+    let mut insert =
+        transaction.prepare_cached("INSERT INTO fields (path, name, value) VALUES (?1, ?2, ?3)")?;
+    for field in Field::STANDARD {
+        if let Some(value) = metadata.lookup(field) {
+            insert.execute(params![key, field.to_string(), value])?;
+        }
+    }
+
+    let mut insert = transaction.prepare_cached(
+        "INSERT INTO entries (path, position, key, value) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for (position, (entry, value)) in metadata.entries().iter().enumerate() {
+        insert.execute(params![key, position as i64, entry, value])?;
+    }
+
+    Ok(())
+}
+
+/// Removes files below the directories in `paths` that don't exist anymore
+fn forget_missing(transaction: &Transaction, paths: &[PathBuf]) -> Result<usize, IndexError> {
+    let dirs: Vec<_> = paths
+        .iter()
+        .filter(|path| path.is_dir())
+        .filter_map(|path| std::path::absolute(path).ok())
+        .collect();
+
+    let missing: Vec<String> = {
+        let mut statement =
+            transaction.prepare("SELECT path FROM files UNION SELECT path FROM checksums")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        rows.collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|path| {
+                let path = Path::new(path);
+                dirs.iter().any(|dir| path.starts_with(dir)) && !path.exists()
+            })
+            .collect()
+    };
+
+    for path in &missing {
+        transaction.execute("DELETE FROM files WHERE path = ?1", [path])?;
+        transaction.execute("DELETE FROM checksums WHERE path = ?1", [path])?;
+    }
+
+    Ok(missing.len())
+}
+
+fn index_path() -> Result<PathBuf, IndexError> {
+    let dir = dirs::data_dir().ok_or(IndexError::NoDataDir)?.join("fmmd");
+    Ok(dir.join("index.sqlite"))
+}
+
+/// Files are stored under their absolute path, which has to be valid UTF-8
+fn key(file: &Path) -> Option<String> {
+    std::path::absolute(file).ok()?.to_str().map(str::to_string)
+}
+
+fn stamp(file: &Path) -> io::Result<Stamp> {
+    let metadata = fs::metadata(file)?;
+    let modified = metadata.modified()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    Ok((metadata.len() as i64, since_epoch.as_nanos() as i64))
+}
+
+/// Metadata as it was stored in the index
+struct Indexed {
+    format: Format,
+    /// Values of [`Field::STANDARD`], by their name
+    fields: Vec<(String, String)>,
+    entries: Vec<(String, String)>,
+    properties: Properties,
+}
+
+impl Metadata for Indexed {
+    fn format(&self) -> Format {
+        self.format
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        self.entries.clone()
+    }
+
+    fn properties(&self) -> Properties {
+        self.properties
+    }
+}
+
+impl Lookup for Indexed {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        let name = field.to_string();
+
+        let found = match field {
+            // Entries are keyed like ID3 frames (`TXXX.Description`), or by their key
+            Field::Raw { .. } => self
+                .entries
+                .iter()
+                .find(|(key, _)| *key == name)
+                .or_else(|| {
+                    let raw_key = field.raw_key()?;
+                    self.entries.iter().find(|(key, _)| key == raw_key)
+                }),
+            _ => self.fields.iter().find(|(key, _)| *key == name),
+        };
+
+        found
+            .map(|(_, value)| value.clone())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use id3::TagLike;
+
+    use super::*;
+    use crate::fixtures;
+
+    /// Writes an MP3 file titled `title` with audio made up from `seed`, and as many frames
+    /// so that files written again are told apart by their size
+    fn mp3(file: &Path, title: &str, seed: u8) {
+        fs::write(file, fixtures::mp3(seed, seed as usize)).unwrap();
+        let mut tag = id3::Tag::new();
+        tag.set_title(title);
+        tag.write_to_path(file, id3::Version::Id3v24).unwrap();
+    }
+
+    fn count(index: &Index, table: &str) -> i64 {
+        let query = format!("SELECT COUNT(*) FROM {}", table);
+        index
+            .connection
+            .query_row(&query, [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn files_are_only_read_again_when_they_change() {
+        let dir = fixtures::directory("index-scan");
+        let mut index = Index::open_at(&dir.join("index.sqlite")).unwrap();
+        let files = [dir.join("a.mp3"), dir.join("b.mp3")];
+        mp3(&files[0], "One", 1);
+        mp3(&files[1], "Two", 2);
+
+        let scanned = scan(&mut index, &files, &[], false, false).unwrap();
+        assert_eq!((scanned.indexed, scanned.unchanged), (2, 0));
+
+        let stored = index.get(&files[0]).unwrap().unwrap();
+        assert_eq!(stored.format(), Format::Mp3);
+        assert_eq!(stored.lookup(&Field::Title).as_deref(), Some("One"));
+        assert_eq!(stored.properties().sample_rate, Some(44100));
+
+        let scanned = scan(&mut index, &files, &[], false, false).unwrap();
+        assert_eq!((scanned.indexed, scanned.unchanged), (0, 2));
+
+        // Stale entries aren't used until the file is indexed again
+        mp3(&files[0], "Uno", 3);
+        assert!(index.get(&files[0]).unwrap().is_none());
+
+        let scanned = scan(&mut index, &files, &[], false, false).unwrap();
+        assert_eq!((scanned.indexed, scanned.unchanged), (1, 1));
+        let stored = index.get(&files[0]).unwrap().unwrap();
+        assert_eq!(stored.lookup(&Field::Title).as_deref(), Some("Uno"));
+
+        let scanned = scan(&mut index, &files, &[], false, true).unwrap();
+        assert_eq!((scanned.indexed, scanned.unchanged), (2, 0));
+        assert_eq!(count(&index, "files"), 2);
+    }
+
+    #[test]
+    fn missing_files_are_forgotten_below_the_paths_scanned() {
+        let dir = fixtures::directory("index-missing");
+        let mut index = Index::open_at(&dir.join("index.sqlite")).unwrap();
+        let file = dir.join("a.mp3");
+        mp3(&file, "One", 1);
+        scan(&mut index, std::slice::from_ref(&file), &[], true, false).unwrap();
+
+        fs::remove_file(&file).unwrap();
+        let scanned = scan(&mut index, &[], &[dir.join("elsewhere")], false, false).unwrap();
+        assert_eq!(scanned.removed, 0);
+
+        let scanned = scan(&mut index, &[], std::slice::from_ref(&dir), false, false).unwrap();
+        assert_eq!(scanned.removed, 1);
+        assert_eq!(count(&index, "files"), 0);
+        assert_eq!(count(&index, "fields"), 0);
+        assert_eq!(count(&index, "checksums"), 0);
+    }
+
+    #[test]
+    fn checksums_are_kept_until_rebuilt() {
+        let dir = fixtures::directory("index-checksums");
+        let mut index = Index::open_at(&dir.join("index.sqlite")).unwrap();
+        let file = dir.join("a.mp3");
+        mp3(&file, "One", 1);
+
+        let scanned = scan(&mut index, std::slice::from_ref(&file), &[], true, false).unwrap();
+        assert_eq!(scanned.recorded, 1);
+        let recorded = index.checksum(&file).unwrap().unwrap();
+        assert_eq!(recorded, checksum::audio(&file).unwrap());
+
+        // The audio changes, but the recorded checksum is what it is compared against
+        mp3(&file, "One", 2);
+        let scanned = scan(&mut index, std::slice::from_ref(&file), &[], true, false).unwrap();
+        assert_eq!((scanned.recorded, scanned.indexed), (0, 1));
+        assert_eq!(index.checksum(&file).unwrap(), Some(recorded.clone()));
+
+        let scanned = scan(&mut index, std::slice::from_ref(&file), &[], true, true).unwrap();
+        assert_eq!(scanned.recorded, 1);
+        assert_ne!(index.checksum(&file).unwrap(), Some(recorded));
+    }
+
+    #[test]
+    fn unreadable_files_are_counted() {
+        let dir = fixtures::directory("index-unreadable");
+        let mut index = Index::open_at(&dir.join("index.sqlite")).unwrap();
+        let file = dir.join("notes.txt");
+        fs::write(&file, "not audio").unwrap();
+
+        let scanned = scan(&mut index, &[file, dir.join("gone.mp3")], &[], false, false).unwrap();
+        assert_eq!(
+            scanned,
+            Scan {
+                errors: 2,
+                ..Scan::default()
+            }
+        );
+    }
+
+    #[test]
+    fn checksums_follow_swapped_files() {
+        let dir = fixtures::directory("index-moves");
+        let index = Index::open_at(&dir.join("index.sqlite")).unwrap();
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(|name| dir.join(name));
+        for (file, checksum) in [(&a, "1"), (&b, "2"), (&d, "stale")] {
+            index
+                .connection
+                .execute(
+                    "INSERT INTO checksums (path, checksum) VALUES (?1, ?2)",
+                    [key(file).unwrap().as_str(), checksum],
+                )
+                .unwrap();
+        }
+
+        let mut moves = ChecksumMoves::with_index(Some(index));
+        assert_eq!(moves.moved(&a, &b).unwrap().as_deref(), Some("1"));
+        assert_eq!(moves.moved(&b, &a).unwrap().as_deref(), Some("2"));
+        assert_eq!(moves.moved(&c, &d).unwrap(), None);
+        moves.apply().unwrap();
+
+        let Some(Some(index)) = &moves.index else {
+            unreachable!();
+        };
+        assert_eq!(index.checksum(&a).unwrap().as_deref(), Some("2"));
+        assert_eq!(index.checksum(&b).unwrap().as_deref(), Some("1"));
+        assert_eq!(index.checksum(&d).unwrap(), None);
+        assert!(moves.moves.is_empty());
+    }
+
+    #[test]
+    fn moves_without_an_index_have_no_checksums() {
+        let mut moves = ChecksumMoves::with_index(None);
+        let moved = moves.moved(Path::new("a.mp3"), Path::new("b.mp3")).unwrap();
+        assert_eq!(moved, None);
+        moves.apply().unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+//! Working out the files to operate on from the paths given on the command line.
+//!
+//! Files are used as given; directories are searched when `--recursive` is passed, with
+//! glob filters deciding which of the files found in them are picked up.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use walkdir::WalkDir;
+
+use crate::error::FmmdError;
+use crate::plan::normalize;
+use crate::report::print_error;
+
+/// Files picked up in directories when no `--include` is given
+const AUDIO_FILES: &[&str] = &[
+    "*.mp3", "*.flac", "*.ogg", "*.oga", "*.opus", "*.m4a", "*.m4b", "*.mp4", "*.wav", "*.aif",
+    "*.aiff", "*.aifc",
+];
+
+#[derive(Args, Clone)]
+pub struct InputArgs {
+    /// Files to work on, or directories with --recursive
+    files: Vec<PathBuf>,
+
+    /// Search directories for files, including their subdirectories
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Only use files in directories matching this glob, e.g. "*.flac" (repeatable, defaults to audio files)
+    #[arg(long, value_name = "GLOB", value_parser = parse_glob)]
+    include: Vec<Glob>,
+
+    /// Skip files and directories matching this glob, e.g. "Podcasts" (repeatable)
+    #[arg(long, value_name = "GLOB", value_parser = parse_glob)]
+    exclude: Vec<Glob>,
+
+    /// Follow symbolic links while searching directories instead of skipping them
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Include hidden files and directories while searching directories
+    #[arg(long)]
+    hidden: bool,
+
+    /// Don't search more than this many directories deep
+    #[arg(long, value_name = "DEPTH", requires = "recursive")]
+    max_depth: Option<usize>,
+}
+
+impl InputArgs {
+    /// The paths given on the command line
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Lists the files to work on, printing errors for paths that can't be used
+    pub fn collect(&self) -> Vec<PathBuf> {
+        self.collect_with(|path, error| {
+            print_error(path, error);
+        })
+    }
+
+    /// Lists the files to work on, passing paths that can't be used to `report`. Files
+    /// reached more than once, e.g. through `-r . ./`, are only listed the first time.
+    pub fn collect_with(&self, mut report: impl FnMut(&Path, FmmdError)) -> Vec<PathBuf> {
+        let include = match self.include.is_empty() {
+            true => glob_set(AUDIO_FILES.iter().map(|glob| parse_glob(glob).unwrap())),
+            false => glob_set(self.include.iter().cloned()),
+        };
+        let exclude = glob_set(self.exclude.iter().cloned());
+
+        let mut files = Vec::new();
+
+        for path in &self.files {
+            if path.is_dir() && self.recursive {
+                self.walk(path, &include, &exclude, &mut files, &mut report);
+            } else if path.is_dir() {
+                report(path, FmmdError::IsDirectory);
+            } else if path.exists() {
+                files.push(path.clone());
+            } else {
+                report(path, FmmdError::NotFound);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        files.retain(|file| seen.insert(normalize(file)));
+        files
+    }
+
+    /// Adds the files below `dir` that pass the filters to `files`
+    fn walk(
+        &self,
+        dir: &Path,
+        include: &GlobSet,
+        exclude: &GlobSet,
+        files: &mut Vec<PathBuf>,
+        report: &mut impl FnMut(&Path, FmmdError),
+    ) {
+        let mut walker = WalkDir::new(dir)
+            .follow_links(self.follow_symlinks)
+            .sort_by_file_name();
+        if let Some(max_depth) = self.max_depth {
+            walker = walker.max_depth(max_depth + 1);
+        }
+
+        let entries = walker.into_iter().filter_entry(|entry| {
+            let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+
+            entry.depth() == 0
+                || !(exclude.is_match(relative) || exclude.is_match(entry.file_name()))
+                    && (self.hidden || !hidden)
+        });
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    let path = error.path().unwrap_or(dir).to_path_buf();
+                    report(&path, error.into());
+                    continue;
+                }
+            };
+
+            let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+            if entry.file_type().is_file() && include.is_match(relative) {
+                files.push(entry.into_path());
+            }
+        }
+    }
+}
+
+fn parse_glob(glob: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(glob).case_insensitive(true).build()
+}
+
+fn glob_set(globs: impl Iterator<Item = Glob>) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(glob);
+    }
+
+    builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// A directory of its own for each test holding empty files at `files`
+    fn directory(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fmmd-input-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    fn args(files: &[PathBuf]) -> InputArgs {
+        InputArgs {
+            files: files.to_vec(),
+            recursive: true,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            follow_symlinks: false,
+            hidden: false,
+            max_depth: None,
+        }
+    }
+
+    /// The files collected below `dir`, relative to it, and the number of errors
+    fn collect(args: &InputArgs, dir: &Path) -> (Vec<String>, usize) {
+        let mut errors = 0;
+        let files = args.collect_with(|_, _| errors += 1);
+        let files = files
+            .iter()
+            .map(|file| {
+                file.strip_prefix(dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        (files, errors)
+    }
+
+    #[test]
+    fn directories_are_searched_for_audio_files() {
+        let dir = directory(
+            "audio",
+            &[
+                "b/02.FLAC",
+                "b/cover.jpg",
+                "a/01.mp3",
+                ".hidden/03.mp3",
+                "a/.04.mp3",
+            ],
+        );
+
+        let (files, errors) = collect(&args(std::slice::from_ref(&dir)), &dir);
+        assert_eq!(files, ["a/01.mp3", "b/02.FLAC"]);
+        assert_eq!(errors, 0);
+
+        let mut hidden = args(std::slice::from_ref(&dir));
+        hidden.hidden = true;
+        let (files, _) = collect(&hidden, &dir);
+        assert_eq!(
+            files,
+            [".hidden/03.mp3", "a/.04.mp3", "a/01.mp3", "b/02.FLAC"]
+        );
+    }
+
+    #[test]
+    fn globs_filter_files_and_directories() {
+        let dir = directory(
+            "globs",
+            &[
+                "Music/01.flac",
+                "Music/02.mp3",
+                "Podcasts/01.mp3",
+                "Music/Live/03.flac",
+            ],
+        );
+
+        let mut args = args(std::slice::from_ref(&dir));
+        args.include = vec![parse_glob("*.flac").unwrap()];
+        args.exclude = vec![parse_glob("live").unwrap()];
+        let (files, _) = collect(&args, &dir);
+        assert_eq!(files, ["Music/01.flac"]);
+
+        args.include = Vec::new();
+        args.exclude = vec![parse_glob("Podcasts").unwrap()];
+        args.max_depth = Some(1);
+        let (files, _) = collect(&args, &dir);
+        assert_eq!(files, ["Music/01.flac", "Music/02.mp3"]);
+    }
+
+    #[test]
+    fn paths_are_only_listed_once() {
+        let dir = directory("once", &["01.mp3", "02.mp3"]);
+        let (files, errors) = collect(
+            &args(&[
+                dir.join("01.mp3"),
+                dir.clone(),
+                dir.join("."),
+                dir.join("missing.mp3"),
+            ]),
+            &dir,
+        );
+        assert_eq!(files, ["01.mp3", "02.mp3"]);
+        assert_eq!(errors, 1);
+
+        let mut flat = args(std::slice::from_ref(&dir));
+        flat.recursive = false;
+        assert_eq!(collect(&flat, &dir), (Vec::new(), 1));
+    }
+}
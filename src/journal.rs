@@ -0,0 +1,337 @@
+//! A journal of every rename run so that runs can be listed and undone.
+//!
+//! Each run gets its own file in the data directory (`~/.local/share/fmmd/journal` on
+//! Linux) with one JSON record per line: a header describing the run, one record per
+//! renamed file and an `undone` record once the run has been reverted.
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::index::{ChecksumMoves, IndexError};
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("Could not find a data directory for the journal")]
+    NoDataDir,
+
+    #[error("Could not access the journal: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Could not read the journal: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("No run with the ID \"{0}\" was found")]
+    UnknownRun(String),
+
+    #[error("There are no runs left to undo")]
+    NothingToUndo,
+
+    #[error(transparent)]
+    Index(#[from] IndexError),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record {
+    Run {
+        id: String,
+        started: String,
+        cwd: PathBuf,
+    },
+    Rename(Entry),
+    Undone {
+        at: String,
+    },
+}
+
+/// A single file renamed during a run
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub old: PathBuf,
+    pub new: PathBuf,
+    /// Size of the file after the rename
+    pub size: u64,
+    /// Modification time of the file after the rename, in nanoseconds since the epoch
+    pub mtime: u64,
+    /// Checksum of the audio, from the index or taken with `--checksums`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Directories created for the new name, outermost first, which are all undo removes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub created: Vec<PathBuf>,
+}
+
+impl Entry {
+    /// Whether the renamed file still looks like it did right after the run
+    pub fn is_unchanged(&self) -> bool {
+        match fs::metadata(&self.new) {
+            Ok(metadata) => {
+                metadata.len() == self.size && modified(&metadata).ok() == Some(self.mtime)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// A previous run as read back from the journal
+pub struct Run {
+    pub id: String,
+    pub started: String,
+    pub cwd: PathBuf,
+    pub renames: Vec<Entry>,
+    pub undone: Option<String>,
+    path: PathBuf,
+}
+
+impl Run {
+    /// Records that the run has been reverted
+    pub fn mark_undone(&mut self) -> Result<(), JournalError> {
+        let at = now();
+        append(&self.path, &Record::Undone { at: at.clone() })?;
+        self.undone = Some(at);
+        Ok(())
+    }
+}
+
+/// The journal of the current run. The file is only created once the first file is renamed.
+pub struct Journal {
+    id: String,
+    path: PathBuf,
+    started: bool,
+    checksums: ChecksumMoves,
+}
+
+impl Journal {
+    pub fn new() -> Result<Journal, JournalError> {
+        Ok(Journal::in_dir(&journal_dir()?, ChecksumMoves::default()))
+    }
+
+    /// A journal in `dir` that leaves the index alone
+    #[cfg(test)]
+    pub fn for_tests(dir: &Path) -> Journal {
+        Journal::in_dir(dir, ChecksumMoves::with_index(None))
+    }
+
+    fn in_dir(dir: &Path, checksums: ChecksumMoves) -> Journal {
+        let base = humantime::format_rfc3339_seconds(SystemTime::now())
+            .to_string()
+            .replace(['-', ':', 'Z'], "")
+            .replace('T', "-");
+
+        // Two runs within the same second get a counter appended
+        let mut id = base.clone();
+        let mut counter = 1;
+        while dir.join(format!("{}.jsonl", id)).exists() {
+            counter += 1;
+            id = format!("{}-{}", base, counter);
+        }
+
+        Journal {
+            path: dir.join(format!("{}.jsonl", id)),
+            id,
+            started: false,
+            checksums,
+        }
+    }
+
+    /// Records a rename that has just happened, along with the checksum of the audio of the
+    /// file and the directories created for it. The checksum recorded in the index wins over
+    /// `checksum`, and moves to the new path.
+    pub fn record(
+        &mut self,
+        old: &Path,
+        new: &Path,
+        checksum: Option<String>,
+        created: Vec<PathBuf>,
+    ) -> Result<(), JournalError> {
+        if !self.started {
+            fs::create_dir_all(self.path.parent().unwrap())?;
+            let header = Record::Run {
+                id: self.id.clone(),
+                started: now(),
+                cwd: env::current_dir()?,
+            };
+            append(&self.path, &header)?;
+            self.started = true;
+        }
+
+        // The file has been moved already, so the rename is recorded even if the index fails
+        let indexed = self.checksums.moved(old, new);
+
+        let metadata = fs::metadata(new)?;
+        let entry = Entry {
+            old: std::path::absolute(old)?,
+            new: std::path::absolute(new)?,
+            size: metadata.len(),
+            mtime: modified(&metadata)?,
+            checksum: indexed.as_ref().ok().cloned().flatten().or(checksum),
+            created: created
+                .iter()
+                .map(std::path::absolute)
+                .collect::<Result<_, _>>()?,
+        };
+
+        append(&self.path, &Record::Rename(entry))?;
+        indexed?;
+        Ok(())
+    }
+}
+
+/// Lists all runs found in the journal, oldest first
+pub fn runs() -> Result<Vec<Run>, JournalError> {
+    let dir = journal_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "jsonl")
+        })
+        .collect();
+    paths.sort_by_cached_key(|path| {
+        let id = path.file_stem().and_then(|stem| stem.to_str());
+        (id.and_then(run_order), path.clone())
+    });
+
+    paths.into_iter().map(|path| load(&path)).collect()
+}
+
+/// The time a run started and its counter within that second, parsed from its ID, e.g.
+/// `20240101-120000-2`. Names don't sort by themselves, as `-10` would come before `-2` and
+/// both before the first run of the second.
+fn run_order(id: &str) -> Option<(u64, u32)> {
+    let (date, rest) = id.split_once('-')?;
+    let (time, counter) = match rest.split_once('-') {
+        Some((time, counter)) => (time, counter.parse().ok()?),
+        None => (rest, 1),
+    };
+
+    if date.len() != 8 || time.len() != 6 {
+        return None;
+    }
+    Some((format!("{}{}", date, time).parse().ok()?, counter))
+}
+
+/// Finds the run with the given ID or, without one, the latest run that hasn't been undone
+pub fn find(id: Option<&str>) -> Result<Run, JournalError> {
+    let runs = runs()?;
+
+    match id {
+        Some(id) => runs
+            .into_iter()
+            .find(|run| run.id == id)
+            .ok_or_else(|| JournalError::UnknownRun(id.to_string())),
+        None => runs
+            .into_iter()
+            .rev()
+            .find(|run| run.undone.is_none())
+            .ok_or(JournalError::NothingToUndo),
+    }
+}
+
+fn load(path: &Path) -> Result<Run, JournalError> {
+    let mut run = Run {
+        id: String::new(),
+        started: String::new(),
+        cwd: PathBuf::new(),
+        renames: Vec::new(),
+        undone: None,
+        path: path.to_path_buf(),
+    };
+
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(&line)? {
+            Record::Run { id, started, cwd } => {
+                run.id = id;
+                run.started = started;
+                run.cwd = cwd;
+            }
+            Record::Rename(entry) => run.renames.push(entry),
+            Record::Undone { at } => run.undone = Some(at),
+        }
+    }
+
+    Ok(run)
+}
+
+fn append(path: &Path, record: &Record) -> Result<(), JournalError> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+fn journal_dir() -> Result<PathBuf, JournalError> {
+    let data_dir = dirs::data_dir().ok_or(JournalError::NoDataDir)?;
+    Ok(data_dir.join("fmmd").join("journal"))
+}
+
+fn now() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+}
+
+fn modified(metadata: &fs::Metadata) -> io::Result<u64> {
+    let modified = metadata.modified()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(since_epoch.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_within_a_second_follow_the_first() {
+        let mut ids = vec![
+            "20240101-120000-10",
+            "20240101-120001",
+            "20240101-120000-2",
+            "20240101-120000",
+            "20231231-235959",
+        ];
+        ids.sort_by_key(|id| run_order(id));
+
+        assert_eq!(
+            ids,
+            [
+                "20231231-235959",
+                "20240101-120000",
+                "20240101-120000-2",
+                "20240101-120000-10",
+                "20240101-120001",
+            ]
+        );
+    }
+
+    #[test]
+    fn other_names_have_no_order() {
+        assert_eq!(run_order("notes"), None);
+        assert_eq!(run_order("2024-01-01"), None);
+        assert_eq!(run_order("20240101-120000-x"), None);
+    }
+
+    #[test]
+    fn entries_of_older_runs_have_no_created_directories() {
+        let line = r#"{"type":"rename","old":"/a.mp3","new":"/b.mp3","size":1,"mtime":2}"#;
+        let Record::Rename(entry) = serde_json::from_str(line).unwrap() else {
+            panic!("not a rename");
+        };
+        assert!(entry.created.is_empty());
+        assert_eq!(entry.checksum, None);
+    }
+}
@@ -0,0 +1,647 @@
+//! The `lookup` subcommand, filling in tags from MusicBrainz.
+//!
+//! Files are grouped into albums by the directory they are in. Releases are searched for
+//! by the title, artist and number of tracks of an album (or given with `--release`), then
+//! scored against its files, and the tags of the best one are written when it scores high
+//! enough.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use owo_colors::OwoColorize;
+use thiserror::Error;
+
+use crate::error::FmmdError;
+use crate::metadata::{self, Field, Format, Metadata};
+use crate::report::{print_error, Status};
+use crate::tag::{self, WriteArgs};
+
+mod musicbrainz;
+
+use musicbrainz::{credit, Client, Release, Track, DEFAULT_BASE_URL};
+
+/// Number of search results that are looked up to score their tracks
+const CANDIDATES: usize = 3;
+
+#[derive(Error, Debug)]
+pub enum LookupError {
+    #[error("Could not query MusicBrainz: {0}")]
+    Request(#[from] ureq::Error),
+
+    #[error("--release can only be used with the files of a single album")]
+    SeveralAlbums,
+}
+
+#[derive(Args)]
+pub struct LookupArgs {
+    #[command(flatten)]
+    write: WriteArgs,
+
+    /// Use this release instead of searching, given by its MusicBrainz ID
+    #[arg(long, value_name = "MBID")]
+    release: Option<String>,
+
+    /// Lowest score in percent a release found by searching needs for its tags to be written
+    #[arg(long, value_name = "PERCENT", default_value_t = 70)]
+    min_score: u32,
+
+    /// MusicBrainz web service to query, e.g. a mirror or a local stand-in
+    #[arg(long, value_name = "URL", default_value = DEFAULT_BASE_URL)]
+    base_url: String,
+
+    /// Write the tags without asking first
+    #[arg(short, long)]
+    yes: bool,
+}
+
+/// A file along with the metadata read from it
+type TaggedFile = (PathBuf, Box<dyn Metadata>);
+
+/// The track of a release a file was matched with, along with the disc it is on
+struct Assigned<'a> {
+    track: &'a Track,
+    disc: u32,
+    discs: usize,
+    tracks: usize,
+}
+
+pub fn lookup(args: &LookupArgs) -> Result<Status, FmmdError> {
+    let mut albums: BTreeMap<PathBuf, Vec<TaggedFile>> = BTreeMap::new();
+    let mut errors = 0;
+
+    for file in args.write.input.collect() {
+        let result = metadata::read(&file).and_then(|metadata| {
+            tag::ensure_writable(metadata.format())?;
+            Ok(metadata)
+        });
+
+        match result {
+            Ok(metadata) => {
+                let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+                albums.entry(dir).or_default().push((file, metadata));
+            }
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    if args.release.is_some() && albums.len() > 1 {
+        return Err(LookupError::SeveralAlbums.into());
+    }
+
+    let mut client = Client::new(&args.base_url);
+    let mut pending = Vec::new();
+    let mut matched = 0;
+    let mut unmatched = 0;
+
+    for (dir, files) in &albums {
+        println!("{}", dir.display().bold());
+
+        let release = match find_release(&mut client, args, dir, files) {
+            Ok(release) => release,
+            Err(error) => {
+                eprintln!("  {}", error.red());
+                errors += 1;
+                continue;
+            }
+        };
+
+        let Some((score, release)) = release else {
+            println!("  {}", "No releases found".yellow());
+            unmatched += 1;
+            continue;
+        };
+
+        let description = describe(&release);
+        // A release given with `--release` is used whatever its score
+        if score < args.min_score && args.release.is_none() {
+            let message = format!("The best match was {} with {}%", description, score);
+            println!("  {}", message.yellow());
+            unmatched += 1;
+            continue;
+        }
+
+        println!("  {}", format!("{}, {}%", description, score).green());
+        matched += 1;
+
+        let assigned = assign(files, &release);
+        for ((file, metadata), assigned) in files.iter().zip(&assigned) {
+            let Some(assigned) = assigned else {
+                println!("  {}: \"{}\"", "No matching track".yellow(), file.display());
+                continue;
+            };
+
+            let values = values(&release, assigned, metadata.format());
+            match tag::prepare_fields(file, &args.write, &values) {
+                Ok(Some(changed)) => pending.push(changed),
+                Ok(None) => {}
+                Err(error) => {
+                    print_error(file, error);
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    if !args.write.dry_run
+        && !pending.is_empty()
+        && (args.yes || tag::confirm(&format!("Write tags to {} files?", pending.len()))?)
+    {
+        for mut changed in pending {
+            if let Err(error) = changed.write() {
+                print_error(&changed.file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    Ok(Status::from_counts(errors + unmatched, matched))
+}
+
+/// Finds the release that matches the files of an album best, along with its score
+fn find_release(
+    client: &mut Client,
+    args: &LookupArgs,
+    dir: &Path,
+    files: &[TaggedFile],
+) -> Result<Option<(u32, Release)>, LookupError> {
+    if let Some(id) = &args.release {
+        let release = client.release(id)?;
+        return Ok(Some((score(files, &release), release)));
+    }
+
+    let title = most_common(files, &[Field::Album]).or_else(|| {
+        let dir = std::path::absolute(dir).ok()?;
+        Some(dir.file_name()?.to_string_lossy().to_string())
+    });
+    let Some(title) = title else {
+        return Ok(None);
+    };
+    let artist = most_common(files, &[Field::AlbumArtist, Field::Artist]);
+
+    let mut results = client.search(&title, artist.as_deref(), files.len())?;
+
+    // Searches don't include tracks, so only the most promising results are looked up
+    results.sort_by_key(|release| std::cmp::Reverse(score(files, release)));
+    results.truncate(CANDIDATES);
+
+    let mut best: Option<(u32, Release)> = None;
+    for result in results {
+        let release = client.release(&result.id)?;
+        let score = score(files, &release);
+        if best.as_ref().is_none_or(|(best, _)| score > *best) {
+            best = Some((score, release));
+        }
+    }
+
+    Ok(best)
+}
+
+/// Scores how well a release matches the files of an album in percent.
+///
+/// Each of the album title, artist, number of tracks, files matched with a track and track
+/// titles counts the same, leaving out what the files don't have.
+fn score(files: &[TaggedFile], release: &Release) -> u32 {
+    let mut scores = Vec::new();
+
+    if let Some(album) = most_common(files, &[Field::Album]) {
+        scores.push(similarity(&album, &release.title));
+    }
+
+    let artist = most_common(files, &[Field::AlbumArtist, Field::Artist]);
+    if let (Some(artist), Some(credit)) = (artist, credit(&release.artist_credit)) {
+        scores.push(similarity(&artist, &credit));
+    }
+
+    let (count, tracks) = (files.len(), release.tracks());
+    scores.push(count.min(tracks) as f64 / count.max(tracks).max(1) as f64);
+
+    // Releases from searches don't list their tracks
+    if release.media.iter().any(|medium| !medium.tracks.is_empty()) {
+        let assigned = assign(files, release);
+        let matched = assigned
+            .iter()
+            .filter(|assigned| assigned.is_some())
+            .count();
+        scores.push(matched as f64 / count.max(1) as f64);
+
+        let titles: Vec<f64> = files
+            .iter()
+            .zip(&assigned)
+            .filter_map(|((_, metadata), assigned)| {
+                let title = metadata.lookup(&Field::Title)?;
+                Some(
+                    assigned
+                        .as_ref()
+                        .map_or(0.0, |assigned| similarity(&title, &assigned.track.title)),
+                )
+            })
+            .collect();
+        if !titles.is_empty() {
+            scores.push(titles.iter().sum::<f64>() / titles.len() as f64);
+        }
+    }
+
+    let score = scores.iter().sum::<f64>() / scores.len() as f64;
+    (score * 100.0).round() as u32
+}
+
+/// Matches files with the tracks of a release: by disc and track number first, then by
+/// title, and finally in the order of their names when there are as many files as tracks
+fn assign<'a>(files: &[TaggedFile], release: &'a Release) -> Vec<Option<Assigned<'a>>> {
+    let discs = release.media.len();
+    let tracks: Vec<Assigned> = release
+        .media
+        .iter()
+        .enumerate()
+        .flat_map(|(index, medium)| {
+            let disc = medium.position.unwrap_or(index as u32 + 1);
+            medium.tracks.iter().map(move |track| Assigned {
+                track,
+                disc,
+                discs,
+                tracks: medium.tracks.len(),
+            })
+        })
+        .collect();
+
+    let mut taken = vec![false; tracks.len()];
+    let mut assigned: Vec<Option<usize>> = vec![None; files.len()];
+
+    let number = |metadata: &dyn Metadata, field: &Field| -> Option<u32> {
+        metadata.lookup(field)?.trim().parse().ok()
+    };
+
+    for (index, (_, metadata)) in files.iter().enumerate() {
+        let Some(position) = number(metadata.as_ref(), &Field::Track) else {
+            continue;
+        };
+        let disc = number(metadata.as_ref(), &Field::Disc).unwrap_or(1);
+
+        let found = tracks
+            .iter()
+            .position(|track| track.disc == disc && track.track.position == position);
+        if let Some(found) = found.filter(|&found| !taken[found]) {
+            taken[found] = true;
+            assigned[index] = Some(found);
+        }
+    }
+
+    for (index, (_, metadata)) in files.iter().enumerate() {
+        let Some(title) = metadata
+            .lookup(&Field::Title)
+            .filter(|_| assigned[index].is_none())
+        else {
+            continue;
+        };
+
+        let best = tracks
+            .iter()
+            .enumerate()
+            .filter(|(found, _)| !taken[*found])
+            .map(|(found, track)| (found, similarity(&title, &track.track.title)))
+            .filter(|(_, similarity)| *similarity >= 0.5)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some((found, _)) = best {
+            taken[found] = true;
+            assigned[index] = Some(found);
+        }
+    }
+
+    if files.len() == tracks.len() {
+        let mut free = (0..tracks.len()).filter(|&found| !taken[found]);
+        let mut unassigned: Vec<usize> = (0..files.len())
+            .filter(|&index| assigned[index].is_none())
+            .collect();
+        unassigned.sort_by_key(|&index| &files[index].0);
+
+        for index in unassigned {
+            assigned[index] = free.next();
+        }
+    }
+
+    let mut tracks: Vec<Option<Assigned>> = tracks.into_iter().map(Some).collect();
+    assigned
+        .into_iter()
+        .map(|found| tracks[found?].take())
+        .collect()
+}
+
+/// The tags of a track of a release, with the MusicBrainz IDs under the keys Picard uses
+/// for the format of the file
+fn values(release: &Release, assigned: &Assigned, format: Format) -> Vec<(Field, String)> {
+    let track = assigned.track;
+    let album_artist = credit(&release.artist_credit);
+    let artist = credit(&track.artist_credit).or(album_artist.clone());
+
+    let musicbrainz = |name: &str, key: &str| match format {
+        Format::Flac | Format::OggVorbis | Format::Opus => Field::Raw {
+            key: key.to_string(),
+            description: None,
+        },
+        // MP4 files get `----:com.apple.iTunes:` atoms named by the description
+        Format::Mp3 | Format::Mp4 | Format::Wav | Format::Aiff => Field::Raw {
+            key: "TXXX".to_string(),
+            description: Some(format!("MusicBrainz {}", name)),
+        },
+    };
+    let first_artist = |credits: &[musicbrainz::ArtistCredit]| {
+        credits.first().map(|credit| credit.artist.id.clone())
+    };
+
+    let values = [
+        (Field::Title, Some(track.title.clone())),
+        (Field::Artist, artist),
+        (Field::Album, Some(release.title.clone())),
+        (Field::AlbumArtist, album_artist),
+        (
+            Field::Year,
+            release.date.clone().filter(|date| !date.is_empty()),
+        ),
+        (
+            Field::Track,
+            Some(format!("{}/{}", track.position, assigned.tracks)),
+        ),
+        (
+            Field::Disc,
+            Some(format!("{}/{}", assigned.disc, assigned.discs)),
+        ),
+        (
+            musicbrainz("Album Id", "MUSICBRAINZ_ALBUMID"),
+            Some(release.id.clone()),
+        ),
+        (
+            musicbrainz("Release Group Id", "MUSICBRAINZ_RELEASEGROUPID"),
+            release.release_group.as_ref().map(|group| group.id.clone()),
+        ),
+        (
+            musicbrainz("Release Track Id", "MUSICBRAINZ_RELEASETRACKID"),
+            Some(track.id.clone()),
+        ),
+        (
+            musicbrainz("Artist Id", "MUSICBRAINZ_ARTISTID"),
+            first_artist(&track.artist_credit).or(first_artist(&release.artist_credit)),
+        ),
+        (
+            musicbrainz("Album Artist Id", "MUSICBRAINZ_ALBUMARTISTID"),
+            first_artist(&release.artist_credit),
+        ),
+    ];
+
+    values
+        .into_iter()
+        .filter_map(|(field, value)| Some((field, value?)))
+        .collect()
+}
+
+/// Describes a release, e.g. "Artist - Title (2003, 12 tracks)"
+fn describe(release: &Release) -> String {
+    let artist = credit(&release.artist_credit).unwrap_or_default();
+    let year = release
+        .date
+        .as_deref()
+        .and_then(|date| date.get(..4))
+        .map(|year| format!("{}, ", year))
+        .unwrap_or_default();
+    let tracks = match release.tracks() {
+        1 => "1 track".to_string(),
+        count => format!("{} tracks", count),
+    };
+
+    format!("\"{} - {}\" ({}{})", artist, release.title, year, tracks)
+}
+
+/// The value most files have for the first of `fields` they have
+fn most_common(files: &[TaggedFile], fields: &[Field]) -> Option<String> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+
+    let values = files
+        .iter()
+        .filter_map(|(_, metadata)| fields.iter().find_map(|field| metadata.lookup(field)));
+    for value in values {
+        match counts.iter_mut().find(|(known, _)| *known == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+
+    // Ties go to the value seen first
+    let most = counts.iter().map(|(_, count)| *count).max()?;
+    counts
+        .into_iter()
+        .find(|(_, count)| *count == most)
+        .map(|(value, _)| value)
+}
+
+/// How alike two names are from 0 to 1, ignoring case, punctuation and spacing
+fn similarity(a: &str, b: &str) -> f64 {
+    let normalize = |value: &str| -> Vec<char> {
+        value
+            .chars()
+            .flat_map(char::to_lowercase)
+            .filter(|c| c.is_alphanumeric())
+            .collect()
+    };
+    let (a, b) = (normalize(a), normalize(b));
+
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    1.0 - distance(&a, &b) as f64 / longest as f64
+}
+
+/// Levenshtein distance between two strings
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use id3::TagLike;
+
+    use super::*;
+
+    const RELEASE: &str = r#"{"id": "r1", "title": "Geogaddi", "date": "2002-02-18",
+        "artist-credit": [{"name": "Boards of Canada", "artist": {"id": "a1"}}],
+        "release-group": {"id": "g1"},
+        "media": [{"position": 1, "tracks": [
+            {"id": "t1", "position": 1, "title": "Ready Lets Go"},
+            {"id": "t2", "position": 2, "title": "Music Is Math"},
+            {"id": "t3", "position": 3, "title": "Beware the Friendly Stranger"}]}]}"#;
+
+    fn release(json: &str) -> Release {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// A file of the album with the given disc, track number and title
+    fn file(name: &str, disc: Option<u32>, track: Option<u32>, title: Option<&str>) -> TaggedFile {
+        let mut tag = id3::Tag::new();
+        tag.set_album("Geogaddi");
+        tag.set_artist("Boards of Canada");
+        if let Some(disc) = disc {
+            tag.set_disc(disc);
+        }
+        if let Some(track) = track {
+            tag.set_track(track);
+        }
+        if let Some(title) = title {
+            tag.set_title(title);
+        }
+        (PathBuf::from(name), Box::new(tag))
+    }
+
+    /// The IDs of the tracks the files were matched with
+    fn assigned_ids(files: &[TaggedFile], release: &Release) -> Vec<Option<String>> {
+        assign(files, release)
+            .iter()
+            .map(|assigned| Some(assigned.as_ref()?.track.id.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn similarity_ignores_case_punctuation_and_spacing() {
+        assert_eq!(similarity("Music Is Math", "music is math!"), 1.0);
+        assert_eq!(similarity("Album", "Albun"), 0.8);
+        assert_eq!(similarity("abc", "xyz"), 0.0);
+        assert_eq!(similarity("", "?!"), 1.0);
+    }
+
+    #[test]
+    fn files_are_assigned_by_number_then_by_title_then_by_name() {
+        let release = release(RELEASE);
+        let files = vec![
+            file("c.mp3", None, None, None),
+            file("a.mp3", None, Some(2), Some("Ready Lets Go")),
+            file("b.mp3", None, None, Some("beware the friendly stranger")),
+        ];
+
+        let ids = assigned_ids(&files, &release);
+        assert_eq!(
+            ids,
+            [Some("t1".into()), Some("t2".into()), Some("t3".into())]
+        );
+    }
+
+    #[test]
+    fn files_are_only_assigned_by_name_when_there_are_as_many_as_tracks() {
+        let release = release(RELEASE);
+        let files = vec![
+            file("a.mp3", None, None, Some("Something Else")),
+            file("b.mp3", None, Some(3), None),
+        ];
+
+        assert_eq!(assigned_ids(&files, &release), [None, Some("t3".into())]);
+    }
+
+    #[test]
+    fn track_numbers_are_matched_on_their_disc() {
+        let release = release(
+            r#"{"id": "r1", "title": "Geogaddi", "media": [
+                {"position": 1, "tracks": [{"id": "t1", "position": 1, "title": "One"}]},
+                {"position": 2, "tracks": [{"id": "t2", "position": 1, "title": "Two"}]}]}"#,
+        );
+        let files = vec![file("a.mp3", Some(2), Some(1), None)];
+
+        let assigned = assign(&files, &release);
+        let assigned = assigned[0].as_ref().unwrap();
+        assert_eq!(assigned.track.id, "t2");
+        assert_eq!((assigned.disc, assigned.discs, assigned.tracks), (2, 2, 1));
+    }
+
+    #[test]
+    fn a_matching_release_scores_full_marks() {
+        let files = vec![
+            file("1.mp3", None, Some(1), Some("Ready Lets Go")),
+            file("2.mp3", None, Some(2), Some("Music Is Math")),
+            file("3.mp3", None, Some(3), Some("Beware the Friendly Stranger")),
+        ];
+
+        assert_eq!(score(&files, &release(RELEASE)), 100);
+    }
+
+    #[test]
+    fn search_results_are_scored_without_their_tracks() {
+        let files = vec![
+            file("1.mp3", None, Some(1), Some("Ready Lets Go")),
+            file("2.mp3", None, Some(2), Some("Music Is Math")),
+            file("3.mp3", None, Some(3), Some("Beware the Friendly Stranger")),
+        ];
+        let release = release(
+            r#"{"id": "r1", "title": "Geogaddi", "track-count": 6,
+                "artist-credit": [{"name": "Boards of Canada", "artist": {"id": "a1"}}]}"#,
+        );
+
+        // Album and artist match, but only half the tracks are there
+        assert_eq!(score(&files, &release), 83);
+    }
+
+    #[test]
+    fn titles_of_other_tracks_lower_the_score() {
+        let files = vec![
+            file("1.mp3", None, Some(1), Some("Ready Lets Go")),
+            file("2.mp3", None, Some(2), Some("Music Is Math")),
+            file("3.mp3", None, Some(3), Some("1969")),
+        ];
+
+        let score = score(&files, &release(RELEASE));
+        assert!((80..100).contains(&score), "{}", score);
+    }
+
+    #[test]
+    fn musicbrainz_ids_are_written_under_the_keys_of_the_format() {
+        let release = release(RELEASE);
+        let files = vec![file("1.mp3", None, Some(1), None)];
+        let assigned = assign(&files, &release);
+        let assigned = assigned[0].as_ref().unwrap();
+
+        let album_id = |format: Format| {
+            values(&release, assigned, format)
+                .into_iter()
+                .find(|(_, value)| value == "r1")
+                .map(|(field, _)| field)
+                .unwrap()
+        };
+
+        assert_eq!(
+            album_id(Format::Mp3).to_string(),
+            "TXXX.MusicBrainz Album Id"
+        );
+        assert_eq!(album_id(Format::Flac).to_string(), "MUSICBRAINZ_ALBUMID");
+        assert_eq!(album_id(Format::Opus).to_string(), "MUSICBRAINZ_ALBUMID");
+        assert_eq!(
+            album_id(Format::Mp4).raw_key(),
+            Some("MusicBrainz Album Id")
+        );
+
+        let keys: Vec<String> = values(&release, assigned, Format::Flac)
+            .into_iter()
+            .filter_map(|(field, _)| Some(field.raw_key()?.to_string()))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "MUSICBRAINZ_ALBUMID",
+                "MUSICBRAINZ_RELEASEGROUPID",
+                "MUSICBRAINZ_RELEASETRACKID",
+                "MUSICBRAINZ_ARTISTID",
+                "MUSICBRAINZ_ALBUMARTISTID",
+            ]
+        );
+    }
+}
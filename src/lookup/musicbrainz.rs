@@ -0,0 +1,289 @@
+//! A small client for the MusicBrainz web service, see <https://musicbrainz.org/doc/MusicBrainz_API>
+use std::net::IpAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use ureq::Agent;
+
+use super::LookupError;
+
+pub const DEFAULT_BASE_URL: &str = "https://musicbrainz.org/ws/2";
+
+const USER_AGENT: &str = concat!("fmmd/", env!("CARGO_PKG_VERSION"));
+
+/// MusicBrainz allows a request per second. Only servers on this machine, such as a local
+/// stand-in for tests, aren't throttled.
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+struct SearchResult {
+    #[serde(default)]
+    releases: Vec<Release>,
+}
+
+/// A release as returned by searches, which leave out the tracks, or by lookups
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Release {
+    pub id: String,
+    pub title: String,
+    pub date: Option<String>,
+    #[serde(default)]
+    pub artist_credit: Vec<ArtistCredit>,
+    pub release_group: Option<ReleaseGroup>,
+    #[serde(default)]
+    pub media: Vec<Medium>,
+    pub track_count: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ArtistCredit {
+    pub name: String,
+    #[serde(default)]
+    pub joinphrase: String,
+    pub artist: Artist,
+}
+
+#[derive(Deserialize)]
+pub struct Artist {
+    pub id: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseGroup {
+    pub id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Medium {
+    pub position: Option<u32>,
+    pub track_count: Option<usize>,
+    #[serde(default)]
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Track {
+    pub id: String,
+    pub position: u32,
+    pub title: String,
+    #[serde(default)]
+    pub artist_credit: Vec<ArtistCredit>,
+}
+
+impl Release {
+    /// Number of tracks on all media
+    pub fn tracks(&self) -> usize {
+        match self.track_count {
+            Some(count) => count,
+            None => self
+                .media
+                .iter()
+                .map(|medium| medium.track_count.unwrap_or(medium.tracks.len()))
+                .sum(),
+        }
+    }
+}
+
+/// Joins the names of the credited artists, e.g. "Artist feat. Other"
+pub fn credit(credits: &[ArtistCredit]) -> Option<String> {
+    let credit: String = credits
+        .iter()
+        .map(|credit| format!("{}{}", credit.name, credit.joinphrase))
+        .collect();
+
+    (!credit.is_empty()).then_some(credit)
+}
+
+pub struct Client {
+    agent: Agent,
+    base_url: String,
+    throttle: bool,
+    last_request: Option<Instant>,
+}
+
+impl Client {
+    pub fn new(base_url: &str) -> Client {
+        let agent = Agent::config_builder()
+            .user_agent(USER_AGENT)
+            .timeout_global(Some(Duration::from_secs(30)))
+            .build()
+            .into();
+
+        Client {
+            agent,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            throttle: !is_local(base_url),
+            last_request: None,
+        }
+    }
+
+    /// Searches releases by title and artist, preferring ones with `tracks` tracks
+    pub fn search(
+        &mut self,
+        title: &str,
+        artist: Option<&str>,
+        tracks: usize,
+    ) -> Result<Vec<Release>, LookupError> {
+        let mut query = format!("release:{}", quote(title));
+        if let Some(artist) = artist {
+            query.push_str(&format!(" AND artist:{}", quote(artist)));
+        }
+
+        // Releases with a different number of tracks are still candidates, e.g. when
+        // some files of the album are missing
+        let exact = format!("{} AND tracks:{}", query, tracks);
+        let result: SearchResult = self.get("release", &[("query", &exact), ("limit", "10")])?;
+        if !result.releases.is_empty() {
+            return Ok(result.releases);
+        }
+
+        let result: SearchResult = self.get("release", &[("query", &query), ("limit", "10")])?;
+        Ok(result.releases)
+    }
+
+    /// Looks up a release along with its tracks
+    pub fn release(&mut self, id: &str) -> Result<Release, LookupError> {
+        let inc = "recordings+artist-credits+release-groups";
+        self.get(&format!("release/{}", id), &[("inc", inc)])
+    }
+
+    fn get<T: DeserializeOwned>(
+        &mut self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, LookupError> {
+        if let Some(last) = self.last_request.filter(|_| self.throttle) {
+            thread::sleep(REQUEST_INTERVAL.saturating_sub(last.elapsed()));
+        }
+        self.last_request = Some(Instant::now());
+
+        let url = format!("{}/{}", self.base_url, path);
+        let mut response = self
+            .agent
+            .get(&url)
+            .query("fmt", "json")
+            .query_pairs(query.iter().copied())
+            .call()?;
+
+        Ok(response.body_mut().read_json()?)
+    }
+}
+
+/// Whether the server at `url` runs on this machine
+fn is_local(url: &str) -> bool {
+    let Some(host) = url
+        .parse::<ureq::http::Uri>()
+        .ok()
+        .and_then(|uri| uri.host().map(str::to_string))
+    else {
+        return false;
+    };
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<IpAddr>()
+            .is_ok_and(|address| address.is_loopback())
+}
+
+/// Quotes a value for a Lucene search query
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    use super::*;
+
+    const RELEASE: &str = r#"{"id": "r1", "title": "Album", "track-count": 2,
+        "artist-credit": [{"name": "Artist", "joinphrase": "", "artist": {"id": "a1"}}]}"#;
+
+    /// Serves `bodies` in turn as JSON, one request per connection, sending the request
+    /// line of each request through the returned channel
+    fn serve(bodies: Vec<String>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for body in bodies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                sender.send(request.trim_end().to_string()).unwrap();
+
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+
+        (format!("http://{}/ws/2/", address), receiver)
+    }
+
+    #[test]
+    fn only_musicbrainz_is_throttled() {
+        assert!(Client::new(DEFAULT_BASE_URL).throttle);
+        assert!(Client::new("https://musicbrainz.org/ws/2/").throttle);
+        assert!(Client::new("https://mirror.example/ws/2").throttle);
+        assert!(!Client::new("http://localhost:5000/ws/2").throttle);
+        assert!(!Client::new("http://127.0.0.1:5000/ws/2/").throttle);
+        assert!(!Client::new("http://[::1]:5000/ws/2").throttle);
+    }
+
+    #[test]
+    fn search_falls_back_to_any_number_of_tracks() {
+        let (base_url, requests) = serve(vec![
+            r#"{"releases": []}"#.to_string(),
+            format!(r#"{{"releases": [{}]}}"#, RELEASE),
+        ]);
+        let mut client = Client::new(&base_url);
+
+        let started = Instant::now();
+        let releases = client.search("Album", Some("Artist"), 3).unwrap();
+        assert!(started.elapsed() < REQUEST_INTERVAL);
+
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0].title, "Album");
+        assert_eq!(releases[0].tracks(), 2);
+        assert_eq!(
+            credit(&releases[0].artist_credit).as_deref(),
+            Some("Artist")
+        );
+
+        let exact = requests.recv().unwrap();
+        assert!(exact.starts_with("GET /ws/2/release?fmt=json&query="));
+        assert!(exact.contains("tracks%3A3"));
+        assert!(!requests.recv().unwrap().contains("tracks"));
+    }
+
+    #[test]
+    fn release_is_looked_up_with_its_tracks() {
+        let (base_url, requests) = serve(vec![RELEASE.to_string()]);
+        let release = Client::new(&base_url).release("r1").unwrap();
+
+        assert_eq!(release.id, "r1");
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("GET /ws/2/release/r1?fmt=json&inc=recordings"));
+    }
+}
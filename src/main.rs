@@ -1,96 +1,129 @@
-use std::fs;
-use std::path::{Path, PathBuf};
-
-use clap::Parser;
-use id3::{Tag, TagLike};
+use clap::{Parser, Subcommand};
 use owo_colors::OwoColorize;
-use thiserror::Error;
+
+use crate::error::FmmdError;
+use crate::report::Status;
+
+mod art;
+mod check;
+mod checksum;
+mod config;
+mod discs;
+mod dupes;
+mod error;
+mod files;
+mod fingerprint;
+#[cfg(test)]
+mod fixtures;
+mod index;
+mod input;
+mod journal;
+mod lookup;
+mod metadata;
+mod plan;
+mod rename;
+mod report;
+mod review;
+mod sanitize;
+mod show;
+mod tag;
+mod template;
+mod tracks;
+mod tui;
+mod undo;
+mod verify;
 
 #[derive(Parser)]
 #[command(name = "fmmd - fix music metadata")]
 #[command(author = "Travis Hathaway")]
 #[command(version = "0.1.0")]
 #[command(about = "Used to rename music files based on their metadata")]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// List of files to rename
-    files: Vec<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Perform a dry run without renaming files
-    #[arg(short, long)]
-    dry_run: bool,
-
-    /// Print verbose output
-    #[arg(short, long)]
-    verbose: bool,
+    #[command(flatten)]
+    rename: rename::RenameArgs,
 }
 
-#[derive(Error, Debug)]
-enum FmmdError {
-    #[error("Could not parse the file")]
-    FileParse(#[from] id3::Error),
-
-    #[error("Could not rename the file")]
-    FileRename(#[from] std::io::Error),
+#[derive(Subcommand)]
+enum Command {
+    /// Revert the renames of a previous run
+    Undo(undo::UndoArgs),
 
-    #[error("Could not find enough information in the file to rename it")]
-    NotEnoughMetadata,
-}
+    /// List previous runs that can be undone
+    History,
 
-/// Attempts to read metadata from file and renames it if it has enough data
-fn rename_file(file: &Path, cli: &Cli) -> Result<(), FmmdError> {
-    let tag = match Tag::read_from_path(file) {
-        Ok(tag) => tag,
-        Err(error) => return Err(FmmdError::FileParse(error)),
-    };
+    /// Write tags to files
+    Tag(tag::TagArgs),
 
-    let new_file = get_filename(tag, file)?;
+    /// Show the tags of files
+    Show(show::ShowArgs),
 
-    if cli.dry_run || cli.verbose {
-        println!(
-            "{} -> {}",
-            file.to_str().unwrap(),
-            new_file.to_str().unwrap()
-        );
-    }
+    /// Find albums whose files disagree on album fields or track numbers
+    Check(check::CheckArgs),
 
-    if cli.dry_run {
-        return Ok(());
-    }
+    /// Browse a library full-screen, editing tags and previewing new names
+    Tui(tui::TuiArgs),
 
-    if let Err(error) = fs::rename(file, new_file) {
-        return Err(FmmdError::FileRename(error));
-    }
+    /// Store the metadata of a library in an index that other commands can read with --index
+    Index(index::IndexArgs),
 
-    Ok(())
-}
+    /// Fill in tags of albums from MusicBrainz
+    Lookup(lookup::LookupArgs),
 
-/// Attempts to crate a new file name based on the `Tag` and `PathBuf` provided.
-///
-/// We need at least the track name and the track number to create a new file name.
-fn get_filename(tag: Tag, file: &Path) -> Result<PathBuf, FmmdError> {
-    let title = tag.title().unwrap_or_default();
-    let track = tag.track().unwrap_or(0);
+    /// Identify files by their audio, with fingerprints matched against known tracks
+    Fingerprint(fingerprint::FingerprintArgs),
 
-    if title.is_empty() && track == 0 {
-        return Err(FmmdError::NotEnoughMetadata);
-    }
+    /// Find copies of the same track and move the worse ones out of the way
+    Dupes(dupes::DupesArgs),
 
-    let parent = file.parent().unwrap().to_str().unwrap();
-    let extension = file.extension().unwrap().to_str().unwrap();
-    let track = format!("{:0>2}", track);
-    let new_path = Path::new(parent).join(format!("{}-{}.{}", track, title, extension));
+    /// Check that the audio of files is unchanged since its checksum was recorded
+    Verify(verify::VerifyArgs),
 
-    Ok(new_path)
+    /// Extract, embed, remove and resize cover art
+    Art(art::ArtArgs),
 }
 
+/// Exit code for invalid arguments, following sysexits.h
+const EXIT_USAGE: i32 = 64;
+
 fn main() {
-    let cli = Cli::parse();
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(error) => {
+            let _ = error.print();
+            // --help and --version end up here as well
+            std::process::exit(if error.use_stderr() { EXIT_USAGE } else { 0 });
+        }
+    };
+
+    let result = match &cli.command {
+        Some(Command::Undo(args)) => undo::undo(args),
+        Some(Command::History) => undo::history().map(|_| Status::Success),
+        Some(Command::Tag(args)) => tag::tag(args),
+        Some(Command::Show(args)) => show::show(args),
+        Some(Command::Check(args)) => check::check(args),
+        Some(Command::Tui(args)) => tui::tui(args),
+        Some(Command::Index(args)) => index::index(args),
+        Some(Command::Lookup(args)) => lookup::lookup(args),
+        Some(Command::Fingerprint(args)) => fingerprint::fingerprint(args),
+        Some(Command::Dupes(args)) => dupes::dupes(args),
+        Some(Command::Verify(args)) => verify::verify(args),
+        Some(Command::Art(args)) => art::art(args),
+        None => rename::rename(&cli.rename),
+    };
 
-    for file in &cli.files {
-        if file.exists() {
-            if let Err(error) = rename_file(file, &cli) {
-                eprintln!("{}: \"{}\"", error.red(), file.to_str().unwrap().red());
-            }
+    match result {
+        Ok(status) => std::process::exit(status.code()),
+        Err(error) => {
+            eprintln!("{}", error.red());
+            let code = match error {
+                FmmdError::Template(_) | FmmdError::Config(_) => EXIT_USAGE,
+                _ => Status::Failure.code(),
+            };
+            std::process::exit(code);
         }
     }
 }
@@ -0,0 +1,114 @@
+//! ID3 tags, as found in MP3 files and embedded in WAV and AIFF files
+use std::path::Path;
+
+use id3::{Content, Tag, TagLike};
+
+use super::{picture_summary, Field, Format, Metadata, MetadataError};
+use crate::template::Lookup;
+
+/// Reads an ID3v2 tag, falling back to ID3v1
+pub fn read(path: &Path) -> Result<Tag, MetadataError> {
+    Ok(id3::v1v2::read_from_path(path)?)
+}
+
+impl Metadata for Tag {
+    fn format(&self) -> Format {
+        Format::Mp3
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        self.frames()
+            .map(|frame| {
+                let id = frame.id();
+                let with_description = |description: &str| match description {
+                    "" => id.to_string(),
+                    description => format!("{}.{}", id, description),
+                };
+
+                match frame.content() {
+                    // Version 2.4 separates multiple values with NUL
+                    Content::Text(text) => (id.to_string(), text.replace('\0', "; ")),
+                    Content::ExtendedText(text) => {
+                        (with_description(&text.description), text.value.clone())
+                    }
+                    Content::ExtendedLink(link) => {
+                        (with_description(&link.description), link.link.clone())
+                    }
+                    Content::Comment(comment) => {
+                        (with_description(&comment.description), comment.text.clone())
+                    }
+                    Content::Lyrics(lyrics) => {
+                        (with_description(&lyrics.description), lyrics.text.clone())
+                    }
+                    Content::Picture(picture) => (
+                        id.to_string(),
+                        picture_summary(
+                            &picture.picture_type.to_string(),
+                            &picture.mime_type,
+                            picture.data.len(),
+                        ),
+                    ),
+                    Content::EncapsulatedObject(object) => (
+                        with_description(&object.description),
+                        format!("{}, {} bytes", object.mime_type, object.data.len()),
+                    ),
+                    Content::Private(private) => (
+                        format!("{}.{}", id, private.owner_identifier),
+                        format!("{} bytes", private.private_data.len()),
+                    ),
+                    Content::Unknown(unknown) => {
+                        (id.to_string(), format!("{} bytes", unknown.data.len()))
+                    }
+                    content => (id.to_string(), content.to_string()),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Lookup for Tag {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        let value = match field {
+            Field::Title => self.title().map(String::from),
+            Field::Artist => self.artist().map(String::from),
+            Field::Album => self.album().map(String::from),
+            Field::AlbumArtist => self.album_artist().map(String::from),
+            Field::Year => self
+                .year()
+                .or_else(|| self.date_recorded().map(|date| date.year))
+                .or_else(|| self.date_released().map(|date| date.year))
+                .map(|year| year.to_string()),
+            Field::Track => self.track().map(|track| track.to_string()),
+            Field::TrackTotal => self.total_tracks().map(|total| total.to_string()),
+            Field::Disc => self.disc().map(|disc| disc.to_string()),
+            Field::DiscTotal => self.total_discs().map(|total| total.to_string()),
+            Field::Genre => self.genre_parsed().map(String::from),
+            Field::Composer => self
+                .get("TCOM")
+                .and_then(|frame| frame.content().text())
+                .map(String::from),
+            Field::Comment => self.comments().next().map(|comment| comment.text.clone()),
+            Field::Raw {
+                key,
+                description: Some(description),
+            } => match key.as_str() {
+                "TXXX" => self
+                    .extended_texts()
+                    .find(|text| text.description.eq_ignore_ascii_case(description))
+                    .map(|text| text.value.clone()),
+                "WXXX" => self
+                    .extended_links()
+                    .find(|link| link.description.eq_ignore_ascii_case(description))
+                    .map(|link| link.link.clone()),
+                "COMM" => self
+                    .comments()
+                    .find(|comment| comment.description.eq_ignore_ascii_case(description))
+                    .map(|comment| comment.text.clone()),
+                _ => None,
+            },
+            Field::Raw { key, .. } => self.get(key).map(|frame| frame.content().to_string()),
+        };
+
+        value.filter(|value| !value.is_empty())
+    }
+}
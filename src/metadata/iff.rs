@@ -0,0 +1,264 @@
+//! WAV and AIFF files, both built out of IFF style chunks.
+//!
+//! Tags are read from an embedded ID3 chunk when there is one, falling back to the
+//! native text chunks: RIFF `LIST`/`INFO` for WAV and `NAME`, `AUTH`, ... for AIFF.
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+use id3::Tag;
+
+use super::{lookup_text, Field, Format, Metadata, MetadataError, Properties};
+use crate::template::Lookup;
+
+/// Text chunks larger than this are treated as corrupt rather than read into memory
+const MAX_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A chunk ID and its body
+type Chunk = ([u8; 4], Vec<u8>);
+
+pub struct ChunkMetadata {
+    format: Format,
+    id3: Option<Tag>,
+    text: Vec<(String, String)>,
+    /// Read from the `fmt ` chunk of WAV and the `COMM` chunk of AIFF files
+    properties: Properties,
+}
+
+pub fn read_wav(path: &Path) -> Result<ChunkMetadata, MetadataError> {
+    let mut metadata = ChunkMetadata {
+        format: Format::Wav,
+        id3: None,
+        text: Vec::new(),
+        properties: Properties::default(),
+    };
+
+    let mut format = None;
+    let (chunks, audio) = read_chunks(path, false, &[b"LIST", b"id3 ", b"ID3 ", b"fmt "])?;
+
+    for (id, body) in chunks {
+        match &id {
+            b"LIST" if body.starts_with(b"INFO") => {
+                for (id, value) in walk(&body[4..], false) {
+                    metadata.text.push((chunk_id(&id), text(value)));
+                }
+            }
+            b"id3 " | b"ID3 " => metadata.id3 = Tag::read_from2(Cursor::new(body)).ok(),
+            b"fmt " => format = Some(body),
+            _ => {}
+        }
+    }
+
+    // Channels at 2, the sample rate at 4 and the bytes per second at 8
+    if let Some(format) = format.filter(|format| format.len() >= 12) {
+        let byte_rate = u32::from_le_bytes(format[8..12].try_into().unwrap());
+        metadata.properties = Properties {
+            duration_ms: audio
+                .filter(|_| byte_rate > 0)
+                .map(|audio| (audio.end - audio.start) * 1000 / byte_rate as u64),
+            sample_rate: Some(u32::from_le_bytes(format[4..8].try_into().unwrap())),
+            channels: Some(u16::from_le_bytes(format[2..4].try_into().unwrap()) as u32),
+            bitrate: Some(byte_rate.saturating_mul(8)),
+        };
+    }
+
+    Ok(metadata)
+}
+
+pub fn read_aiff(path: &Path) -> Result<ChunkMetadata, MetadataError> {
+    let mut metadata = ChunkMetadata {
+        format: Format::Aiff,
+        id3: None,
+        text: Vec::new(),
+        properties: Properties::default(),
+    };
+
+    let wanted = [
+        b"NAME", b"AUTH", b"ANNO", b"(c) ", b"ID3 ", b"id3 ", b"COMM",
+    ];
+    for (id, body) in read_chunks(path, true, &wanted)?.0 {
+        match &id {
+            b"ID3 " | b"id3 " => metadata.id3 = Tag::read_from2(Cursor::new(body)).ok(),
+            b"COMM" => metadata.properties = aiff_properties(&body),
+            _ => metadata.text.push((chunk_id(&id), text(&body))),
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Reads the channels, sample frames, bits per sample and the sample rate, which is stored
+/// as an 80 bit extended precision float, from a `COMM` chunk
+fn aiff_properties(body: &[u8]) -> Properties {
+    if body.len() < 18 {
+        return Properties::default();
+    }
+
+    let channels = u16::from_be_bytes(body[0..2].try_into().unwrap()) as u32;
+    let frames = u32::from_be_bytes(body[2..6].try_into().unwrap()) as u64;
+    let bits = u16::from_be_bytes(body[6..8].try_into().unwrap()) as u32;
+
+    let exponent = (u16::from_be_bytes(body[8..10].try_into().unwrap()) & 0x7FFF) as i32;
+    let mantissa = u64::from_be_bytes(body[10..18].try_into().unwrap());
+    let sample_rate = (mantissa as f64 * 2f64.powi(exponent - 16383 - 63)).round() as u32;
+
+    Properties {
+        duration_ms: (sample_rate > 0).then(|| frames * 1000 / sample_rate as u64),
+        sample_rate: Some(sample_rate),
+        channels: Some(channels),
+        bitrate: Some(sample_rate * channels * bits),
+    }
+}
+
+/// The byte range of the audio (`data` or `SSND`) chunk of a WAV or AIFF file
+pub fn audio_range(path: &Path, format: Format) -> Result<Range<u64>, MetadataError> {
+    read_chunks(path, format == Format::Aiff, &[])?
+        .1
+        .ok_or(MetadataError::Malformed("no audio chunk"))
+}
+
+/// Reads the top level chunks listed in `wanted`, skipping over everything else.
+/// The byte range of the audio (`data` or `SSND`) chunk is returned along with them.
+fn read_chunks(
+    path: &Path,
+    big_endian: bool,
+    wanted: &[&[u8; 4]],
+) -> Result<(Vec<Chunk>, Option<Range<u64>>), MetadataError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let file_size = reader.get_ref().metadata()?.len();
+    let mut chunks = Vec::new();
+    let mut audio = None;
+
+    // Skip the `RIFF`/`FORM` header and the form type
+    let mut position = reader.seek(SeekFrom::Start(12))?;
+
+    while position + 8 <= file_size {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+
+        let id: [u8; 4] = header[..4].try_into().unwrap();
+        let size = size(header[4..].try_into().unwrap(), big_endian) as u64;
+        // Chunks are padded to an even number of bytes
+        let padded = size + size % 2;
+
+        if &id == b"data" || &id == b"SSND" {
+            let start = position + 8;
+            audio = Some(start..(start + size).min(file_size));
+        }
+
+        if wanted.contains(&&id) {
+            if size > MAX_CHUNK_SIZE {
+                return Err(MetadataError::Malformed("chunk is too large"));
+            }
+
+            let mut body = vec![0; size as usize];
+            reader.read_exact(&mut body)?;
+            reader.seek(SeekFrom::Current((padded - size) as i64))?;
+            chunks.push((id, body));
+        } else {
+            reader.seek(SeekFrom::Current(padded as i64))?;
+        }
+
+        position += 8 + padded;
+    }
+
+    Ok((chunks, audio))
+}
+
+/// Walks the chunks contained in `data`, e.g. the sub chunks of a `LIST` chunk
+fn walk(mut data: &[u8], big_endian: bool) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+
+    while data.len() >= 8 {
+        let id: [u8; 4] = data[..4].try_into().unwrap();
+        let size = size(data[4..8].try_into().unwrap(), big_endian);
+        let Some(body) = data.get(8..8 + size) else {
+            break;
+        };
+
+        chunks.push((id, body));
+        data = data.get(8 + size + size % 2..).unwrap_or_default();
+    }
+
+    chunks
+}
+
+fn size(bytes: [u8; 4], big_endian: bool) -> usize {
+    if big_endian {
+        u32::from_be_bytes(bytes) as usize
+    } else {
+        u32::from_le_bytes(bytes) as usize
+    }
+}
+
+fn chunk_id(id: &[u8; 4]) -> String {
+    String::from_utf8_lossy(id).trim_end().to_string()
+}
+
+/// Text chunks are usually NUL terminated and may be padded
+fn text(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string()
+}
+
+fn wav_keys(field: &Field) -> &'static [&'static str] {
+    match field {
+        Field::Title => &["INAM"],
+        Field::Artist => &["IART"],
+        Field::Album => &["IPRD"],
+        Field::AlbumArtist => &["IAAR"],
+        Field::Year => &["ICRD"],
+        Field::Track => &["ITRK", "IPRT"],
+        Field::Genre => &["IGNR"],
+        Field::Composer => &["IMUS"],
+        Field::Comment => &["ICMT"],
+        _ => &[],
+    }
+}
+
+fn aiff_keys(field: &Field) -> &'static [&'static str] {
+    match field {
+        Field::Title => &["NAME"],
+        Field::Artist => &["AUTH"],
+        Field::Comment => &["ANNO"],
+        _ => &[],
+    }
+}
+
+impl Metadata for ChunkMetadata {
+    fn format(&self) -> Format {
+        self.format
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        let id3 = self.id3.iter().flat_map(|tag| tag.entries());
+        id3.chain(self.text.iter().cloned()).collect()
+    }
+
+    fn properties(&self) -> Properties {
+        self.properties
+    }
+}
+
+impl Lookup for ChunkMetadata {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        if let Some(value) = self.id3.as_ref().and_then(|tag| tag.lookup(field)) {
+            return Some(value);
+        }
+
+        let keys = match self.format {
+            Format::Aiff => aiff_keys,
+            _ => wav_keys,
+        };
+
+        lookup_text(field, keys, |key| {
+            self.text
+                .iter()
+                .find(|(id, _)| id == key)
+                .map(|(_, value)| value.clone())
+        })
+    }
+}
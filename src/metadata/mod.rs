@@ -0,0 +1,421 @@
+//! Reading metadata from the different audio file formats.
+//!
+//! Each backend exposes its tags through the [`Metadata`] trait so that the same
+//! templates work regardless of the format. The backend is chosen by looking at the
+//! first bytes of the file rather than at its extension.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::error::FmmdError;
+use crate::template::Lookup;
+
+mod id3tag;
+mod iff;
+mod mp4;
+mod mpeg;
+mod vorbis;
+
+pub use vorbis::{comment_keys, write_flac};
+
+#[derive(Error, Debug)]
+pub enum MetadataError {
+    #[error(transparent)]
+    Id3(#[from] id3::Error),
+
+    #[error(transparent)]
+    Flac(#[from] metaflac::Error),
+
+    #[error(transparent)]
+    Mp4(#[from] mp4ameta::Error),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    Malformed(&'static str),
+}
+
+/// Audio file formats fmmd knows how to read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    Mp3,
+    Flac,
+    OggVorbis,
+    Opus,
+    Mp4,
+    Wav,
+    Aiff,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Mp3 => "MP3",
+            Format::Flac => "FLAC",
+            Format::OggVorbis => "Ogg Vorbis",
+            Format::Opus => "Opus",
+            Format::Mp4 => "MP4",
+            Format::Wav => "WAV",
+            Format::Aiff => "AIFF",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Metadata read from a file, independent of the format it is stored in
+pub trait Metadata: Lookup {
+    fn format(&self) -> Format;
+
+    /// Everything stored in the tag as key and value, in the order it is stored in.
+    /// Binary data such as cover art is summarized instead of included.
+    fn entries(&self) -> Vec<(String, String)>;
+
+    /// Properties of the audio, for the formats that store them next to the tags
+    fn properties(&self) -> Properties {
+        Properties::default()
+    }
+}
+
+/// Properties of the audio stream of a file, unknown ones left out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Properties {
+    pub duration_ms: Option<u64>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    /// Average bits per second
+    pub bitrate: Option<u32>,
+}
+
+/// A piece of metadata that can be looked up in a tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Year,
+    Track,
+    TrackTotal,
+    Disc,
+    DiscTotal,
+    Genre,
+    Composer,
+    Comment,
+    /// A format specific field: an ID3 frame such as `TPE3`, a Vorbis comment such as
+    /// `MUSICBRAINZ_ALBUMID`, a RIFF INFO or AIFF chunk or an MP4 freeform atom.
+    ///
+    /// The description narrows down ID3 `TXXX`, `WXXX` and `COMM` frames; other formats
+    /// use it as the key, so `TXXX.REPLAYGAIN_TRACK_GAIN` works everywhere.
+    Raw {
+        key: String,
+        description: Option<String>,
+    },
+}
+
+impl Field {
+    /// The well-known fields, in the order they are usually listed in
+    pub const STANDARD: &'static [Field] = &[
+        Field::Title,
+        Field::Artist,
+        Field::Album,
+        Field::AlbumArtist,
+        Field::Year,
+        Field::Track,
+        Field::TrackTotal,
+        Field::Disc,
+        Field::DiscTotal,
+        Field::Genre,
+        Field::Composer,
+        Field::Comment,
+    ];
+
+    /// Parses a field name as it appears in a template.
+    ///
+    /// Well-known fields are matched case-insensitively, anything else has to be an upper
+    /// case key of at least four characters such as `TPE3` or `TXXX.MusicBrainz Album Id`.
+    pub fn parse(name: &str) -> Option<Field> {
+        let field = match name.to_ascii_lowercase().as_str() {
+            "title" => Field::Title,
+            "artist" => Field::Artist,
+            "album" => Field::Album,
+            "albumartist" => Field::AlbumArtist,
+            "year" => Field::Year,
+            "track" => Field::Track,
+            "tracktotal" => Field::TrackTotal,
+            "disc" => Field::Disc,
+            "disctotal" => Field::DiscTotal,
+            "genre" => Field::Genre,
+            "composer" => Field::Composer,
+            "comment" => Field::Comment,
+            _ => {
+                let (key, description) = match name.split_once('.') {
+                    Some((key, description)) => (key, Some(description.to_string())),
+                    None => (name, None),
+                };
+
+                if !is_raw_key(key) {
+                    return None;
+                }
+
+                Field::Raw {
+                    key: key.to_string(),
+                    description,
+                }
+            }
+        };
+
+        Some(field)
+    }
+
+    /// The key to look up in formats without ID3 style descriptions
+    pub fn raw_key(&self) -> Option<&str> {
+        match self {
+            Field::Raw {
+                description: Some(description),
+                ..
+            } => Some(description),
+            Field::Raw { key, .. } => Some(key),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Field::Title => "title",
+            Field::Artist => "artist",
+            Field::Album => "album",
+            Field::AlbumArtist => "albumartist",
+            Field::Year => "year",
+            Field::Track => "track",
+            Field::TrackTotal => "tracktotal",
+            Field::Disc => "disc",
+            Field::DiscTotal => "disctotal",
+            Field::Genre => "genre",
+            Field::Composer => "composer",
+            Field::Comment => "comment",
+            Field::Raw {
+                key,
+                description: Some(description),
+            } => return write!(f, "{}.{}", key, description),
+            Field::Raw { key, .. } => key,
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Raw keys are upper case letters, digits and underscores starting with a letter (e.g. `TPE1`)
+fn is_raw_key(key: &str) -> bool {
+    key.len() >= 4
+        && key.starts_with(|c: char| c.is_ascii_uppercase())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Tells albums apart by their artist and title
+pub fn album(metadata: &dyn Metadata) -> Option<(String, String)> {
+    let artist = metadata
+        .lookup(&Field::AlbumArtist)
+        .or_else(|| metadata.lookup(&Field::Artist))
+        .unwrap_or_default();
+
+    Some((artist, metadata.lookup(&Field::Album)?))
+}
+
+/// Reads the metadata of `path` with the backend matching its contents
+pub fn read(path: &Path) -> Result<Box<dyn Metadata>, FmmdError> {
+    let metadata: Box<dyn Metadata> = match detect(path)? {
+        Format::Mp3 => Box::new(mpeg::read(path)?),
+        Format::Flac => Box::new(vorbis::read_flac(path)?),
+        format @ (Format::OggVorbis | Format::Opus) => Box::new(vorbis::read_ogg(path, format)?),
+        Format::Mp4 => Box::new(mp4::read(path)?),
+        Format::Wav => Box::new(iff::read_wav(path)?),
+        Format::Aiff => Box::new(iff::read_aiff(path)?),
+    };
+
+    Ok(metadata)
+}
+
+/// The byte ranges of a file that hold its audio, leaving out its tags and everything
+/// else that changes when they are written
+pub fn audio_ranges(path: &Path) -> Result<Vec<Range<u64>>, FmmdError> {
+    let ranges = match detect(path)? {
+        Format::Mp3 => vec![mpeg::audio_range(path)?],
+        Format::Flac => vec![vorbis::flac_audio_range(path)?],
+        Format::OggVorbis | Format::Opus => vorbis::ogg_audio_ranges(path)?,
+        Format::Mp4 => mp4::audio_ranges(path)?,
+        format @ (Format::Wav | Format::Aiff) => vec![iff::audio_range(path, format)?],
+    };
+
+    Ok(ranges)
+}
+
+/// Works out the format of a file from its magic bytes
+pub fn detect(path: &Path) -> Result<Format, FmmdError> {
+    let mut head = Vec::with_capacity(512);
+    File::open(path)?.take(512).read_to_end(&mut head)?;
+
+    let format = match head.as_slice() {
+        [b'I', b'D', b'3', ..] => Format::Mp3,
+        // MPEG audio frame sync; layer bits of `00` would be AAC in an ADTS stream instead
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 && second & 0x06 != 0 => Format::Mp3,
+        [b'f', b'L', b'a', b'C', ..] => Format::Flac,
+        [b'O', b'g', b'g', b'S', ..] => {
+            vorbis::detect_ogg(&head).ok_or(FmmdError::UnsupportedFormat)?
+        }
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Format::Mp4,
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Format::Wav,
+        [b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', b'F' | b'C', ..] => Format::Aiff,
+        _ => return Err(FmmdError::UnsupportedFormat),
+    };
+
+    Ok(format)
+}
+
+/// Describes embedded cover art without including the image itself
+fn picture_summary(kind: &str, mime_type: &str, size: usize) -> String {
+    format!("{}, {}, {} bytes", kind, mime_type, size)
+}
+
+/// Splits numbers like `3/12` into the number and the total
+fn number_pair(value: &str) -> (Option<u32>, Option<u32>) {
+    let (number, total) = match value.split_once('/') {
+        Some((number, total)) => (number, Some(total)),
+        None => (value, None),
+    };
+
+    (
+        number.trim().parse().ok(),
+        total.and_then(|total| total.trim().parse().ok()),
+    )
+}
+
+/// Picks the year out of dates such as `2003-04-01`
+fn year(date: &str) -> Option<String> {
+    let year: String = date
+        .trim()
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+
+    (year.len() == 4).then_some(year)
+}
+
+/// Looks up standard fields in formats that store everything as text under a fixed key.
+///
+/// `keys` maps each field to the keys it can be stored under, in order of preference.
+fn lookup_text(
+    field: &Field,
+    keys: fn(&Field) -> &'static [&'static str],
+    get: impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+    let first = |keys: &[&str]| keys.iter().find_map(|key| get(key));
+
+    let value = match field {
+        Field::Raw { .. } => field.raw_key().and_then(&get),
+        Field::Year => first(keys(field)).and_then(|date| year(&date)),
+        Field::Track | Field::Disc => first(keys(field))
+            .and_then(|value| number_pair(&value).0)
+            .map(|number| number.to_string()),
+        Field::TrackTotal | Field::DiscTotal => {
+            let number = match field {
+                Field::TrackTotal => Field::Track,
+                _ => Field::Disc,
+            };
+
+            first(keys(field))
+                .and_then(|total| total.trim().parse::<u32>().ok())
+                .or_else(|| first(keys(&number)).and_then(|value| number_pair(&value).1))
+                .map(|total| total.to_string())
+        }
+        _ => first(keys(field)),
+    };
+
+    value.filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Writes `head` to a file of its own and detects its format
+    fn detect_head(name: &str, head: &[u8]) -> Result<Format, FmmdError> {
+        let path =
+            std::env::temp_dir().join(format!("fmmd-detect-{}-{}", std::process::id(), name));
+        fs::write(&path, head).unwrap();
+        let format = detect(&path);
+        fs::remove_file(&path).unwrap();
+        format
+    }
+
+    #[test]
+    fn formats_are_told_by_their_first_bytes() {
+        let ogg = |packet: &[u8]| {
+            let mut page = b"OggS".to_vec();
+            page.resize(26, 0);
+            page.extend([1, packet.len() as u8]);
+            page.extend(packet);
+            page
+        };
+
+        let heads: &[(&str, Vec<u8>, Format)] = &[
+            ("id3", b"ID3\x04\0\0".to_vec(), Format::Mp3),
+            ("mpeg", vec![0xFF, 0xFB, 0x90, 0x00], Format::Mp3),
+            ("flac", b"fLaC\0\0\0\x22".to_vec(), Format::Flac),
+            ("vorbis", ogg(b"\x01vorbis"), Format::OggVorbis),
+            ("opus", ogg(b"OpusHead"), Format::Opus),
+            ("mp4", b"\0\0\0\x20ftypM4A ".to_vec(), Format::Mp4),
+            ("wav", b"RIFF\0\0\0\0WAVEfmt ".to_vec(), Format::Wav),
+            ("aiff", b"FORM\0\0\0\0AIFFCOMM".to_vec(), Format::Aiff),
+            ("aifc", b"FORM\0\0\0\0AIFCFVER".to_vec(), Format::Aiff),
+        ];
+
+        for (name, head, format) in heads {
+            assert_eq!(detect_head(name, head).unwrap(), *format, "{}", name);
+        }
+    }
+
+    #[test]
+    fn other_files_are_unsupported() {
+        // ADTS AAC has the MPEG frame sync but a layer of `00`
+        let heads: &[(&str, &[u8])] = &[
+            ("adts", &[0xFF, 0xF1, 0x50, 0x80]),
+            ("text", b"not a fLaC file"),
+            ("empty", b""),
+            ("riff", b"RIFF\0\0\0\0AVI LIST"),
+            ("ogg", b"OggS"),
+        ];
+
+        for (name, head) in heads {
+            assert!(
+                matches!(detect_head(name, head), Err(FmmdError::UnsupportedFormat)),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn numbers_and_years_are_picked_out_of_text() {
+        assert_eq!(number_pair("3/12"), (Some(3), Some(12)));
+        assert_eq!(number_pair(" 3 "), (Some(3), None));
+        assert_eq!(number_pair("x/12"), (None, Some(12)));
+
+        assert_eq!(year("2003-04-01").as_deref(), Some("2003"));
+        assert_eq!(year("2003").as_deref(), Some("2003"));
+        assert_eq!(year("03"), None);
+    }
+}
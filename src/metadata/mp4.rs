@@ -0,0 +1,149 @@
+//! MP4/M4A metadata atoms
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+use mp4ameta::{ChannelConfig, Data, DataIdent, Tag};
+
+use super::{picture_summary, year, Field, Format, Metadata, MetadataError, Properties};
+use crate::template::Lookup;
+
+pub fn read(path: &Path) -> Result<Tag, MetadataError> {
+    Ok(Tag::read_from_path(path)?)
+}
+
+/// The bodies of the top level `mdat` atoms, which hold the audio
+pub fn audio_ranges(path: &Path) -> Result<Vec<Range<u64>>, MetadataError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let file_size = reader.get_ref().metadata()?.len();
+    let mut ranges = Vec::new();
+    let mut position = 0;
+
+    while position + 8 <= file_size {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+
+        let mut header_size = 8;
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // The atom runs to the end of the file
+            0 => file_size - position,
+            // The size follows as a 64 bit number
+            1 => {
+                let mut size = [0; 8];
+                reader.read_exact(&mut size)?;
+                header_size = 16;
+                u64::from_be_bytes(size)
+            }
+            size => size as u64,
+        };
+
+        if size < header_size {
+            return Err(MetadataError::Malformed("invalid atom size"));
+        }
+
+        let end = (position + size).min(file_size);
+        if &header[4..] == b"mdat" {
+            ranges.push(position + header_size..end);
+        }
+
+        position = reader.seek(SeekFrom::Start(end))?;
+    }
+
+    Ok(ranges)
+}
+
+impl Metadata for Tag {
+    fn format(&self) -> Format {
+        Format::Mp4
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        self.data()
+            .map(|(ident, data)| {
+                let key = match ident {
+                    DataIdent::Fourcc(fourcc) => fourcc.to_string(),
+                    DataIdent::Freeform { name, .. } => name.to_string(),
+                };
+
+                let value = match data {
+                    Data::Utf8(text) | Data::Utf16(text) => text.clone(),
+                    Data::Jpeg(image) => picture_summary("Cover", "image/jpeg", image.len()),
+                    Data::Png(image) => picture_summary("Cover", "image/png", image.len()),
+                    Data::Bmp(image) => picture_summary("Cover", "image/bmp", image.len()),
+                    // Track and disc numbers are stored as `0, number, total, 0` in 16 bits each
+                    Data::Reserved(bytes) if key == "trkn" || key == "disk" => {
+                        let number = |index: usize| {
+                            let bytes = bytes.get(index * 2..index * 2 + 2)?;
+                            Some(u16::from_be_bytes(bytes.try_into().ok()?))
+                        };
+
+                        match (number(1), number(2)) {
+                            (Some(number), Some(total)) if total > 0 => {
+                                format!("{}/{}", number, total)
+                            }
+                            (Some(number), _) => number.to_string(),
+                            _ => format!("{} bytes", bytes.len()),
+                        }
+                    }
+                    Data::Reserved(bytes) | Data::BeSigned(bytes) if bytes.len() <= 8 => bytes
+                        .iter()
+                        .fold(0i64, |value, &byte| value << 8 | byte as i64)
+                        .to_string(),
+                    data => format!("{} bytes", data.bytes().map_or(0, <[u8]>::len)),
+                };
+
+                (key, value)
+            })
+            .collect()
+    }
+
+    fn properties(&self) -> Properties {
+        let duration_ms = self.duration().as_millis() as u64;
+        let channels = self.channel_config().map(|config| match config {
+            ChannelConfig::FiveOne => 6,
+            ChannelConfig::SevenOne => 8,
+            config => config as u32,
+        });
+
+        Properties {
+            duration_ms: (duration_ms > 0).then_some(duration_ms),
+            sample_rate: self.sample_rate().map(|rate| rate.hz()),
+            channels,
+            bitrate: self.avg_bitrate(),
+        }
+    }
+}
+
+impl Lookup for Tag {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        let value = match field {
+            Field::Title => self.title().map(String::from),
+            Field::Artist => self.artist().map(String::from),
+            Field::Album => self.album().map(String::from),
+            Field::AlbumArtist => self.album_artist().map(String::from),
+            Field::Year => self.year().and_then(year),
+            Field::Track => self.track_number().map(|track| track.to_string()),
+            Field::TrackTotal => self.total_tracks().map(|total| total.to_string()),
+            Field::Disc => self.disc_number().map(|disc| disc.to_string()),
+            Field::DiscTotal => self.total_discs().map(|total| total.to_string()),
+            Field::Genre => self.genre().map(String::from),
+            Field::Composer => self.composer().map(String::from),
+            Field::Comment => self.comment().map(String::from),
+            Field::Raw { .. } => {
+                let key = field.raw_key()?;
+
+                // Matches both plain atoms (`©wrk`) and freeform ones (`----:com.apple.iTunes:KEY`)
+                self.data()
+                    .find(|(ident, _)| match ident {
+                        DataIdent::Fourcc(fourcc) => fourcc.to_string() == key,
+                        DataIdent::Freeform { name, .. } => name.eq_ignore_ascii_case(key),
+                    })
+                    .and_then(|(_, data)| data.string())
+                    .map(String::from)
+            }
+        };
+
+        value.filter(|value| !value.is_empty())
+    }
+}
@@ -0,0 +1,245 @@
+//! MP3 files: an ID3 tag along with the properties of the MPEG audio frames
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+use id3::Tag;
+
+use super::{id3tag, Field, Format, Metadata, MetadataError, Properties};
+use crate::template::Lookup;
+
+/// How far past the ID3v2 tag the first frame is looked for
+const SYNC_WINDOW: usize = 64 * 1024;
+
+/// Bit rates in kbit/s by version, layer and index
+const BITRATES: [[[u32; 15]; 3]; 2] = [
+    // MPEG 1, layers I, II and III
+    [
+        [
+            0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+        ],
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+        ],
+        [
+            0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+        ],
+    ],
+    // MPEG 2 and 2.5
+    [
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+        ],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ],
+];
+
+pub struct Mp3 {
+    tag: Tag,
+    properties: Properties,
+}
+
+pub fn read(path: &Path) -> Result<Mp3, MetadataError> {
+    let tag = id3tag::read(path)?;
+    // Files whose frames can't be made sense of still have a tag worth reading
+    let properties = properties(path).unwrap_or_default();
+
+    Ok(Mp3 { tag, properties })
+}
+
+/// The part of an MP3 file between the ID3v2 tags at its start and the APE and ID3v1
+/// tags at its end
+pub fn audio_range(path: &Path) -> Result<Range<u64>, MetadataError> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+
+    let mut start = 0;
+    loop {
+        let mut header = [0; 10];
+        file.seek(SeekFrom::Start(start))?;
+        if file.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+            break;
+        }
+
+        // The size is stored in 7 bits per byte and leaves out the header and footer
+        let size = header[6..]
+            .iter()
+            .fold(0, |size, &byte| (size << 7) | (byte & 0x7F) as u64);
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        start += 10 + size + footer;
+    }
+
+    let mut end = file_size;
+    let mut tail = |offset: u64, buffer: &mut [u8]| -> Result<bool, MetadataError> {
+        if offset < start + buffer.len() as u64 {
+            return Ok(false);
+        }
+        file.seek(SeekFrom::Start(offset - buffer.len() as u64))?;
+        file.read_exact(buffer)?;
+        Ok(true)
+    };
+
+    let mut id3v1 = [0; 3 + 125];
+    if tail(end, &mut id3v1)? && id3v1.starts_with(b"TAG") {
+        end -= 128;
+    }
+
+    // The APEv2 footer holds the size of the tag without its header, if it has one
+    let mut ape = [0; 32];
+    if tail(end, &mut ape)? && ape.starts_with(b"APETAGEX") {
+        let size = u32::from_le_bytes(ape[12..16].try_into().unwrap()) as u64;
+        let flags = u32::from_le_bytes(ape[20..24].try_into().unwrap());
+        let header = if flags & (1 << 31) != 0 { 32 } else { 0 };
+        end = end.saturating_sub(size + header);
+    }
+
+    Ok(start.min(end)..end)
+}
+
+/// Reads the properties from the first frame, using the frame count of a Xing or VBRI
+/// header for variable bit rate files and the bit rate of the frame for the rest
+fn properties(path: &Path) -> Result<Properties, MetadataError> {
+    let range = audio_range(path)?;
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(range.start))?;
+
+    let mut head = Vec::with_capacity(SYNC_WINDOW);
+    file.take(SYNC_WINDOW as u64).read_to_end(&mut head)?;
+
+    let (offset, frame) = (0..head.len().saturating_sub(4))
+        .find_map(|offset| Some((offset, Frame::parse(&head[offset..])?)))
+        .ok_or(MetadataError::Malformed("no MPEG audio frame"))?;
+
+    let audio_size = range.end - (range.start + offset as u64);
+    let first = &head[offset..];
+    let frames = frame_count(first, &frame);
+
+    let (duration_ms, bitrate) = match frames {
+        Some(frames) => {
+            let duration = frames * frame.samples as u64 * 1000 / frame.sample_rate as u64;
+            let bitrate = (duration > 0).then(|| (audio_size * 8000 / duration) as u32);
+            (duration, bitrate)
+        }
+        None => {
+            let bitrate = frame.bitrate * 1000;
+            (audio_size * 8000 / bitrate as u64, Some(bitrate))
+        }
+    };
+
+    Ok(Properties {
+        duration_ms: Some(duration_ms),
+        sample_rate: Some(frame.sample_rate),
+        channels: Some(frame.channels),
+        bitrate,
+    })
+}
+
+/// The number of frames stored in the Xing (or `Info`) or VBRI header of the first frame
+fn frame_count(first: &[u8], frame: &Frame) -> Option<u64> {
+    let at = |start: usize, length: usize| first.get(start..start + length);
+    let number = |start: usize| Some(u32::from_be_bytes(at(start, 4)?.try_into().ok()?) as u64);
+
+    // The Xing header follows the side information, VBRI always comes 32 bytes in
+    let xing = 4 + frame.side_info;
+    if matches!(at(xing, 4), Some(b"Xing" | b"Info")) && number(xing + 4)? & 1 != 0 {
+        return number(xing + 8).filter(|&frames| frames > 0);
+    }
+
+    if at(36, 4) == Some(b"VBRI") {
+        return number(36 + 14).filter(|&frames| frames > 0);
+    }
+
+    None
+}
+
+/// The header of an MPEG audio frame
+struct Frame {
+    /// Kbit/s
+    bitrate: u32,
+    sample_rate: u32,
+    channels: u32,
+    /// Samples per channel
+    samples: u32,
+    /// Size of the side information following the header in layer III frames
+    side_info: usize,
+}
+
+impl Frame {
+    fn parse(header: &[u8]) -> Option<Frame> {
+        let [0xFF, second, third, fourth, ..] = *header else {
+            return None;
+        };
+        if second & 0xE0 != 0xE0 {
+            return None;
+        }
+
+        // 0 is MPEG 2.5, 2 is MPEG 2 and 3 is MPEG 1
+        let version = (second >> 3) & 0x03;
+        let layer = match (second >> 1) & 0x03 {
+            3 => 1,
+            2 => 2,
+            1 => 3,
+            _ => return None,
+        };
+        if version == 1 {
+            return None;
+        }
+        let mpeg1 = version == 3;
+
+        let bitrate_index = (third >> 4) as usize;
+        let rate_index = ((third >> 2) & 0x03) as usize;
+        if bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+            return None;
+        }
+
+        let bitrate = BITRATES[!mpeg1 as usize][layer - 1][bitrate_index];
+        let sample_rate = [44100, 48000, 32000][rate_index]
+            >> match version {
+                3 => 0,
+                2 => 1,
+                _ => 2,
+            };
+        let mono = fourth >> 6 == 3;
+
+        let samples = match (layer, mpeg1) {
+            (1, _) => 384,
+            (2, _) | (3, true) => 1152,
+            _ => 576,
+        };
+        let side_info = match (mpeg1, mono) {
+            (true, false) => 32,
+            (true, true) | (false, false) => 17,
+            (false, true) => 9,
+        };
+
+        Some(Frame {
+            bitrate,
+            sample_rate,
+            channels: if mono { 1 } else { 2 },
+            samples,
+            side_info,
+        })
+    }
+}
+
+impl Metadata for Mp3 {
+    fn format(&self) -> Format {
+        Format::Mp3
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        self.tag.entries()
+    }
+
+    fn properties(&self) -> Properties {
+        self.properties
+    }
+}
+
+impl Lookup for Mp3 {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        self.tag.lookup(field)
+    }
+}
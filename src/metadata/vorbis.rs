@@ -0,0 +1,541 @@
+//! Vorbis comments, used by FLAC, Ogg Vorbis and Opus files
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+use metaflac::block::{Block, BlockType};
+
+use super::{lookup_text, picture_summary, Field, Format, Metadata, MetadataError, Properties};
+use crate::plan::temporary_name;
+use crate::template::Lookup;
+
+/// Comment packets larger than this are treated as corrupt rather than read into memory
+const MAX_PACKET_SIZE: usize = 64 * 1024 * 1024;
+
+/// How far from the end the last Ogg page is looked for, twice the largest page size
+const LAST_PAGE_WINDOW: u64 = 2 * 65307;
+
+pub struct VorbisComments {
+    format: Format,
+    /// Comments in file order, with upper case keys
+    comments: Vec<(String, String)>,
+    /// Summaries of the cover art stored in FLAC picture blocks
+    pictures: Vec<String>,
+    /// Read from the FLAC stream info block
+    properties: Properties,
+}
+
+pub fn read_flac(path: &Path) -> Result<VorbisComments, MetadataError> {
+    let tag = metaflac::Tag::read_from_path(path)?;
+
+    let mut comments = Vec::new();
+    if let Some(vorbis_comments) = tag.vorbis_comments() {
+        for (key, values) in &vorbis_comments.comments {
+            for value in values {
+                comments.push((key.to_ascii_uppercase(), value.clone()));
+            }
+        }
+    }
+
+    let pictures = tag
+        .pictures()
+        .map(|picture| {
+            let kind = format!("{:?}", picture.picture_type);
+            picture_summary(&kind, &picture.mime_type, picture.data.len())
+        })
+        .collect();
+
+    let properties = match tag.get_streaminfo() {
+        Some(info) => {
+            let duration_ms = (info.sample_rate > 0)
+                .then(|| info.total_samples * 1000 / info.sample_rate as u64)
+                .filter(|_| info.total_samples > 0);
+            let file_size = std::fs::metadata(path)?.len();
+
+            Properties {
+                duration_ms,
+                sample_rate: Some(info.sample_rate),
+                channels: Some(info.num_channels as u32),
+                bitrate: duration_ms
+                    .filter(|&duration| duration > 0)
+                    .map(|duration| (file_size * 8000 / duration) as u32),
+            }
+        }
+        None => Properties::default(),
+    };
+
+    Ok(VorbisComments {
+        format: Format::Flac,
+        comments,
+        pictures,
+        properties,
+    })
+}
+
+pub fn read_ogg(path: &Path, format: Format) -> Result<VorbisComments, MetadataError> {
+    let packet = read_comment_packet(path)?;
+
+    let magic: &[u8] = match format {
+        Format::Opus => b"OpusTags",
+        _ => b"\x03vorbis",
+    };
+
+    let body = packet
+        .strip_prefix(magic)
+        .ok_or(MetadataError::Malformed("missing comment header"))?;
+
+    Ok(VorbisComments {
+        format,
+        comments: parse_comments(body)?,
+        pictures: Vec::new(),
+        // Files whose pages can't be made sense of still have comments worth reading
+        properties: ogg_properties(path, format).unwrap_or_default(),
+    })
+}
+
+/// Reads the sample rate and channels from the identification header and the duration
+/// from the granule position of the last page, the number of samples up to its end
+fn ogg_properties(path: &Path, format: Format) -> Result<Properties, MetadataError> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+
+    let mut head = Vec::with_capacity(512);
+    file.by_ref().take(512).read_to_end(&mut head)?;
+    let serial = head
+        .get(14..18)
+        .ok_or(MetadataError::Malformed("invalid Ogg page"))?;
+    let segments = *head
+        .get(26)
+        .ok_or(MetadataError::Malformed("invalid Ogg page"))? as usize;
+    let packet = head
+        .get(27 + segments..)
+        .ok_or(MetadataError::Malformed("invalid Ogg page"))?;
+
+    let byte = |at: usize| packet.get(at).copied();
+    let number = |at: usize, length: usize| {
+        let bytes = packet.get(at..at + length)?;
+        Some(
+            bytes
+                .iter()
+                .rev()
+                .fold(0, |number, &byte| (number << 8) | byte as u64),
+        )
+    };
+    let truncated = || MetadataError::Malformed("truncated identification header");
+
+    // Opus always counts samples at 48 kHz, after skipping the encoder delay
+    let (sample_rate, channels, pre_skip) = match format {
+        Format::Opus => (
+            48000,
+            byte(9).ok_or_else(truncated)?,
+            number(10, 2).ok_or_else(truncated)?,
+        ),
+        _ => (
+            number(12, 4).ok_or_else(truncated)? as u32,
+            byte(11).ok_or_else(truncated)?,
+            0,
+        ),
+    };
+
+    let start = file_size.saturating_sub(LAST_PAGE_WINDOW);
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(start))?;
+    file.read_to_end(&mut tail)?;
+
+    // The last page of the first stream with a granule position; -1 means there is none
+    let granule = (0..tail.len().saturating_sub(27))
+        .rev()
+        .filter(|&at| &tail[at..at + 4] == b"OggS" && tail[at + 14..at + 18] == *serial)
+        .map(|at| i64::from_le_bytes(tail[at + 6..at + 14].try_into().unwrap()))
+        .find(|&granule| granule >= 0);
+
+    let duration_ms = granule
+        .filter(|_| sample_rate > 0)
+        .map(|granule| (granule as u64).saturating_sub(pre_skip) * 1000 / sample_rate as u64)
+        .filter(|&duration| duration > 0);
+
+    Ok(Properties {
+        duration_ms,
+        sample_rate: Some(sample_rate).filter(|&rate| rate > 0),
+        channels: Some(channels as u32),
+        bitrate: duration_ms.map(|duration| (file_size * 8000 / duration) as u32),
+    })
+}
+
+/// The frames of a FLAC file, which follow its metadata blocks
+pub fn flac_audio_range(path: &Path) -> Result<Range<u64>, MetadataError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let file_size = reader.get_ref().metadata()?.len();
+
+    // Skip the `fLaC` marker, then each block until the one flagged as the last
+    reader.seek(SeekFrom::Start(4))?;
+    let position = loop {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        let position = reader.seek(SeekFrom::Current(size as i64))?;
+
+        if header[0] & 0x80 != 0 {
+            break position;
+        }
+    };
+
+    Ok(position.min(file_size)..file_size)
+}
+
+/// Writes the metadata blocks and the audio of a FLAC file anew. metaflac would keep the
+/// space of removed or shrunk blocks as padding, leaving the file as large as before, and
+/// rewrites the file in place when the blocks grow.
+///
+/// The new file is written next to the old one and only replaces it once it is complete,
+/// so that a failed write never costs the audio.
+pub fn write_flac(tag: &mut metaflac::Tag, path: &Path) -> Result<(), MetadataError> {
+    let audio = flac_audio_range(path)?;
+
+    tag.remove_blocks(BlockType::Padding);
+    tag.push_block(Block::Padding(1024));
+
+    let temporary = temporary_name(path);
+    let result = (|| -> Result<(), MetadataError> {
+        let mut output = File::create(&temporary)?;
+        tag.write_to(&mut output)?;
+
+        let mut input = File::open(path)?;
+        input.seek(SeekFrom::Start(audio.start))?;
+        let length = audio.end - audio.start;
+        if io::copy(&mut input.take(length), &mut output)? != length {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        output.sync_all()?;
+        fs::set_permissions(&temporary, fs::metadata(path)?.permissions())?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// The parts of an Ogg file holding the packets of its streams, leaving out the page
+/// headers and the comment header, which change along with the tags
+pub fn ogg_audio_ranges(path: &Path) -> Result<Vec<Range<u64>>, MetadataError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let file_size = reader.get_ref().metadata()?.len();
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    let mut serial = None;
+    let mut packets = 0;
+    let mut position = 0;
+
+    while position < file_size {
+        let mut header = [0; 27];
+        reader.read_exact(&mut header)?;
+
+        if &header[..4] != b"OggS" {
+            return Err(MetadataError::Malformed("invalid Ogg page"));
+        }
+
+        let page_serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let first_stream = *serial.get_or_insert(page_serial) == page_serial;
+        let mut lacing = vec![0; header[26] as usize];
+        reader.read_exact(&mut lacing)?;
+
+        let mut start = position + 27 + lacing.len() as u64;
+        for size in lacing {
+            let end = start + size as u64;
+            let comments = first_stream && packets == 1;
+
+            if !comments && size > 0 {
+                match ranges.last_mut() {
+                    Some(last) if last.end == start => last.end = end,
+                    _ => ranges.push(start..end),
+                }
+            }
+
+            // A segment shorter than 255 bytes ends the packet
+            if first_stream && size < 255 {
+                packets += 1;
+            }
+            start = end;
+        }
+
+        position = reader.seek(SeekFrom::Start(start))?;
+    }
+
+    Ok(ranges)
+}
+
+/// Tells Ogg Vorbis and Opus apart by the identification header in the first page
+pub fn detect_ogg(head: &[u8]) -> Option<Format> {
+    let segments = *head.get(26)? as usize;
+    let packet = head.get(27 + segments..)?;
+
+    if packet.starts_with(b"\x01vorbis") {
+        Some(Format::OggVorbis)
+    } else if packet.starts_with(b"OpusHead") {
+        Some(Format::Opus)
+    } else {
+        None
+    }
+}
+
+/// Reads Ogg pages until the second packet of the first logical stream, the comment header
+fn read_comment_packet(path: &Path) -> Result<Vec<u8>, MetadataError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut serial = None;
+    let mut packets = 0;
+    let mut packet = Vec::new();
+
+    loop {
+        let mut header = [0; 27];
+        reader.read_exact(&mut header)?;
+
+        if &header[..4] != b"OggS" {
+            return Err(MetadataError::Malformed("invalid Ogg page"));
+        }
+
+        let page_serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let mut lacing = vec![0; header[26] as usize];
+        reader.read_exact(&mut lacing)?;
+
+        if *serial.get_or_insert(page_serial) != page_serial {
+            let size: u64 = lacing.iter().map(|&size| size as u64).sum();
+            reader.seek(SeekFrom::Current(size as i64))?;
+            continue;
+        }
+
+        for size in lacing {
+            let start = packet.len();
+            packet.resize(start + size as usize, 0);
+            reader.read_exact(&mut packet[start..])?;
+
+            if packet.len() > MAX_PACKET_SIZE {
+                return Err(MetadataError::Malformed("Ogg packet is too large"));
+            }
+
+            // A segment shorter than 255 bytes ends the packet
+            if size < 255 {
+                packets += 1;
+                if packets == 2 {
+                    return Ok(packet);
+                }
+                packet.clear();
+            }
+        }
+    }
+}
+
+/// Parses the body of a comment header: a vendor string followed by `KEY=value` pairs
+fn parse_comments(body: &[u8]) -> Result<Vec<(String, String)>, MetadataError> {
+    let mut rest = body;
+
+    let mut take = |length: usize| -> Result<&[u8], MetadataError> {
+        if rest.len() < length {
+            return Err(MetadataError::Malformed("truncated Vorbis comment"));
+        }
+        let (bytes, remaining) = rest.split_at(length);
+        rest = remaining;
+        Ok(bytes)
+    };
+
+    fn length(bytes: &[u8]) -> usize {
+        u32::from_le_bytes(bytes.try_into().unwrap()) as usize
+    }
+
+    let vendor_length = length(take(4)?);
+    take(vendor_length)?;
+
+    let count = length(take(4)?);
+    let mut comments = Vec::new();
+
+    for _ in 0..count {
+        let comment_length = length(take(4)?);
+        let comment = String::from_utf8_lossy(take(comment_length)?);
+
+        if let Some((key, value)) = comment.split_once('=') {
+            comments.push((key.to_ascii_uppercase(), value.to_string()));
+        }
+    }
+
+    Ok(comments)
+}
+
+/// The keys a field is stored under, the one written first
+pub fn comment_keys(field: &Field) -> &'static [&'static str] {
+    match field {
+        Field::Title => &["TITLE"],
+        Field::Artist => &["ARTIST"],
+        Field::Album => &["ALBUM"],
+        Field::AlbumArtist => &["ALBUMARTIST", "ALBUM ARTIST"],
+        Field::Year => &["DATE", "YEAR"],
+        Field::Track => &["TRACKNUMBER"],
+        Field::TrackTotal => &["TRACKTOTAL", "TOTALTRACKS"],
+        Field::Disc => &["DISCNUMBER"],
+        Field::DiscTotal => &["DISCTOTAL", "TOTALDISCS"],
+        Field::Genre => &["GENRE"],
+        Field::Composer => &["COMPOSER"],
+        Field::Comment => &["COMMENT", "DESCRIPTION"],
+        Field::Raw { .. } => &[],
+    }
+}
+
+impl Metadata for VorbisComments {
+    fn format(&self) -> Format {
+        self.format
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        let comments = self.comments.iter().map(|(key, value)| match key.as_str() {
+            // Ogg files embed cover art as a base64 encoded comment
+            "METADATA_BLOCK_PICTURE" => (key.clone(), format!("{} bytes of base64", value.len())),
+            _ => (key.clone(), value.clone()),
+        });
+        let pictures = self
+            .pictures
+            .iter()
+            .map(|picture| ("PICTURE".to_string(), picture.clone()));
+
+        comments.chain(pictures).collect()
+    }
+
+    fn properties(&self) -> Properties {
+        self.properties
+    }
+}
+
+impl Lookup for VorbisComments {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        lookup_text(field, comment_keys, |key| {
+            self.comments
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.clone())
+        })
+    }
+}
+
+/// The tag of a FLAC file as it is written back, see [`crate::tag`]
+impl Lookup for metaflac::Tag {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        let comments = &self.vorbis_comments()?.comments;
+        lookup_text(field, comment_keys, |key| {
+            comments
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .and_then(|(_, values)| values.first().cloned())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A comment header body with the given `KEY=value` comments
+    fn body(comments: &[&str]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend(6u32.to_le_bytes());
+        body.extend(b"vendor");
+        body.extend((comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            body.extend((comment.len() as u32).to_le_bytes());
+            body.extend(comment.as_bytes());
+        }
+        body
+    }
+
+    fn comments(comments: &[&str]) -> VorbisComments {
+        VorbisComments {
+            format: Format::OggVorbis,
+            comments: parse_comments(&body(comments)).unwrap(),
+            pictures: Vec::new(),
+            properties: Properties::default(),
+        }
+    }
+
+    #[test]
+    fn comments_are_split_at_the_first_equals_sign() {
+        let parsed = parse_comments(&body(&["title=A=B", "Artist=", "no separator"])).unwrap();
+        assert_eq!(
+            parsed,
+            [
+                ("TITLE".to_string(), "A=B".to_string()),
+                ("ARTIST".to_string(), String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_comments_are_malformed() {
+        let full = body(&["TITLE=Title"]);
+        for length in [0, 3, 10, 14, full.len() - 1] {
+            assert!(matches!(
+                parse_comments(&full[..length]),
+                Err(MetadataError::Malformed(_))
+            ));
+        }
+
+        // So is a count of more comments than follow
+        let mut lying = body(&[]);
+        lying[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse_comments(&lying).is_err());
+    }
+
+    #[test]
+    fn fields_are_looked_up_under_all_their_keys() {
+        let comments = comments(&[
+            "ALBUM ARTIST=Album Artist",
+            "YEAR=2003-04-01",
+            "TRACKNUMBER=3/12",
+            "DESCRIPTION=Comment",
+            "MUSICBRAINZ_ALBUMID=id",
+            "Mood=Calm",
+        ]);
+
+        assert_eq!(
+            comments.lookup(&Field::AlbumArtist).as_deref(),
+            Some("Album Artist")
+        );
+        assert_eq!(comments.lookup(&Field::Year).as_deref(), Some("2003"));
+        assert_eq!(comments.lookup(&Field::Track).as_deref(), Some("3"));
+        assert_eq!(comments.lookup(&Field::TrackTotal).as_deref(), Some("12"));
+        assert_eq!(comments.lookup(&Field::Comment).as_deref(), Some("Comment"));
+        assert_eq!(
+            comments
+                .lookup(&Field::parse("MUSICBRAINZ_ALBUMID").unwrap())
+                .as_deref(),
+            Some("id")
+        );
+        assert_eq!(
+            comments
+                .lookup(&Field::parse("TXXX.mood").unwrap())
+                .as_deref(),
+            Some("Calm")
+        );
+        assert_eq!(comments.lookup(&Field::Disc), None);
+    }
+
+    #[test]
+    fn ogg_streams_are_told_apart_by_their_first_packet() {
+        let page = |packet: &[u8]| {
+            let mut page = b"OggS".to_vec();
+            page.resize(26, 0);
+            page.push(1);
+            page.push(packet.len() as u8);
+            page.extend(packet);
+            page
+        };
+
+        assert_eq!(
+            detect_ogg(&page(b"\x01vorbis\0\0")),
+            Some(Format::OggVorbis)
+        );
+        assert_eq!(detect_ogg(&page(b"OpusHead\x01")), Some(Format::Opus));
+        assert_eq!(detect_ogg(&page(b"\x7fFLAC")), None);
+        assert_eq!(detect_ogg(b"OggS"), None);
+    }
+}
@@ -0,0 +1,668 @@
+//! The full list of renames of a run, worked out before any file is touched so that
+//! conflicts between them can be found and resolved up front, and carried out in an
+//! order that never overwrites a file that still has to be moved.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::error::FmmdError;
+use crate::files::move_file;
+use crate::metadata::{Field, Metadata};
+
+/// What to do when a new name is already taken
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Leave the file where it is
+    #[default]
+    Skip,
+    /// Add a number to the new name, e.g. "01-Title (2).mp3"
+    Suffix,
+    /// Replace the existing file
+    Overwrite,
+    /// Abort the run without renaming anything
+    Fail,
+}
+
+#[derive(Debug)]
+pub enum ConflictKind {
+    /// A file already exists at the new name
+    Exists,
+    /// Another file of this run is renamed to the same name
+    Duplicate(PathBuf),
+}
+
+/// A new name that was taken, along with how it was resolved
+#[derive(Debug)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    /// The name the file would have gotten without the conflict
+    pub target: PathBuf,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ConflictKind::Exists => write!(f, "\"{}\" already exists", self.target.display()),
+            ConflictKind::Duplicate(other) => write!(
+                f,
+                "\"{}\" is also the new name of \"{}\"",
+                self.target.display(),
+                other.display()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Rename,
+    /// The file already has the right name
+    Unchanged,
+    /// Skipped because of a conflict
+    Skip,
+}
+
+pub struct PlannedRename {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub metadata: Box<dyn Metadata>,
+    /// Fields the new name was built from
+    pub fields: Vec<Field>,
+    pub action: Action,
+    pub conflict: Option<Conflict>,
+    /// Whether an existing file may be replaced, see [`ConflictPolicy::Overwrite`]
+    pub overwrite: bool,
+}
+
+#[derive(Default)]
+pub struct Plan {
+    pub renames: Vec<PlannedRename>,
+}
+
+impl Plan {
+    pub fn add(
+        &mut self,
+        source: PathBuf,
+        target: PathBuf,
+        fields: Vec<Field>,
+        metadata: Box<dyn Metadata>,
+    ) {
+        let action = match normalize(&source) == normalize(&target) {
+            true => Action::Unchanged,
+            false => Action::Rename,
+        };
+
+        self.renames.push(PlannedRename {
+            source,
+            target,
+            metadata,
+            fields,
+            action,
+            conflict: None,
+            overwrite: false,
+        });
+    }
+
+    /// Finds renames whose new name is taken, either by an existing file or by an earlier
+    /// rename of the same run, and applies `policy` to them.
+    ///
+    /// A file that is renamed away in the same run doesn't take up its name, which is what
+    /// allows names to be swapped around within a batch.
+    ///
+    /// Returns the number of conflicts found.
+    pub fn resolve_conflicts(&mut self, policy: ConflictPolicy) -> usize {
+        let mut claimed: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let mut conflicts = 0;
+
+        for rename in &self.renames {
+            if rename.action == Action::Unchanged {
+                claimed.insert(normalize(&rename.target), rename.source.clone());
+            }
+        }
+
+        for rename in &mut self.renames {
+            if rename.action != Action::Rename {
+                continue;
+            }
+
+            let target = normalize(&rename.target);
+            match claimed.get(&target) {
+                Some(other) => {
+                    let kind = ConflictKind::Duplicate(other.clone());
+                    rename.resolve_conflict(kind, policy, &mut claimed);
+                    conflicts += 1;
+                }
+                None => {
+                    claimed.insert(target, rename.source.clone());
+                }
+            }
+        }
+
+        // Skipping a rename keeps its file in place, which can block other renames in turn
+        loop {
+            let vacated: HashSet<PathBuf> = self
+                .renames
+                .iter()
+                .filter(|rename| rename.action == Action::Rename)
+                .map(|rename| normalize(&rename.source))
+                .collect();
+
+            let mut skipped = false;
+
+            for rename in &mut self.renames {
+                if rename.action != Action::Rename || rename.conflict.is_some() {
+                    continue;
+                }
+
+                if rename.target.exists()
+                    && !same_file(&rename.source, &rename.target)
+                    && !vacated.contains(&normalize(&rename.target))
+                {
+                    rename.resolve_conflict(ConflictKind::Exists, policy, &mut claimed);
+                    skipped |= rename.action == Action::Skip;
+                    conflicts += 1;
+                }
+            }
+
+            if !skipped {
+                break;
+            }
+        }
+
+        conflicts
+    }
+
+    /// Finds what keeps `target` from being the new name of the rename at `index`, for
+    /// names picked after conflicts were resolved
+    pub fn conflict_with(&self, index: usize, target: &Path) -> Option<Conflict> {
+        let normalized = normalize(target);
+
+        let other = self.renames.iter().enumerate().find(|(other, rename)| {
+            *other != index
+                && rename.action != Action::Skip
+                && normalize(&rename.target) == normalized
+        });
+        let vacated = self.renames.iter().any(|rename| {
+            rename.action == Action::Rename && normalize(&rename.source) == normalized
+        });
+
+        let kind = match other {
+            Some((_, other)) => ConflictKind::Duplicate(other.source.clone()),
+            None if target.exists()
+                && !same_file(&self.renames[index].source, target)
+                && !vacated =>
+            {
+                ConflictKind::Exists
+            }
+            None => return None,
+        };
+
+        Some(Conflict {
+            kind,
+            target: target.to_path_buf(),
+        })
+    }
+
+    /// Works out an order for the renames with [`Action::Rename`], see [`schedule`]
+    pub fn schedule(&self) -> Vec<Step> {
+        let moves: Vec<(usize, &Path, &Path)> = self
+            .renames
+            .iter()
+            .enumerate()
+            .filter(|(_, rename)| rename.action == Action::Rename)
+            .map(|(index, rename)| (index, rename.source.as_path(), rename.target.as_path()))
+            .collect();
+
+        schedule(&moves)
+    }
+}
+
+impl PlannedRename {
+    fn resolve_conflict(
+        &mut self,
+        kind: ConflictKind,
+        policy: ConflictPolicy,
+        claimed: &mut HashMap<PathBuf, PathBuf>,
+    ) {
+        self.conflict = Some(Conflict {
+            kind,
+            target: self.target.clone(),
+        });
+
+        match policy {
+            ConflictPolicy::Skip | ConflictPolicy::Fail => self.action = Action::Skip,
+            ConflictPolicy::Overwrite => self.overwrite = true,
+            ConflictPolicy::Suffix => {
+                self.target = free_name(&self.target, claimed);
+                claimed.insert(normalize(&self.target), self.source.clone());
+            }
+        }
+    }
+}
+
+/// A step in carrying out a list of moves, see [`schedule`]
+#[derive(Debug)]
+pub enum Step {
+    /// Moves a file out of the way to a temporary name
+    Park {
+        index: usize,
+        source: PathBuf,
+        temporary: PathBuf,
+    },
+    /// Moves a file to its target, from the temporary name if it was parked
+    Move { index: usize, source: PathBuf },
+}
+
+/// Works out an order in which `moves` (an index, source and target each) can be carried
+/// out without moving a file onto one that still has to be moved away.
+///
+/// Moves form chains (`a -> b`, `b -> c`) that are carried out from the end, and cycles
+/// (`a -> b`, `b -> a`) that are broken up by parking one of the files under a temporary
+/// name first. Steps refer to moves by the index given with them.
+pub fn schedule(moves: &[(usize, &Path, &Path)]) -> Vec<Step> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Visit {
+        Pending,
+        OnStack,
+        Done,
+    }
+
+    let by_source: HashMap<PathBuf, usize> = moves
+        .iter()
+        .enumerate()
+        .map(|(position, (_, source, _))| (normalize(source), position))
+        .collect();
+
+    // The move whose file currently sits at the target of each move
+    let blockers: HashMap<usize, usize> = moves
+        .iter()
+        .enumerate()
+        .filter_map(|(position, (_, _, target))| {
+            let blocker = *by_source.get(&normalize(target))?;
+            (blocker != position).then_some((position, blocker))
+        })
+        .collect();
+
+    let mut visits = vec![Visit::Pending; moves.len()];
+    let mut steps = Vec::new();
+
+    for start in 0..moves.len() {
+        if visits[start] != Visit::Pending {
+            continue;
+        }
+
+        // Follow the chain of blocked moves until it ends or loops back
+        let mut stack = vec![start];
+        visits[start] = Visit::OnStack;
+
+        while let Some(&blocker) = blockers.get(stack.last().unwrap()) {
+            match visits[blocker] {
+                Visit::Pending => {
+                    visits[blocker] = Visit::OnStack;
+                    stack.push(blocker);
+                }
+                Visit::OnStack => {
+                    let (index, source, _) = moves[blocker];
+                    steps.push(Step::Park {
+                        index,
+                        source: source.to_path_buf(),
+                        temporary: temporary_name(source),
+                    });
+                    break;
+                }
+                Visit::Done => break,
+            }
+        }
+
+        while let Some(position) = stack.pop() {
+            let (index, source, _) = moves[position];
+            steps.push(Step::Move {
+                index,
+                source: source.to_path_buf(),
+            });
+            visits[position] = Visit::Done;
+        }
+    }
+
+    steps
+}
+
+/// Carries out `steps`, calling `perform` with the index of each move and the path its file
+/// currently has. Parked files whose move fails are put back where they came from.
+///
+/// With `fail_fast` the remaining steps are dropped after the first error, putting back
+/// files that were parked but not moved yet.
+pub fn execute(
+    steps: Vec<Step>,
+    fail_fast: bool,
+    mut perform: impl FnMut(usize, &Path) -> Result<(), FmmdError>,
+    mut report: impl FnMut(usize, FmmdError),
+) {
+    let mut parked: HashMap<usize, (PathBuf, PathBuf)> = HashMap::new();
+
+    for step in steps {
+        let failed = match step {
+            Step::Park {
+                index,
+                source,
+                temporary,
+            } => match move_file(&source, &temporary) {
+                Ok(()) => {
+                    parked.insert(index, (source, temporary));
+                    false
+                }
+                Err(error) => {
+                    report(index, error.into());
+                    true
+                }
+            },
+            Step::Move { index, source } => {
+                let from = match parked.get(&index) {
+                    Some((_, temporary)) => temporary.as_path(),
+                    None => source.as_path(),
+                };
+
+                match perform(index, from) {
+                    Ok(()) => {
+                        parked.remove(&index);
+                        false
+                    }
+                    Err(error) => {
+                        report(index, error);
+
+                        if let Some((source, temporary)) = parked.remove(&index) {
+                            unpark(index, &source, temporary, &mut report);
+                        }
+                        true
+                    }
+                }
+            }
+        };
+
+        if failed && fail_fast {
+            break;
+        }
+    }
+
+    for (index, (source, temporary)) in parked {
+        unpark(index, &source, temporary, &mut report);
+    }
+}
+
+/// Moves a parked file back to where it came from
+fn unpark(
+    index: usize,
+    source: &Path,
+    temporary: PathBuf,
+    report: &mut impl FnMut(usize, FmmdError),
+) {
+    if source.exists() || move_file(&temporary, source).is_err() {
+        report(index, FmmdError::LeftAtTemporaryName(temporary));
+    }
+}
+
+/// Finds an unused hidden name next to `path` to park it under
+pub fn temporary_name(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    (1..)
+        .map(|counter| {
+            path.with_file_name(format!(".{}.fmmd-{}-{}", name, std::process::id(), counter))
+        })
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// Finds the first name of the form "name (N).ext" that isn't taken
+fn free_name(target: &Path, claimed: &HashMap<PathBuf, PathBuf>) -> PathBuf {
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = target
+        .extension()
+        .map(|extension| extension.to_string_lossy());
+
+    (2..)
+        .map(|counter| {
+            let name = match &extension {
+                Some(extension) => format!("{} ({}).{}", stem, counter, extension),
+                None => format!("{} ({})", stem, counter),
+            };
+            target.with_file_name(name)
+        })
+        .find(|candidate| !candidate.exists() && !claimed.contains_key(&normalize(candidate)))
+        .unwrap()
+}
+
+/// Makes `path` absolute and removes `.` and `..` components so paths can be compared
+pub fn normalize(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// Whether both paths point at the same file, e.g. when only the case of a name changes
+/// on a case-insensitive file system
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (file_id(a), file_id(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Identifies a file whatever path it is reached through
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileId(#[cfg(unix)] (u64, u64), #[cfg(not(unix))] PathBuf);
+
+/// The device and inode of a file
+#[cfg(unix)]
+pub fn file_id(path: &Path) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = path.metadata().ok()?;
+    Some(FileId((metadata.dev(), metadata.ino())))
+}
+
+/// The canonical path of a file, where there are no inodes to compare
+#[cfg(not(unix))]
+pub fn file_id(path: &Path) -> Option<FileId> {
+    path.canonicalize().ok().map(FileId)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// An empty directory of its own for each test
+    fn directory(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fmmd-plan-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A plan renaming the files in `dir` named by each pair
+    fn plan(dir: &Path, renames: &[(&str, &str)]) -> Plan {
+        let mut plan = Plan::default();
+        for (source, target) in renames {
+            plan.add(
+                dir.join(source),
+                dir.join(target),
+                Vec::new(),
+                Box::new(id3::Tag::new()),
+            );
+        }
+        plan
+    }
+
+    fn actions(plan: &Plan) -> Vec<Action> {
+        plan.renames.iter().map(|rename| rename.action).collect()
+    }
+
+    #[test]
+    fn the_first_rename_to_a_name_gets_it() {
+        let dir = directory("duplicates");
+        let mut skipped = plan(&dir, &[("a", "x"), ("b", "x"), ("c", "c")]);
+        assert_eq!(skipped.resolve_conflicts(ConflictPolicy::Skip), 1);
+        assert_eq!(
+            actions(&skipped),
+            [Action::Rename, Action::Skip, Action::Unchanged]
+        );
+        assert!(matches!(
+            &skipped.renames[1].conflict,
+            Some(Conflict { kind: ConflictKind::Duplicate(other), .. }) if *other == dir.join("a")
+        ));
+
+        let mut suffixed = plan(&dir, &[("a", "x.mp3"), ("b", "x.mp3"), ("c", "x.mp3")]);
+        assert_eq!(suffixed.resolve_conflicts(ConflictPolicy::Suffix), 2);
+        assert_eq!(suffixed.renames[1].target, dir.join("x (2).mp3"));
+        assert_eq!(suffixed.renames[2].target, dir.join("x (3).mp3"));
+    }
+
+    #[test]
+    fn unchanged_files_keep_their_names() {
+        let dir = directory("unchanged");
+        let mut plan = plan(&dir, &[("b", "a"), ("a", "a")]);
+        assert_eq!(plan.resolve_conflicts(ConflictPolicy::Skip), 1);
+        assert_eq!(actions(&plan), [Action::Skip, Action::Unchanged]);
+    }
+
+    #[test]
+    fn existing_files_are_only_overwritten_when_asked() {
+        let dir = directory("existing");
+        for name in ["a", "b"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        let mut skipped = plan(&dir, &[("a", "b")]);
+        assert_eq!(skipped.resolve_conflicts(ConflictPolicy::Skip), 1);
+        assert!(matches!(
+            skipped.renames[0].conflict,
+            Some(Conflict {
+                kind: ConflictKind::Exists,
+                ..
+            })
+        ));
+
+        let mut overwritten = plan(&dir, &[("a", "b")]);
+        assert_eq!(overwritten.resolve_conflicts(ConflictPolicy::Overwrite), 1);
+        assert_eq!(actions(&overwritten), [Action::Rename]);
+        assert!(overwritten.renames[0].overwrite);
+    }
+
+    #[test]
+    fn names_can_be_swapped() {
+        let dir = directory("swap");
+        for name in ["a", "b"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        let mut plan = plan(&dir, &[("a", "b"), ("b", "a")]);
+        assert_eq!(plan.resolve_conflicts(ConflictPolicy::Skip), 0);
+        assert_eq!(actions(&plan), [Action::Rename, Action::Rename]);
+    }
+
+    #[test]
+    fn skipped_renames_block_the_names_they_keep() {
+        let dir = directory("blocked");
+        for name in ["a", "b", "c"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        // `b` can't move onto `c`, so `a` can't move onto `b` either
+        let mut plan = plan(&dir, &[("a", "b"), ("b", "c")]);
+        assert_eq!(plan.resolve_conflicts(ConflictPolicy::Skip), 2);
+        assert_eq!(actions(&plan), [Action::Skip, Action::Skip]);
+    }
+
+    /// The steps for moves between the given names, as `park a` and `move a`
+    fn steps(moves: &[(&str, &str)]) -> Vec<String> {
+        let moves: Vec<(usize, &Path, &Path)> = moves
+            .iter()
+            .enumerate()
+            .map(|(index, (source, target))| (index, Path::new(*source), Path::new(*target)))
+            .collect();
+
+        schedule(&moves)
+            .into_iter()
+            .map(|step| match step {
+                Step::Park { index, .. } => format!("park {}", moves[index].1.display()),
+                Step::Move { index, .. } => format!("move {}", moves[index].1.display()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn independent_moves_keep_their_order() {
+        assert_eq!(steps(&[("a", "x"), ("b", "y")]), ["move a", "move b"]);
+    }
+
+    #[test]
+    fn chains_are_moved_from_their_end() {
+        assert_eq!(
+            steps(&[("a", "b"), ("b", "c"), ("c", "d")]),
+            ["move c", "move b", "move a"]
+        );
+    }
+
+    #[test]
+    fn cycles_are_broken_by_parking_a_file() {
+        assert_eq!(
+            steps(&[("a", "b"), ("b", "a")]),
+            ["park a", "move b", "move a"]
+        );
+        assert_eq!(
+            steps(&[("a", "b"), ("b", "c"), ("c", "a")]),
+            ["park a", "move c", "move b", "move a"]
+        );
+    }
+
+    #[test]
+    fn chains_reaching_scheduled_moves_stop_there() {
+        // `a -> b` is only looked at once `b -> c -> d` and the cycle are scheduled
+        assert_eq!(
+            steps(&[("b", "c"), ("x", "y"), ("c", "d"), ("y", "x"), ("a", "b")]),
+            ["move c", "move b", "park x", "move y", "move x", "move a"]
+        );
+    }
+
+    #[test]
+    fn parked_files_are_moved_into_place() {
+        let dir = directory("execute");
+        for name in ["a", "b"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        let moves = [(0, a.as_path(), b.as_path()), (1, b.as_path(), a.as_path())];
+        let targets = [&b, &a];
+        let mut errors = 0;
+        execute(
+            schedule(&moves),
+            false,
+            |index, from| Ok(move_file(from, targets[index])?),
+            |_, _| errors += 1,
+        );
+
+        assert_eq!(errors, 0);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "b");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "a");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    }
+}
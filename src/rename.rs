@@ -0,0 +1,453 @@
+//! Renaming files after their metadata, what fmmd does when no subcommand is given
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use owo_colors::OwoColorize;
+
+use crate::checksum;
+use crate::config;
+use crate::discs::{self, DiscPrefix};
+use crate::error::FmmdError;
+use crate::files::{create_dirs, move_file, remove_empty_dirs};
+use crate::index;
+use crate::input::InputArgs;
+use crate::journal::Journal;
+use crate::metadata::{Field, Format, Metadata};
+use crate::plan::{execute, same_file, Action, ConflictPolicy, Plan, PlannedRename};
+use crate::report::{print_error, FileReport, Outcome, Report, ReportFormat, Status};
+use crate::review;
+use crate::sanitize::{self, Profile, Sanitized, Sanitizer};
+use crate::template::{Lookup, Template, DEFAULT_LIBRARY_TEMPLATE, DEFAULT_TEMPLATE};
+use crate::tracks::{self, Padded};
+
+#[derive(Args, Clone)]
+pub struct RenameArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Perform a dry run without renaming files
+    #[arg(short, long)]
+    dry_run: bool,
+
+    /// Print verbose output
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Template used to build the new file name, e.g. "{albumartist|artist}/{album}/{track:02} {title}"
+    #[arg(short, long)]
+    template: Option<String>,
+
+    /// Move files into <ARTIST>/<ALBUM>/ directories below this directory instead of renaming them in place
+    #[arg(short, long, value_name = "DIR")]
+    library_root: Option<PathBuf>,
+
+    /// Remove directories that are left empty after moving files out of them
+    #[arg(long, overrides_with = "no_remove_empty_dirs")]
+    remove_empty_dirs: bool,
+
+    /// Keep directories left empty, even if the configuration removes them
+    #[arg(long, overrides_with = "remove_empty_dirs")]
+    no_remove_empty_dirs: bool,
+
+    /// What to do when the new name of a file is already taken [default: skip]
+    #[arg(long, value_enum)]
+    on_conflict: Option<ConflictPolicy>,
+
+    /// Ask before renaming each file, showing the tags its new name was built from
+    #[arg(short, long, conflicts_with_all = ["dry_run", "report"])]
+    interactive: bool,
+
+    /// Stop at the first file that can't be read or renamed
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Print a machine-readable report of what happened to each file
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+
+    /// File system to make new names safe for [default: posix]
+    #[arg(long, value_enum, value_name = "PROFILE")]
+    sanitize: Option<Profile>,
+
+    /// Replace text in field values before sanitizing them, e.g. --replace ":= -" (repeatable)
+    #[arg(long, value_name = "FROM=TO", value_parser = sanitize::parse_replacement)]
+    replace: Vec<(String, String)>,
+
+    /// When to put the disc number in front of file names, e.g. "2-01-Title.mp3" [default: auto]
+    #[arg(long, value_enum, value_name = "WHEN")]
+    disc_prefix: Option<DiscPrefix>,
+
+    /// Put the tracks of albums with more than one disc in "Disc N" directories
+    #[arg(long, overrides_with = "no_disc_folders")]
+    disc_folders: bool,
+
+    /// Keep the tracks of all discs together, even if the configuration sets --disc-folders
+    #[arg(long, overrides_with = "disc_folders")]
+    no_disc_folders: bool,
+
+    /// Pad track numbers to this many digits instead of working it out from the track total
+    #[arg(long, value_name = "DIGITS")]
+    track_width: Option<usize>,
+
+    /// Use the settings of this profile from the configuration files
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Read files that haven't changed since `fmmd index` from the index
+    #[arg(long)]
+    index: bool,
+
+    /// Record the checksum of the audio of each file in the journal, for `fmmd verify --run`
+    #[arg(long)]
+    checksums: bool,
+
+    /// Templates for files of a format, only set from the configuration files
+    #[arg(skip)]
+    format_templates: HashMap<Format, String>,
+}
+
+impl RenameArgs {
+    /// Fills in what wasn't given on the command line from the configuration files
+    fn configure(&self) -> Result<RenameArgs, FmmdError> {
+        let path = self.input.paths().first().map(PathBuf::as_path);
+        let settings = config::load(path, self.profile.as_deref())?;
+
+        let mut replace = settings.replace;
+        config::add_replacements(&mut replace, self.replace.clone());
+
+        // A template given on the command line applies to all formats
+        let format_templates = match self.template {
+            Some(_) => HashMap::new(),
+            None => settings
+                .formats
+                .into_iter()
+                .filter_map(|(format, settings)| Some((format, settings.template?)))
+                .collect(),
+        };
+
+        Ok(RenameArgs {
+            template: self.template.clone().or(settings.template),
+            library_root: self.library_root.clone().or(settings.library_root),
+            remove_empty_dirs: flag(self.remove_empty_dirs, self.no_remove_empty_dirs)
+                .or(settings.remove_empty_dirs)
+                .unwrap_or(false),
+            on_conflict: self.on_conflict.or(settings.on_conflict),
+            sanitize: self.sanitize.or(settings.sanitize),
+            disc_prefix: self.disc_prefix.or(settings.disc_prefix),
+            track_width: self.track_width.or(settings.track_width),
+            disc_folders: flag(self.disc_folders, self.no_disc_folders)
+                .or(settings.disc_folders)
+                .unwrap_or(false),
+            replace,
+            format_templates,
+            ..self.clone()
+        })
+    }
+}
+
+/// A switch given on the command line as `--x` or `--no-x`, whichever came last
+fn flag(yes: bool, no: bool) -> Option<bool> {
+    match (yes, no) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Renames all files given on the command line, recording the renames in the journal.
+///
+/// The new names of all files are worked out first so that conflicts can be dealt with
+/// before anything is renamed.
+pub fn rename(args: &RenameArgs) -> Result<Status, FmmdError> {
+    let cli = &args.configure()?;
+    let on_conflict = cli.on_conflict.unwrap_or_default();
+
+    let template = cli.template.as_deref().unwrap_or(match cli.library_root {
+        Some(_) => DEFAULT_LIBRARY_TEMPLATE,
+        None => DEFAULT_TEMPLATE,
+    });
+
+    let template = Template::parse(template)?;
+    let format_templates = cli
+        .format_templates
+        .iter()
+        .map(|(format, template)| Ok((*format, Template::parse(template)?)))
+        .collect::<Result<HashMap<_, _>, FmmdError>>()?;
+    let sanitizer = Sanitizer::new(cli.sanitize.unwrap_or_default(), cli.replace.clone());
+
+    let mut plan = Plan::default();
+    let mut report = Report::new(cli.report);
+
+    let files = cli.input.collect_with(|file, error| {
+        print_error(file, &error);
+        report.add(FileReport::unplanned(file, &error));
+    });
+
+    let indexed = index::open(cli.index)?;
+    let mut read = Vec::new();
+
+    for file in files {
+        if cli.fail_fast && report.summary.failed > 0 {
+            break;
+        }
+
+        match index::read(indexed.as_ref(), &file) {
+            Ok(metadata) => read.push((file, metadata)),
+            Err(error) => {
+                print_error(&file, &error);
+                report.add(FileReport::unplanned(&file, &error));
+            }
+        }
+    }
+
+    // Disc totals and track widths use all files, even those after a --fail-fast error
+    let read = discs::infer_totals(read);
+    let widths = tracks::widths(&read, cli.track_width);
+
+    for ((file, metadata), width) in read.into_iter().zip(widths) {
+        if cli.fail_fast && report.summary.failed > 0 {
+            break;
+        }
+
+        let template = format_templates
+            .get(&metadata.format())
+            .unwrap_or(&template);
+
+        let padded = Padded {
+            source: metadata.as_ref(),
+            width,
+        };
+
+        match plan_file(&file, metadata.as_ref(), &padded, template, &sanitizer, cli) {
+            Ok((target, fields)) => plan.add(file, target, fields, metadata),
+            Err(error) => {
+                print_error(&file, &error);
+                report.add(FileReport::unplanned(&file, &error));
+            }
+        }
+    }
+
+    let conflicts = plan.resolve_conflicts(on_conflict);
+    print_conflicts(&plan, on_conflict);
+
+    let conflict_abort = on_conflict == ConflictPolicy::Fail && conflicts > 0;
+    let abort = conflict_abort || cli.fail_fast && report.summary.failed > 0;
+    let quit = cli.interactive && !abort && !review::review(&mut plan, &sanitizer)?;
+
+    for rename in &plan.renames {
+        match rename.action {
+            Action::Unchanged => {
+                if cli.verbose && cli.report.is_none() {
+                    println!("{} (unchanged)", rename.source.display());
+                }
+                report.add(FileReport::planned(rename, Outcome::Unchanged, None));
+            }
+            Action::Skip => report.add(FileReport::planned(rename, Outcome::Skipped, None)),
+            Action::Rename => {}
+        }
+    }
+
+    if cli.dry_run || abort || quit {
+        for rename in &plan.renames {
+            if rename.action == Action::Rename {
+                if cli.dry_run && !abort {
+                    print_rename(rename, cli);
+                }
+                report.add(FileReport::planned(rename, Outcome::NotRun, None));
+            }
+        }
+
+        if conflict_abort {
+            report.finish();
+            return Err(FmmdError::Conflicts(conflicts));
+        }
+
+        return Ok(finish(&report, cli));
+    }
+
+    let mut journal = Journal::new()?;
+    let mut renamed = HashSet::new();
+    let mut failures = BTreeMap::new();
+
+    execute(
+        plan.schedule(),
+        cli.fail_fast,
+        |index, from| {
+            let rename = &plan.renames[index];
+            rename_file(rename, from, cli, &mut journal)?;
+            renamed.insert(index);
+            report.add(FileReport::planned(rename, Outcome::Renamed, None));
+            Ok(())
+        },
+        |index, error| {
+            print_error(&plan.renames[index].source, &error);
+            // Only the last error of a file ends up in the report
+            failures.insert(index, error);
+        },
+    );
+
+    for (index, rename) in plan.renames.iter().enumerate() {
+        if rename.action != Action::Rename || renamed.contains(&index) {
+            continue;
+        }
+
+        // Files without an error weren't attempted because of --fail-fast
+        match failures.get(&index) {
+            Some(error) => report.add(FileReport::planned(rename, Outcome::Failed, Some(error))),
+            None => report.add(FileReport::planned(rename, Outcome::NotRun, None)),
+        }
+    }
+
+    Ok(finish(&report, cli))
+}
+
+/// Prints the report, or the summary when no report was asked for
+fn finish(report: &Report, cli: &RenameArgs) -> Status {
+    match cli.report {
+        Some(_) => report.finish(),
+        None => println!("{}", report.summary_line(cli.dry_run)),
+    }
+
+    report.summary.status(cli.dry_run)
+}
+
+fn print_conflicts(plan: &Plan, policy: ConflictPolicy) {
+    for rename in &plan.renames {
+        let Some(conflict) = &rename.conflict else {
+            continue;
+        };
+
+        let resolution = match policy {
+            ConflictPolicy::Skip => "skipped".to_string(),
+            ConflictPolicy::Fail => "not renamed".to_string(),
+            ConflictPolicy::Overwrite => "overwriting".to_string(),
+            ConflictPolicy::Suffix => format!("renaming to \"{}\"", rename.target.display()),
+        };
+
+        eprintln!(
+            "{}: \"{}\" ({})",
+            format!("Conflict, {}", conflict).yellow(),
+            rename.source.display().yellow(),
+            resolution
+        );
+    }
+}
+
+/// Works out the new name of a file, along with the fields used to build it.
+/// The values of fields are taken from `values`, `metadata` as it is stored in the file.
+fn plan_file(
+    file: &Path,
+    metadata: &dyn Metadata,
+    values: &dyn Lookup,
+    template: &Template,
+    sanitizer: &Sanitizer,
+    cli: &RenameArgs,
+) -> Result<(PathBuf, Vec<Field>), FmmdError> {
+    let base = match &cli.library_root {
+        Some(library_root) => library_root.as_path(),
+        None => file.parent().unwrap_or(Path::new("")),
+    };
+
+    let prefix = cli.disc_prefix.unwrap_or_default();
+    let template = discs::apply(template, metadata, prefix, cli.disc_folders);
+
+    get_filename(values, file, base, &template, sanitizer)
+}
+
+fn print_rename(rename: &PlannedRename, cli: &RenameArgs) {
+    let (file, new_file) = (&rename.source, &rename.target);
+
+    if cli.report.is_some() {
+        // The report is the only thing printed to stdout
+    } else if cli.verbose {
+        println!(
+            "{} -> {} ({})",
+            file.display(),
+            new_file.display(),
+            rename.metadata.format()
+        );
+    } else if cli.dry_run {
+        println!("{} -> {}", file.display(), new_file.display());
+    }
+}
+
+/// Carries out a planned rename, moving the file from `from`, which is where it currently is
+fn rename_file(
+    rename: &PlannedRename,
+    from: &Path,
+    cli: &RenameArgs,
+    journal: &mut Journal,
+) -> Result<(), FmmdError> {
+    let (file, new_file) = (&rename.source, &rename.target);
+
+    // Guards against files showing up since planning and renames that failed earlier on
+    if new_file.exists() && !rename.overwrite && !same_file(from, new_file) {
+        return Err(FmmdError::TargetExists);
+    }
+
+    // Taken before the move, so that it is known which file it belongs to if the move fails
+    let checksum = match cli.checksums {
+        true => Some(checksum::audio(from)?),
+        false => None,
+    };
+
+    print_rename(rename, cli);
+
+    let old_parent = file.parent().and_then(|parent| parent.canonicalize().ok());
+
+    let created = match new_file.parent() {
+        Some(parent) => create_dirs(parent)?,
+        None => Vec::new(),
+    };
+
+    if let Err(error) = move_file(from, new_file) {
+        return Err(FmmdError::FileRename(error));
+    }
+
+    journal.record(file, new_file, checksum, created)?;
+
+    if cli.remove_empty_dirs {
+        if let Some(old_parent) = old_parent {
+            remove_empty_dirs(&old_parent, cli.library_root.as_deref());
+        }
+    }
+
+    Ok(())
+}
+
+/// Attempts to crate a new file name based on the `Metadata` and `PathBuf` provided.
+///
+/// The new name is built by rendering `template`, which needs to pick up at least one
+/// field from the metadata. Directories in the template are created relative to `base`.
+/// Field values and the resulting names are made safe with `sanitizer`.
+pub fn get_filename(
+    metadata: &dyn Lookup,
+    file: &Path,
+    base: &Path,
+    template: &Template,
+    sanitizer: &Sanitizer,
+) -> Result<(PathBuf, Vec<Field>), FmmdError> {
+    let rendered = template.render(&Sanitized {
+        source: metadata,
+        sanitizer,
+    });
+
+    if rendered.fields.is_empty() || rendered.components.is_empty() {
+        return Err(FmmdError::NotEnoughMetadata);
+    }
+
+    // Titles may contain dots, so the extension is appended rather than set
+    let extension = file
+        .extension()
+        .map(|extension| extension.to_string_lossy());
+    let last = rendered.components.len() - 1;
+
+    let mut new_path = base.to_path_buf();
+    for (index, component) in rendered.components.iter().enumerate() {
+        let extension = extension.as_deref().filter(|_| index == last);
+        new_path.push(sanitizer.component(component, extension));
+    }
+
+    Ok((new_path, rendered.fields))
+}
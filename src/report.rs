@@ -0,0 +1,316 @@
+//! Machine-readable reports of what a run did to each file, see `--report`, along with
+//! the exit status and error messages shared by all subcommands
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use owo_colors::OwoColorize;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+use crate::error::FmmdError;
+use crate::plan::{Action, PlannedRename};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// A single JSON document printed at the end of the run
+    Json,
+    /// One JSON object per line, printed as soon as each file is done
+    Ndjson,
+}
+
+/// What ended up happening to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Renamed,
+    /// The file already had the right name
+    Unchanged,
+    /// Left alone because of a conflict, missing metadata or because it was declined
+    Skipped,
+    Failed,
+    /// Not attempted, because of `--dry-run` or an aborted run
+    NotRun,
+}
+
+#[derive(Serialize)]
+pub struct FileReport {
+    pub source: PathBuf,
+    pub target: Option<PathBuf>,
+    pub format: Option<String>,
+    /// What was planned for the file, missing when it couldn't be planned at all
+    pub planned: Option<Action>,
+    pub result: Outcome,
+    pub conflict: Option<String>,
+    /// Values of the fields used to build the new name
+    pub metadata: Pairs,
+    pub error: Option<ErrorReport>,
+}
+
+#[derive(Serialize)]
+pub struct ErrorReport {
+    /// See [`FmmdError::kind`]
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl From<&FmmdError> for ErrorReport {
+    fn from(error: &FmmdError) -> ErrorReport {
+        ErrorReport {
+            kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl FileReport {
+    /// A file that no new name could be worked out for, skipped when it lacks metadata
+    pub fn unplanned(source: &Path, error: &FmmdError) -> FileReport {
+        let result = match error {
+            FmmdError::NotEnoughMetadata => Outcome::Skipped,
+            _ => Outcome::Failed,
+        };
+
+        FileReport {
+            source: source.to_path_buf(),
+            target: None,
+            format: None,
+            planned: None,
+            result,
+            conflict: None,
+            metadata: Pairs(Vec::new()),
+            error: Some(error.into()),
+        }
+    }
+
+    pub fn planned(
+        rename: &PlannedRename,
+        result: Outcome,
+        error: Option<&FmmdError>,
+    ) -> FileReport {
+        let metadata = rename
+            .fields
+            .iter()
+            .filter_map(|field| Some((field.to_string(), rename.metadata.lookup(field)?)))
+            .collect();
+
+        FileReport {
+            source: rename.source.clone(),
+            target: Some(rename.target.clone()),
+            format: Some(rename.metadata.format().to_string()),
+            planned: Some(rename.action),
+            result,
+            conflict: rename.conflict.as_ref().map(ToString::to_string),
+            metadata: Pairs(metadata),
+            error: error.map(ErrorReport::from),
+        }
+    }
+}
+
+/// Number of files per [`Outcome`]
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub total: usize,
+    pub renamed: usize,
+    pub unchanged: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub not_run: usize,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Line<'a> {
+    File(&'a FileReport),
+    Summary(&'a Summary),
+}
+
+impl Summary {
+    /// Whether the run went well, `dry_run` counting files that weren't run as fine
+    pub fn status(&self, dry_run: bool) -> Status {
+        let mut succeeded = self.renamed + self.unchanged + self.skipped;
+        if dry_run {
+            succeeded += self.not_run;
+        }
+
+        Status::from_counts(self.failed, succeeded)
+    }
+}
+
+/// How a run ended, deciding the exit code of fmmd
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    /// Some files failed
+    Partial,
+    /// All files failed
+    Failure,
+}
+
+impl Status {
+    /// How a command went that failed on `errors` files and got `done` others done
+    pub fn from_counts(errors: usize, done: usize) -> Status {
+        match (errors, done) {
+            (0, _) => Status::Success,
+            (_, 0) => Status::Failure,
+            _ => Status::Partial,
+        }
+    }
+
+    pub fn code(self) -> i32 {
+        match self {
+            Status::Success => 0,
+            Status::Partial => 1,
+            Status::Failure => 2,
+        }
+    }
+}
+
+/// Prints why `file` couldn't be used to stderr
+pub fn print_error(file: &Path, error: impl Display) {
+    eprintln!("{}: \"{}\"", error.red(), file.display().red());
+}
+
+/// Collects the reports of all files of a run and prints them in the requested format
+pub struct Report {
+    format: Option<ReportFormat>,
+    files: Vec<FileReport>,
+    pub summary: Summary,
+    /// Why files were skipped and how many of them, in the order they came up
+    reasons: Vec<(&'static str, usize)>,
+}
+
+impl Report {
+    pub fn new(format: Option<ReportFormat>) -> Report {
+        Report {
+            format,
+            files: Vec::new(),
+            summary: Summary::default(),
+            reasons: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, file: FileReport) {
+        let summary = &mut self.summary;
+        summary.total += 1;
+        match file.result {
+            Outcome::Renamed => summary.renamed += 1,
+            Outcome::Unchanged => summary.unchanged += 1,
+            Outcome::Skipped => {
+                summary.skipped += 1;
+                self.add_reason(&file);
+            }
+            Outcome::Failed => summary.failed += 1,
+            Outcome::NotRun => summary.not_run += 1,
+        }
+
+        match self.format {
+            Some(ReportFormat::Ndjson) => print_line(&Line::File(&file)),
+            Some(ReportFormat::Json) => self.files.push(file),
+            None => {}
+        }
+    }
+
+    fn add_reason(&mut self, file: &FileReport) {
+        let reason = match &file.error {
+            Some(error) if error.kind == "not_enough_metadata" => "not enough metadata",
+            Some(error) => error.kind,
+            None if file.conflict.is_some() => "name taken",
+            None => "declined",
+        };
+
+        match self.reasons.iter_mut().find(|(known, _)| *known == reason) {
+            Some((_, count)) => *count += 1,
+            None => self.reasons.push((reason, 1)),
+        }
+    }
+
+    /// Describes the run in a line, e.g. "412 renamed, 3 skipped: not enough metadata, 1 error"
+    pub fn summary_line(&self, dry_run: bool) -> String {
+        let summary = &self.summary;
+        let mut parts = Vec::new();
+
+        match dry_run {
+            true => parts.push(format!("{} to rename", summary.not_run)),
+            false => parts.push(format!("{} renamed", summary.renamed)),
+        }
+        if summary.unchanged > 0 {
+            parts.push(format!("{} unchanged", summary.unchanged));
+        }
+        if summary.skipped > 0 {
+            let reasons = match self.reasons.as_slice() {
+                [(reason, _)] => reason.to_string(),
+                reasons => reasons
+                    .iter()
+                    .map(|(reason, count)| format!("{} {}", count, reason))
+                    .collect::<Vec<_>>()
+                    .join(" and "),
+            };
+            parts.push(format!("{} skipped: {}", summary.skipped, reasons));
+        }
+        if summary.not_run > 0 && !dry_run {
+            parts.push(format!("{} not run", summary.not_run));
+        }
+        if summary.failed > 0 {
+            let noun = if summary.failed == 1 {
+                "error"
+            } else {
+                "errors"
+            };
+            parts.push(format!("{} {}", summary.failed, noun));
+        }
+
+        parts.join(", ")
+    }
+
+    /// Prints the report, or just the summary when the files were printed as they came in
+    pub fn finish(&self) {
+        #[derive(Serialize)]
+        struct Document<'a> {
+            files: &'a [FileReport],
+            summary: &'a Summary,
+        }
+
+        match self.format {
+            Some(ReportFormat::Json) => {
+                let document = Document {
+                    files: &self.files,
+                    summary: &self.summary,
+                };
+                println!("{}", serde_json::to_string_pretty(&document).unwrap());
+            }
+            Some(ReportFormat::Ndjson) => print_line(&Line::Summary(&self.summary)),
+            None => {}
+        }
+    }
+}
+
+fn print_line(line: &Line) {
+    println!("{}", serde_json::to_string(line).unwrap());
+}
+
+/// Key and value pairs serialized as a map that keeps their order
+pub struct Pairs(pub Vec<(String, String)>);
+
+impl Serialize for Pairs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_follows_the_counts() {
+        assert_eq!(Status::from_counts(0, 0), Status::Success);
+        assert_eq!(Status::from_counts(0, 3), Status::Success);
+        assert_eq!(Status::from_counts(1, 3), Status::Partial);
+        assert_eq!(Status::from_counts(2, 0), Status::Failure);
+    }
+}
@@ -0,0 +1,161 @@
+//! Going through the renames of a plan one by one with `--interactive`, before any file
+//! is touched.
+use std::path::{Path, PathBuf};
+
+use owo_colors::OwoColorize;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::error::FmmdError;
+use crate::metadata;
+use crate::plan::{normalize, Action, Plan, PlannedRename};
+use crate::sanitize::Sanitizer;
+
+const QUESTION: &str = "Rename? [y]es, [n]o, [e]dit, [a]ll of this album, [q]uit: ";
+
+enum Answer {
+    Yes,
+    No,
+    Edit,
+    Album,
+    Quit,
+}
+
+/// Asks about each rename of `plan`, turning declined renames into skipped ones and
+/// taking over edited names once `sanitizer` made them safe.
+///
+/// Returns `false` when the user quit, in which case nothing should be renamed.
+pub fn review(plan: &mut Plan, sanitizer: &Sanitizer) -> Result<bool, FmmdError> {
+    let mut editor = DefaultEditor::new()?;
+    // Albums that were accepted as a whole, see [`album`]
+    let mut accepted: Vec<(Option<(String, String)>, PathBuf)> = Vec::new();
+
+    for index in 0..plan.renames.len() {
+        let rename = &plan.renames[index];
+        if rename.action != Action::Rename || accepted.contains(&album(rename)) {
+            continue;
+        }
+
+        print_rename(rename);
+
+        loop {
+            let answer = match ask(&mut editor)? {
+                Some(answer) => answer,
+                None => return Ok(false),
+            };
+
+            match answer {
+                Answer::Yes => break,
+                Answer::No => {
+                    plan.renames[index].action = Action::Skip;
+                    break;
+                }
+                Answer::Edit => {
+                    if edit(plan, index, sanitizer, &mut editor)? {
+                        break;
+                    }
+                }
+                Answer::Album => {
+                    accepted.push(album(&plan.renames[index]));
+                    break;
+                }
+                Answer::Quit => return Ok(false),
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+fn print_rename(rename: &PlannedRename) {
+    println!(
+        "{} -> {}",
+        rename.source.display().bold(),
+        rename.target.display().bold()
+    );
+
+    for field in &rename.fields {
+        if let Some(value) = rename.metadata.lookup(field) {
+            println!("  {}: {}", field.to_string().cyan(), value);
+        }
+    }
+}
+
+/// Asks until a known answer is given, `None` when input ended
+fn ask(editor: &mut DefaultEditor) -> Result<Option<Answer>, FmmdError> {
+    loop {
+        let line = match editor.readline(QUESTION) {
+            Ok(line) => line,
+            Err(ReadlineError::Eof | ReadlineError::Interrupted) => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        let answer = match line.trim().to_lowercase().as_str() {
+            "y" | "yes" => Answer::Yes,
+            "n" | "no" => Answer::No,
+            "e" | "edit" => Answer::Edit,
+            "a" | "all" => Answer::Album,
+            "q" | "quit" => Answer::Quit,
+            _ => continue,
+        };
+
+        return Ok(Some(answer));
+    }
+}
+
+/// Lets the user change the new name of a rename, returning `false` when it has to be
+/// asked about again
+fn edit(
+    plan: &mut Plan,
+    index: usize,
+    sanitizer: &Sanitizer,
+    editor: &mut DefaultEditor,
+) -> Result<bool, FmmdError> {
+    let current = plan.renames[index].target.to_string_lossy().to_string();
+
+    let line = match editor.readline_with_initial("New name: ", (&current, "")) {
+        Ok(line) => line,
+        Err(ReadlineError::Eof | ReadlineError::Interrupted) => return Ok(false),
+        Err(error) => return Err(error.into()),
+    };
+
+    let typed = Path::new(line.trim());
+    if typed.as_os_str().is_empty() {
+        return Ok(false);
+    }
+
+    // Typed names are held to the same rules as rendered ones
+    let target = sanitizer.path(typed);
+    if target != typed {
+        println!("Made safe as {}", target.display().bold());
+    }
+
+    if let Some(conflict) = plan.conflict_with(index, &target) {
+        println!("{}", format!("Conflict, {}", conflict).yellow());
+        return Ok(false);
+    }
+
+    let rename = &mut plan.renames[index];
+    rename.target = target;
+    if normalize(&rename.source) == normalize(&rename.target) {
+        rename.action = Action::Unchanged;
+    }
+
+    Ok(true)
+}
+
+/// Tells albums apart for accepting them as a whole, by their directory when the files
+/// don't name an album
+fn album(rename: &PlannedRename) -> (Option<(String, String)>, PathBuf) {
+    match metadata::album(rename.metadata.as_ref()) {
+        Some(album) => (Some(album), PathBuf::new()),
+        None => (
+            None,
+            rename
+                .source
+                .parent()
+                .unwrap_or(Path::new(""))
+                .to_path_buf(),
+        ),
+    }
+}
@@ -0,0 +1,328 @@
+//! Turning rendered names into file names that are safe to use on the target file system.
+//!
+//! Field values are cleaned up as they are filled into the template, so a title like
+//! `AC/DC` can't create a directory. Each path component is checked once more as a whole
+//! for names the file system reserves and for its length.
+use std::path::{Component, Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::metadata::Field;
+use crate::template::Lookup;
+
+/// Replaces characters that aren't allowed when no replacement is given for them
+const REPLACEMENT: &str = "_";
+
+/// Maximum length of a single name, including the extension
+const MAX_LENGTH: usize = 255;
+
+/// Names Windows reserves for devices, regardless of their extension
+const DEVICE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The file systems names can be made safe for
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// Linux and macOS: only replace `/` and control characters, up to 255 bytes per name
+    #[default]
+    Posix,
+    /// Also replace `\ : * ? " < > |`, device names like CON and trailing dots and spaces
+    Windows,
+    /// Like windows, also replacing `+ , ; = [ ]` for devices that only read short names
+    Fat32,
+    /// Like windows, with non-ASCII characters transliterated and anything but letters,
+    /// digits and `space . _ - ( )` replaced
+    StrictAscii,
+}
+
+impl Profile {
+    fn is_reserved(self, c: char) -> bool {
+        if c == '/' || c.is_control() {
+            return true;
+        }
+
+        match self {
+            Profile::Posix => false,
+            Profile::Windows => "\\:*?\"<>|".contains(c),
+            Profile::Fat32 => "\\:*?\"<>|+,;=[]".contains(c),
+            Profile::StrictAscii => !(c.is_ascii_alphanumeric() || " ._-()".contains(c)),
+        }
+    }
+
+    /// Length of a name as counted by the file system
+    fn length(self, name: &str) -> usize {
+        match self {
+            Profile::Posix | Profile::StrictAscii => name.len(),
+            // NTFS and FAT store long names in UTF-16
+            Profile::Windows | Profile::Fat32 => name.encode_utf16().count(),
+        }
+    }
+
+    fn is_windows_like(self) -> bool {
+        self != Profile::Posix
+    }
+}
+
+/// Makes names safe for a [`Profile`], see the module documentation
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    profile: Profile,
+    /// Replacements applied to field values before anything else, from `--replace`
+    replacements: Vec<(String, String)>,
+}
+
+impl Sanitizer {
+    pub fn new(profile: Profile, replacements: Vec<(String, String)>) -> Sanitizer {
+        Sanitizer {
+            profile,
+            replacements,
+        }
+    }
+
+    /// Cleans up a field value so it can become part of a name
+    pub fn value(&self, value: &str) -> String {
+        let mut value = value.to_string();
+
+        for (from, to) in &self.replacements {
+            value = value.replace(from.as_str(), to);
+        }
+
+        self.replace_reserved(&value)
+    }
+
+    /// Makes a whole path component safe, `extension` being appended to it when given.
+    ///
+    /// Names that are too long are shortened, keeping the extension intact.
+    pub fn component(&self, name: &str, extension: Option<&str>) -> String {
+        let mut stem = self.replace_reserved(name);
+
+        let suffix = match extension {
+            Some(extension) => format!(".{}", self.replace_reserved(extension)),
+            None => String::new(),
+        };
+
+        if self.profile.is_windows_like() {
+            trim_trailing_dots(&mut stem);
+        }
+
+        // `.` and `..` would point at a different directory altogether
+        if stem.is_empty() || stem == "." || stem == ".." {
+            stem = REPLACEMENT.to_string();
+        }
+
+        // Windows only looks at the name before the first dot, e.g. `CON` of `CON.live`
+        if self.profile.is_windows_like() && is_device_name(&stem) {
+            let end = stem.find('.').unwrap_or(stem.len());
+            stem.insert_str(end, REPLACEMENT);
+        }
+
+        let available = MAX_LENGTH.saturating_sub(self.profile.length(&suffix));
+        while self.profile.length(&stem) > available {
+            stem.pop();
+        }
+
+        if self.profile.is_windows_like() {
+            trim_trailing_dots(&mut stem);
+        }
+
+        stem + &suffix
+    }
+
+    /// Makes a whole path safe that was typed in rather than rendered, checking each name
+    /// with [`Sanitizer::component`] and keeping the extension of the file name intact.
+    ///
+    /// Directories that exist already are kept as they are, like the base directory of
+    /// rendered names.
+    pub fn path(&self, path: &Path) -> PathBuf {
+        let mut sanitized = PathBuf::new();
+        let mut components = path.components().peekable();
+
+        while let Some(component) = components.next() {
+            let Component::Normal(name) = component else {
+                sanitized.push(component);
+                continue;
+            };
+
+            if components.peek().is_none() {
+                let name = Path::new(name);
+                let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+                let extension = name
+                    .extension()
+                    .map(|extension| extension.to_string_lossy());
+                sanitized.push(self.component(&stem, extension.as_deref()));
+            } else if sanitized.join(name).is_dir() {
+                sanitized.push(name);
+            } else {
+                sanitized.push(self.component(&name.to_string_lossy(), None));
+            }
+        }
+
+        sanitized
+    }
+
+    fn replace_reserved(&self, value: &str) -> String {
+        let value = match self.profile {
+            Profile::StrictAscii => deunicode::deunicode(value),
+            _ => value.to_string(),
+        };
+
+        let mut replaced = String::with_capacity(value.len());
+
+        for c in value.chars() {
+            match self.profile.is_reserved(c) {
+                true => replaced.push_str(REPLACEMENT),
+                false => replaced.push(c),
+            }
+        }
+
+        replaced
+    }
+}
+
+/// Sanitizes the values of another [`Lookup`] as they are looked up
+pub struct Sanitized<'a, L: ?Sized> {
+    pub source: &'a L,
+    pub sanitizer: &'a Sanitizer,
+}
+
+impl<L: Lookup + ?Sized> Lookup for Sanitized<'_, L> {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        self.source
+            .lookup(field)
+            .map(|value| self.sanitizer.value(&value))
+    }
+
+    fn lookup_unpadded(&self, field: &Field) -> Option<String> {
+        self.source
+            .lookup_unpadded(field)
+            .map(|value| self.sanitizer.value(&value))
+    }
+}
+
+/// Parses a `FROM=TO` replacement given on the command line
+pub fn parse_replacement(replacement: &str) -> Result<(String, String), String> {
+    match replacement.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((from.to_string(), to.to_string())),
+        _ => Err("expected FROM=TO, e.g. \":= -\"".to_string()),
+    }
+}
+
+/// Windows silently drops trailing dots and spaces, so names ending in them can't be opened
+fn trim_trailing_dots(name: &mut String) {
+    let trimmed = name.trim_end_matches(['.', ' ']).len();
+    name.truncate(trimmed);
+}
+
+/// Windows also reserves device names followed by an extension, e.g. `NUL.mp3`
+fn is_device_name(name: &str) -> bool {
+    let base = name.split('.').next().unwrap_or_default().trim_end();
+    DEVICE_NAMES
+        .iter()
+        .any(|device| device.eq_ignore_ascii_case(base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitizer(profile: Profile) -> Sanitizer {
+        Sanitizer::new(profile, Vec::new())
+    }
+
+    fn posix_component(name: &str) -> String {
+        sanitizer(Profile::Posix).component(name, Some("mp3"))
+    }
+
+    #[test]
+    fn values_cannot_create_directories() {
+        assert_eq!(
+            sanitizer(Profile::Posix).value("AC/DC: Live?"),
+            "AC_DC: Live?"
+        );
+        assert_eq!(
+            sanitizer(Profile::Windows).value("AC/DC: Live?"),
+            "AC_DC_ Live_"
+        );
+        assert_eq!(sanitizer(Profile::Fat32).value("a+b;c"), "a_b_c");
+        assert_eq!(
+            sanitizer(Profile::StrictAscii).value("Björk & Sigur Rós"),
+            "Bjork _ Sigur Ros"
+        );
+    }
+
+    #[test]
+    fn replacements_come_first() {
+        let sanitizer = Sanitizer::new(Profile::Windows, vec![(":".to_string(), " -".to_string())]);
+        assert_eq!(sanitizer.value("Live: 1999"), "Live - 1999");
+    }
+
+    #[test]
+    fn windows_names_are_made_usable() {
+        let windows = sanitizer(Profile::Windows);
+        assert_eq!(windows.component("Title. ", Some("mp3")), "Title.mp3");
+        assert_eq!(windows.component("nul", Some("mp3")), "nul_.mp3");
+        assert_eq!(windows.component("Con.live", None), "Con_.live");
+        assert_eq!(
+            windows.component("LPT1 .b-sides", Some("mp3")),
+            "LPT1 _.b-sides.mp3"
+        );
+        assert_eq!(windows.component("...", None), "_");
+
+        let posix = sanitizer(Profile::Posix);
+        assert_eq!(posix.component("nul", Some("mp3")), "nul.mp3");
+        assert_eq!(posix.component("..", None), "_");
+    }
+
+    #[test]
+    fn typed_paths_are_made_safe_below_existing_directories() {
+        let dir = crate::fixtures::directory("sanitize-path");
+        std::fs::create_dir(dir.join("Existing: dir")).unwrap();
+        let windows = sanitizer(Profile::Windows);
+
+        assert_eq!(
+            windows.path(&dir.join("Existing: dir/New: dir/Con.live.").join("AUX.mp3")),
+            dir.join("Existing: dir/New_ dir/Con_.live/AUX_.mp3")
+        );
+        assert_eq!(
+            windows.path(Path::new("../a?b/Title. .mp3")),
+            Path::new("../a_b/Title.mp3")
+        );
+
+        let long = sanitizer(Profile::Posix).path(&dir.join(format!("{}.mp3", "a".repeat(300))));
+        assert_eq!(long.file_name().unwrap().len(), MAX_LENGTH);
+        assert_eq!(long.extension().unwrap(), "mp3");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn long_names_keep_their_extension() {
+        let name = posix_component(&"a".repeat(300));
+        assert_eq!(name.len(), MAX_LENGTH);
+        assert!(name.ends_with("a.mp3"));
+
+        // Two bytes each, but one UTF-16 unit
+        let name = sanitizer(Profile::Windows).component(&"é".repeat(300), Some("mp3"));
+        assert_eq!(name.encode_utf16().count(), MAX_LENGTH);
+        let name = posix_component(&"é".repeat(300));
+        assert!(name.len() <= MAX_LENGTH);
+    }
+
+    #[test]
+    fn replacements_need_something_to_replace() {
+        assert_eq!(
+            parse_replacement(":= -"),
+            Ok((":".to_string(), " -".to_string()))
+        );
+        assert_eq!(
+            parse_replacement("&="),
+            Ok(("&".to_string(), String::new()))
+        );
+        assert!(parse_replacement("=x").is_err());
+        assert!(parse_replacement("x").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//! The `show` subcommand, listing what fmmd reads from files
+use std::io;
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use owo_colors::OwoColorize;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::error::FmmdError;
+use crate::index::{self, Index};
+use crate::input::InputArgs;
+use crate::metadata::Field;
+use crate::report::{print_error, Pairs, Status};
+
+#[derive(Error, Debug)]
+pub enum ShowError {
+    #[error("Could not write JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Could not write YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("Could not write CSV: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+#[derive(Args)]
+pub struct ShowArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// How to print the tags
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+
+    /// Read files that haven't changed since `fmmd index` from the index
+    #[arg(long)]
+    index: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// A table per file, for reading
+    Table,
+    Json,
+    Yaml,
+    /// One row per field and frame: file, format, kind, key, value
+    Csv,
+}
+
+/// Everything read from a single file
+#[derive(Serialize)]
+struct FileTags {
+    file: PathBuf,
+    format: String,
+    /// Values of the fields templates can use, as fmmd understands them
+    fields: Pairs,
+    /// Everything stored in the tag
+    frames: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    key: String,
+    value: String,
+}
+
+pub fn show(args: &ShowArgs) -> Result<Status, FmmdError> {
+    let indexed = index::open(args.index)?;
+    let mut files = Vec::new();
+    let mut errors = 0;
+
+    for file in args.input.collect() {
+        match read(indexed.as_ref(), file.clone()) {
+            Ok(tags) => files.push(tags),
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    match args.format {
+        OutputFormat::Table => print_tables(&files),
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(io::stdout(), &files).map_err(ShowError::from)?;
+            println!();
+        }
+        OutputFormat::Yaml => {
+            serde_yaml::to_writer(io::stdout(), &files).map_err(ShowError::from)?
+        }
+        OutputFormat::Csv => write_csv(&files).map_err(ShowError::from)?,
+    }
+
+    Ok(Status::from_counts(errors, files.len()))
+}
+
+fn read(indexed: Option<&Index>, file: PathBuf) -> Result<FileTags, FmmdError> {
+    let metadata = index::read(indexed, &file)?;
+
+    let fields = Field::STANDARD
+        .iter()
+        .filter_map(|field| Some((field.to_string(), metadata.lookup(field)?)))
+        .collect();
+
+    let frames = metadata
+        .entries()
+        .into_iter()
+        .map(|(key, value)| Entry { key, value })
+        .collect();
+
+    Ok(FileTags {
+        file,
+        format: metadata.format().to_string(),
+        fields: Pairs(fields),
+        frames,
+    })
+}
+
+fn print_tables(files: &[FileTags]) {
+    for (index, tags) in files.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        println!("{}  {}", tags.file.display().bold(), tags.format.yellow());
+
+        let width = tags
+            .fields
+            .0
+            .iter()
+            .map(|(key, _)| key)
+            .chain(tags.frames.iter().map(|entry| &entry.key))
+            .map(|key| key.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let fields = tags.fields.0.iter().map(|(key, value)| (key, value));
+        let frames = tags.frames.iter().map(|entry| (&entry.key, &entry.value));
+        let sections: [(&str, Vec<_>); 2] =
+            [("Fields", fields.collect()), ("Frames", frames.collect())];
+
+        for (title, rows) in sections {
+            if rows.is_empty() {
+                continue;
+            }
+
+            println!("  {}", title.dimmed());
+            for (key, value) in rows {
+                // Keeps multi-line values such as lyrics on a single row
+                let value = value.lines().collect::<Vec<_>>().join(" / ");
+                println!("    {:width$}  {}", key.cyan(), value, width = width);
+            }
+        }
+    }
+}
+
+fn write_csv(files: &[FileTags]) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    writer.write_record(["file", "format", "kind", "key", "value"])?;
+
+    for tags in files {
+        let file = tags.file.to_string_lossy();
+        let fields = tags
+            .fields
+            .0
+            .iter()
+            .map(|(key, value)| ("field", key, value));
+        let frames = tags
+            .frames
+            .iter()
+            .map(|entry| ("frame", &entry.key, &entry.value));
+
+        for (kind, key, value) in fields.chain(frames) {
+            writer.write_record([file.as_ref(), tags.format.as_str(), kind, key, value])?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,334 @@
+//! Writing ID3 tags, those of MP3 files and the ID3 chunks of WAV and AIFF files
+use std::path::Path;
+
+use id3::frame::{Comment, ExtendedLink, ExtendedText};
+use id3::{ErrorKind, Frame, Tag, TagLike, Timestamp, Version};
+
+use super::TagError;
+use crate::metadata::{Field, Format, MetadataError};
+
+/// Reads the ID3 tag of a file, or an empty one if it has none yet.
+///
+/// The ID3v1 tag of an MP3 file is removed when the file is written, so the fields only
+/// it has are carried over into the ID3v2 tag.
+pub fn read(file: &Path, format: Format) -> Result<Tag, MetadataError> {
+    // The ID3 chunk of WAV and AIFF files is found by looking at the file
+    let mut tag = id3::no_tag_ok(Tag::read_from_path(file))?.unwrap_or_default();
+
+    if format == Format::Mp3 {
+        let v1 = match id3::v1::Tag::read_from_path(file) {
+            Ok(v1) => Some(Tag::from(v1)),
+            Err(error) if matches!(error.kind, ErrorKind::NoTag) => None,
+            Err(error) => return Err(error.into()),
+        };
+
+        if let Some(v1) = v1 {
+            let dated = tag.year().is_some() || tag.date_recorded().is_some();
+            for frame in v1.frames() {
+                let missing = match frame.id() {
+                    "TYER" => !dated,
+                    id => tag.get(id).is_none(),
+                };
+
+                if missing {
+                    tag.add_frame(frame.clone());
+                }
+            }
+        }
+    }
+
+    Ok(tag)
+}
+
+pub fn set(tag: &mut Tag, field: &Field, value: &str, version: Version) -> Result<(), TagError> {
+    let number = |value: &str| {
+        value
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| TagError::InvalidValue(field.clone(), value.to_string()))
+    };
+
+    match field {
+        Field::Title => tag.set_title(value),
+        Field::Artist => tag.set_artist(value),
+        Field::Album => tag.set_album(value),
+        Field::AlbumArtist => tag.set_album_artist(value),
+        Field::Genre => tag.set_genre(value),
+        Field::Composer => tag.set_text("TCOM", value),
+        Field::Year => {
+            let timestamp: Timestamp = value
+                .trim()
+                .parse()
+                .map_err(|_| TagError::InvalidValue(field.clone(), value.to_string()))?;
+            tag.remove_year();
+            tag.remove_date_recorded();
+
+            // ID3v2.3 only has a year frame, ID3v2.4 replaced it with timestamps
+            match version {
+                Version::Id3v24 => tag.set_date_recorded(timestamp),
+                _ => tag.set_year(timestamp.year),
+            }
+        }
+        Field::Track | Field::Disc => {
+            let (id, total) = match field {
+                Field::Track => ("TRCK", tag.total_tracks()),
+                _ => ("TPOS", tag.total_discs()),
+            };
+
+            let text = match value.split_once('/') {
+                Some((value, total)) => format!("{}/{}", number(value)?, number(total)?),
+                None => match total {
+                    Some(total) => format!("{}/{}", number(value)?, total),
+                    None => number(value)?.to_string(),
+                },
+            };
+            tag.set_text(id, text);
+        }
+        Field::TrackTotal => tag.set_total_tracks(number(value)?),
+        Field::DiscTotal => tag.set_total_discs(number(value)?),
+        Field::Comment => {
+            tag.add_frame(Comment {
+                lang: "eng".to_string(),
+                description: String::new(),
+                text: value.to_string(),
+            });
+        }
+        Field::Raw {
+            key,
+            description: Some(description),
+        } => {
+            let frame: Frame = match key.as_str() {
+                "TXXX" => ExtendedText {
+                    description: description.clone(),
+                    value: value.to_string(),
+                }
+                .into(),
+                "WXXX" => ExtendedLink {
+                    description: description.clone(),
+                    link: value.to_string(),
+                }
+                .into(),
+                "COMM" => Comment {
+                    lang: "eng".to_string(),
+                    description: description.clone(),
+                    text: value.to_string(),
+                }
+                .into(),
+                _ => return Err(TagError::UnsupportedFrame(field.to_string())),
+            };
+            // Replaces the frames it is looked up in, whatever the case of their description
+            remove(tag, field);
+            tag.add_frame(frame);
+        }
+        Field::Raw { key, .. } if key.starts_with('T') && key != "TXXX" => tag.set_text(key, value),
+        Field::Raw { key, .. } if key.starts_with('W') && key != "WXXX" => {
+            tag.add_frame(Frame::link(key, value));
+        }
+        Field::Raw { .. } => return Err(TagError::UnsupportedFrame(field.to_string())),
+    };
+
+    Ok(())
+}
+
+pub fn remove(tag: &mut Tag, field: &Field) {
+    match field {
+        Field::Title => tag.remove_title(),
+        Field::Artist => tag.remove_artist(),
+        Field::Album => tag.remove_album(),
+        Field::AlbumArtist => tag.remove_album_artist(),
+        Field::Genre => tag.remove_genre(),
+        Field::Year => {
+            tag.remove_year();
+            tag.remove_date_recorded();
+        }
+        Field::Track => tag.remove_track(),
+        Field::TrackTotal => tag.remove_total_tracks(),
+        Field::Disc => tag.remove_disc(),
+        Field::DiscTotal => tag.remove_total_discs(),
+        Field::Composer => {
+            tag.remove("TCOM");
+        }
+        Field::Comment => {
+            tag.remove("COMM");
+        }
+        Field::Raw {
+            key,
+            description: Some(description),
+        } => {
+            // Descriptions are matched ignoring case, like when they are looked up
+            let matches = |other: &str| other.eq_ignore_ascii_case(description);
+            let frames: Vec<Frame> = tag.remove(key);
+            for frame in frames {
+                let keep = match frame.content() {
+                    id3::Content::ExtendedText(text) => !matches(&text.description),
+                    id3::Content::ExtendedLink(link) => !matches(&link.description),
+                    id3::Content::Comment(comment) => !matches(&comment.description),
+                    _ => true,
+                };
+
+                if keep {
+                    tag.add_frame(frame);
+                }
+            }
+        }
+        Field::Raw { key, .. } => {
+            tag.remove(key);
+        }
+    }
+}
+
+/// Moves the date into the frame the version to be written uses
+pub fn convert_dates(tag: &mut Tag, version: Version) {
+    match version {
+        Version::Id3v24 => {
+            if let (Some(year), None) = (tag.year(), tag.date_recorded()) {
+                tag.remove_year();
+                tag.set_date_recorded(Timestamp {
+                    year,
+                    month: None,
+                    day: None,
+                    hour: None,
+                    minute: None,
+                    second: None,
+                });
+            }
+        }
+        _ => {
+            if let (None, Some(date)) = (tag.year(), tag.date_recorded()) {
+                tag.remove_date_recorded();
+                tag.set_year(date.year);
+            }
+        }
+    }
+}
+
+/// The fields of the frames not covered by the well-known fields
+pub fn raw_fields(tag: &Tag) -> Vec<Field> {
+    tag.frames().filter_map(raw_field).collect()
+}
+
+/// The field a frame not covered by the well-known fields is looked up as
+fn raw_field(frame: &Frame) -> Option<Field> {
+    let description = match frame.content() {
+        id3::Content::ExtendedText(text) => Some(text.description.clone()),
+        id3::Content::ExtendedLink(link) => Some(link.description.clone()),
+        id3::Content::Comment(comment) => Some(comment.description.clone()),
+        _ => None,
+    };
+
+    match frame.id() {
+        "TIT2" | "TPE1" | "TALB" | "TPE2" | "TYER" | "TDRC" | "TRCK" | "TPOS" | "TCON" | "TCOM" => {
+            None
+        }
+        "COMM" if description.as_deref() == Some("") => None,
+        key => Field::parse(&match description {
+            Some(description) => format!("{}.{}", key, description),
+            None => key.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(name: &str) -> Field {
+        Field::parse(name).unwrap()
+    }
+
+    fn extended_texts(tag: &Tag) -> Vec<(String, String)> {
+        tag.extended_texts()
+            .map(|text| (text.description.clone(), text.value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn extended_text_is_replaced_whatever_the_case_of_its_description() {
+        let mut tag = Tag::new();
+        tag.add_frame(ExtendedText {
+            description: "MOOD".to_string(),
+            value: "calm".to_string(),
+        });
+        tag.add_frame(ExtendedText {
+            description: "Other".to_string(),
+            value: "kept".to_string(),
+        });
+
+        set(&mut tag, &raw("TXXX.mood"), "tense", Version::Id3v24).unwrap();
+        set(&mut tag, &raw("TXXX.Mood"), "eerie", Version::Id3v24).unwrap();
+
+        assert_eq!(
+            extended_texts(&tag),
+            [
+                ("Other".to_string(), "kept".to_string()),
+                ("Mood".to_string(), "eerie".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_keep_their_totals() {
+        let mut tag = Tag::new();
+        set(&mut tag, &Field::Track, "3/12", Version::Id3v24).unwrap();
+        set(&mut tag, &Field::Track, "4", Version::Id3v24).unwrap();
+        set(&mut tag, &Field::Disc, "1", Version::Id3v24).unwrap();
+
+        assert_eq!(tag.get("TRCK").unwrap().content().text(), Some("4/12"));
+        assert_eq!(tag.get("TPOS").unwrap().content().text(), Some("1"));
+        assert!(set(&mut tag, &Field::Track, "four", Version::Id3v24).is_err());
+    }
+
+    #[test]
+    fn years_are_set_in_the_frame_of_the_version() {
+        let mut v23 = Tag::new();
+        set(&mut v23, &Field::Year, "2002-02-18", Version::Id3v23).unwrap();
+        assert_eq!(v23.year(), Some(2002));
+        assert_eq!(v23.date_recorded(), None);
+
+        let mut v24 = Tag::new();
+        v24.set_year(1998);
+        set(&mut v24, &Field::Year, "2002-02-18", Version::Id3v24).unwrap();
+        assert_eq!(v24.year(), None);
+        assert_eq!(v24.date_recorded().unwrap().to_string(), "2002-02-18");
+    }
+
+    #[test]
+    fn frames_without_a_text_or_link_value_are_not_set() {
+        let mut tag = Tag::new();
+        assert!(matches!(
+            set(&mut tag, &raw("APIC"), "cover", Version::Id3v24),
+            Err(TagError::UnsupportedFrame(_))
+        ));
+        assert!(matches!(
+            set(&mut tag, &raw("PRIV.owner"), "data", Version::Id3v24),
+            Err(TagError::UnsupportedFrame(_))
+        ));
+        assert_eq!(tag.frames().count(), 0);
+    }
+
+    #[test]
+    fn only_frames_with_a_matching_description_are_removed() {
+        let mut tag = Tag::new();
+        for description in ["MusicBrainz Album Id", "MOOD"] {
+            tag.add_frame(ExtendedText {
+                description: description.to_string(),
+                value: "value".to_string(),
+            });
+        }
+        tag.set_text("TCOM", "Composer");
+        tag.set_title("Title");
+
+        remove(&mut tag, &raw("TXXX.musicbrainz album id"));
+        remove(&mut tag, &Field::Composer);
+
+        assert_eq!(
+            extended_texts(&tag),
+            [("MOOD".to_string(), "value".to_string())]
+        );
+        assert!(tag.get("TCOM").is_none());
+        assert_eq!(tag.title(), Some("Title"));
+
+        remove(&mut tag, &raw("TXXX"));
+        assert!(extended_texts(&tag).is_empty());
+    }
+}
@@ -0,0 +1,529 @@
+//! The `tag` subcommand, writing tags instead of reading them.
+//!
+//! ID3 tags are written to MP3 files and to the ID3 chunks of WAV and AIFF files, Vorbis
+//! comments to FLAC files and metadata atoms to MP4 files. Whatever isn't changed is
+//! written back untouched. Ogg files can't be written yet.
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use clap::{Args, Subcommand, ValueEnum};
+use id3::Version;
+use thiserror::Error;
+
+use crate::error::FmmdError;
+use crate::input::InputArgs;
+use crate::metadata::{self, Field, Format, MetadataError};
+use crate::report::{print_error, Status};
+use crate::template::{Lookup, Template};
+
+mod id3tag;
+mod mp4;
+mod vorbis;
+
+#[derive(Error, Debug)]
+pub enum TagError {
+    #[error("Writing tags to {0} files is not supported")]
+    UnsupportedFormat(Format),
+
+    #[error("\"{1}\" is not a valid value for {0}")]
+    InvalidValue(Field, String),
+
+    #[error("Frame {0} can't be written, only text (T...) and link (W...) frames can")]
+    UnsupportedFrame(String),
+
+    #[error("The path doesn't match the pattern")]
+    NoMatch,
+
+    #[error("Could not write the tag: {0}")]
+    Write(#[from] MetadataError),
+}
+
+#[derive(Args)]
+pub struct TagArgs {
+    #[command(subcommand)]
+    command: TagCommand,
+}
+
+#[derive(Subcommand)]
+enum TagCommand {
+    /// Set fields, e.g. `fmmd tag set --artist X --track 3/12 FILES...`
+    Set(Box<SetArgs>),
+
+    /// Remove fields or frames, e.g. `fmmd tag remove --frame COMM FILES...`
+    Remove(RemoveArgs),
+
+    /// Set fields from the paths of files, e.g. `fmmd tag from-path --pattern "{artist}/{album}/{track} - {title}" FILES...`
+    FromPath(FromPathArgs),
+}
+
+/// Options shared by everything that writes tags
+#[derive(Args)]
+pub struct WriteArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Show the changes without writing them
+    #[arg(short, long)]
+    pub dry_run: bool,
+
+    /// Print the changes made to each file
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// ID3 version to write, defaults to the version of the existing tag or 2.4
+    #[arg(long, value_enum, value_name = "VERSION")]
+    id3_version: Option<Id3Version>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Id3Version {
+    #[value(name = "2.3")]
+    V23,
+    #[value(name = "2.4")]
+    V24,
+}
+
+#[derive(Args)]
+struct SetArgs {
+    #[command(flatten)]
+    write: WriteArgs,
+
+    #[arg(long)]
+    title: Option<String>,
+
+    #[arg(long)]
+    artist: Option<String>,
+
+    #[arg(long)]
+    album: Option<String>,
+
+    #[arg(long)]
+    album_artist: Option<String>,
+
+    /// Year or full date, e.g. 2003 or 2003-04-01
+    #[arg(long)]
+    year: Option<String>,
+
+    /// Track number, optionally with the total, e.g. 3 or 3/12
+    #[arg(long)]
+    track: Option<String>,
+
+    /// Disc number, optionally with the total, e.g. 1 or 1/2
+    #[arg(long)]
+    disc: Option<String>,
+
+    #[arg(long)]
+    genre: Option<String>,
+
+    #[arg(long)]
+    composer: Option<String>,
+
+    #[arg(long)]
+    comment: Option<String>,
+
+    /// Set any field or frame, e.g. --frame TPE3=Conductor or --frame "TXXX.Mood=Calm" (repeatable)
+    #[arg(long = "frame", value_name = "FRAME=VALUE", value_parser = parse_assignment)]
+    frames: Vec<(Field, String)>,
+}
+
+impl SetArgs {
+    /// All fields to set, in the order they are applied
+    fn assignments(&self) -> Vec<(Field, String)> {
+        let named = [
+            (Field::Title, &self.title),
+            (Field::Artist, &self.artist),
+            (Field::Album, &self.album),
+            (Field::AlbumArtist, &self.album_artist),
+            (Field::Year, &self.year),
+            (Field::Track, &self.track),
+            (Field::Disc, &self.disc),
+            (Field::Genre, &self.genre),
+            (Field::Composer, &self.composer),
+            (Field::Comment, &self.comment),
+        ];
+
+        named
+            .into_iter()
+            .filter_map(|(field, value)| Some((field, value.clone()?)))
+            .chain(self.frames.iter().cloned())
+            .collect()
+    }
+}
+
+#[derive(Args)]
+struct RemoveArgs {
+    #[command(flatten)]
+    write: WriteArgs,
+
+    /// Field or frame to remove, e.g. comment, COMM or "TXXX.Mood" (repeatable)
+    #[arg(long = "frame", value_name = "FRAME", required = true, value_parser = parse_field)]
+    frames: Vec<Field>,
+}
+
+#[derive(Args)]
+struct FromPathArgs {
+    #[command(flatten)]
+    write: WriteArgs,
+
+    /// Template the paths of the files look like, e.g. "{artist}/{album}/{track} - {title}"
+    #[arg(short, long)]
+    pattern: String,
+
+    /// Write the tags without asking first
+    #[arg(short, long)]
+    yes: bool,
+}
+
+pub fn tag(args: &TagArgs) -> Result<Status, FmmdError> {
+    match &args.command {
+        TagCommand::Set(args) => {
+            let assignments = args.assignments();
+            update(&args.write, |tags| {
+                for (field, value) in &assignments {
+                    tags.set(field, value)?;
+                }
+                Ok(())
+            })
+        }
+        TagCommand::Remove(args) => update(&args.write, |tags| {
+            for field in &args.frames {
+                tags.remove(field);
+            }
+            Ok(())
+        }),
+        TagCommand::FromPath(args) => from_path(args),
+    }
+}
+
+/// Fails for formats tags can't be written to, for commands that would otherwise only
+/// find out once they have done their work
+pub fn ensure_writable(format: Format) -> Result<(), TagError> {
+    match format {
+        Format::OggVorbis | Format::Opus => Err(TagError::UnsupportedFormat(format)),
+        _ => Ok(()),
+    }
+}
+
+/// The tag of a file in the form it is written back in
+#[derive(Clone)]
+enum Tags {
+    /// Along with the version it is to be written as
+    Id3(id3::Tag, Version),
+    Flac(metaflac::Tag),
+    Mp4(mp4ameta::Tag),
+}
+
+impl Tags {
+    fn read(file: &Path, format: Format, version: Option<Id3Version>) -> Result<Tags, FmmdError> {
+        let tags = match format {
+            Format::Mp3 | Format::Wav | Format::Aiff => {
+                let tag = id3tag::read(file, format)?;
+                let version = match version {
+                    Some(Id3Version::V23) => Version::Id3v23,
+                    Some(Id3Version::V24) => Version::Id3v24,
+                    None if tag.version() == Version::Id3v23 => Version::Id3v23,
+                    None => Version::Id3v24,
+                };
+                Tags::Id3(tag, version)
+            }
+            Format::Flac => {
+                Tags::Flac(metaflac::Tag::read_from_path(file).map_err(MetadataError::from)?)
+            }
+            Format::Mp4 => {
+                Tags::Mp4(mp4ameta::Tag::read_from_path(file).map_err(MetadataError::from)?)
+            }
+            format => return Err(TagError::UnsupportedFormat(format).into()),
+        };
+
+        Ok(tags)
+    }
+
+    /// Sets a field, removing it when `value` is empty
+    fn set(&mut self, field: &Field, value: &str) -> Result<(), TagError> {
+        if value.is_empty() {
+            self.remove(field);
+            return Ok(());
+        }
+
+        match self {
+            Tags::Id3(tag, version) => id3tag::set(tag, field, value, *version),
+            Tags::Flac(tag) => vorbis::set(tag, field, value),
+            Tags::Mp4(tag) => mp4::set(tag, field, value),
+        }
+    }
+
+    fn remove(&mut self, field: &Field) {
+        match self {
+            Tags::Id3(tag, _) => id3tag::remove(tag, field),
+            Tags::Flac(tag) => vorbis::remove(tag, field),
+            Tags::Mp4(tag) => mp4::remove(tag, field),
+        }
+    }
+
+    /// The fields beyond the well-known ones, which are compared one by one
+    fn raw_fields(&self) -> Vec<Field> {
+        match self {
+            Tags::Id3(tag, _) => id3tag::raw_fields(tag),
+            Tags::Flac(tag) => vorbis::raw_fields(tag),
+            Tags::Mp4(tag) => mp4::raw_fields(tag),
+        }
+    }
+
+    /// Whether the tag is written in another ID3 version than it was read in
+    fn converts(&self) -> bool {
+        matches!(self, Tags::Id3(tag, version) if tag.version() != *version)
+    }
+
+    fn write(&mut self, file: &Path, format: Format) -> Result<(), MetadataError> {
+        match self {
+            // Also removes the ID3v1 tag, which would still hold the old values
+            Tags::Id3(tag, version) if format == Format::Mp3 => {
+                Ok(id3::v1v2::write_to_path(file, tag, *version)?)
+            }
+            Tags::Id3(tag, version) => Ok(tag.write_to_path(file, *version)?),
+            Tags::Flac(tag) => metadata::write_flac(tag, file),
+            Tags::Mp4(tag) => Ok(tag.write_to_path(file)?),
+        }
+    }
+}
+
+impl Lookup for Tags {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        match self {
+            Tags::Id3(tag, _) => tag.lookup(field),
+            Tags::Flac(tag) => tag.lookup(field),
+            Tags::Mp4(tag) => tag.lookup(field),
+        }
+    }
+}
+
+/// A tag that has been changed but not written yet
+pub struct Pending {
+    pub file: PathBuf,
+    format: Format,
+    tags: Tags,
+}
+
+impl Pending {
+    pub fn write(&mut self) -> Result<(), TagError> {
+        Ok(self.tags.write(&self.file, self.format)?)
+    }
+}
+
+/// Applies `change` to the tag of every file and writes it back
+fn update(
+    args: &WriteArgs,
+    change: impl Fn(&mut Tags) -> Result<(), TagError>,
+) -> Result<Status, FmmdError> {
+    let mut errors = 0;
+    let mut done = 0;
+
+    for file in args.input.collect() {
+        let result = prepare(&file, args, args.dry_run || args.verbose, &change);
+
+        let result = match result {
+            Ok(Some(mut pending)) if !args.dry_run => pending.write().map_err(FmmdError::from),
+            Ok(_) => Ok(()),
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok(()) => done += 1,
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    Ok(Status::from_counts(errors, done))
+}
+
+/// Sets `values` in the tag of `file`, for other commands that fix tags.
+/// The changes are printed when `print` is set.
+pub fn set_fields(
+    file: &Path,
+    args: &WriteArgs,
+    values: &[(Field, String)],
+    print: bool,
+) -> Result<(), FmmdError> {
+    let pending = prepare(file, args, print, |tags| {
+        for (field, value) in values {
+            tags.set(field, value)?;
+        }
+        Ok(())
+    })?;
+
+    match pending {
+        Some(mut pending) if !args.dry_run => Ok(pending.write()?),
+        _ => Ok(()),
+    }
+}
+
+/// Works out the tag of `file` with `values` set and prints the changes, for commands that
+/// ask before writing. Returns the changed tag when it needs to be written.
+pub fn prepare_fields(
+    file: &Path,
+    args: &WriteArgs,
+    values: &[(Field, String)],
+) -> Result<Option<Pending>, FmmdError> {
+    prepare(file, args, true, |tags| {
+        for (field, value) in values {
+            tags.set(field, value)?;
+        }
+        Ok(())
+    })
+}
+
+/// Sets the fields picked out of the path of each file by a template, asking before
+/// writing anything
+fn from_path(args: &FromPathArgs) -> Result<Status, FmmdError> {
+    let pattern = Template::parse(&args.pattern)?;
+    let mut pending = Vec::new();
+    let mut errors = 0;
+    let mut unchanged = 0;
+
+    for file in args.write.input.collect() {
+        let result = match extract(&pattern, &file) {
+            Some(values) => prepare_fields(&file, &args.write, &values),
+            None => Err(TagError::NoMatch.into()),
+        };
+
+        match result {
+            Ok(Some(changed)) => pending.push(changed),
+            Ok(None) => unchanged += 1,
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    // Files left as they are because of --dry-run or the answer count as done
+    let done = unchanged + pending.len();
+    if args.write.dry_run || pending.is_empty() {
+        return Ok(Status::from_counts(errors, done));
+    }
+
+    if !args.yes && !confirm(&format!("Write tags to {} files?", pending.len()))? {
+        return Ok(Status::from_counts(errors, done));
+    }
+
+    let mut written = 0;
+    for mut changed in pending {
+        match changed.write() {
+            Ok(()) => written += 1,
+            Err(error) => {
+                print_error(&changed.file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    Ok(Status::from_counts(errors, unchanged + written))
+}
+
+/// Matches the path of `file` without its extension against `pattern`, starting with the
+/// file name and adding directories until the pattern matches
+fn extract(pattern: &Template, file: &Path) -> Option<Vec<(Field, String)>> {
+    let path = std::path::absolute(file).ok()?;
+    let mut components: Vec<String> = path
+        .parent()?
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect();
+    components.push(path.file_stem()?.to_string_lossy().to_string());
+
+    (1..=components.len())
+        .find_map(|count| pattern.extract(&components[components.len() - count..].join("/")))
+}
+
+pub fn confirm(question: &str) -> Result<bool, FmmdError> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Applies `change` to the tag of `file`, printing the changes when `print` is set.
+///
+/// Returns the changed tag when it needs to be written.
+fn prepare(
+    file: &Path,
+    args: &WriteArgs,
+    print: bool,
+    change: impl FnOnce(&mut Tags) -> Result<(), TagError>,
+) -> Result<Option<Pending>, FmmdError> {
+    let format = metadata::detect(file)?;
+    let before = Tags::read(file, format, args.id3_version)?;
+
+    let mut after = before.clone();
+    if let Tags::Id3(tag, version) = &mut after {
+        id3tag::convert_dates(tag, *version);
+    }
+    change(&mut after)?;
+
+    let changes = diff(&before, &after);
+    let rewrite = !changes.is_empty() || after.converts();
+
+    if print {
+        match rewrite {
+            true => println!("{}", file.display()),
+            false => println!("{} (unchanged)", file.display()),
+        }
+
+        for (field, old, new) in &changes {
+            println!("  {}: {} -> {}", field, show(old), show(new));
+        }
+    }
+
+    Ok(rewrite.then(|| Pending {
+        file: file.to_path_buf(),
+        format,
+        tags: after,
+    }))
+}
+
+/// Lists the fields whose value differs between both tags
+fn diff(before: &Tags, after: &Tags) -> Vec<(Field, Option<String>, Option<String>)> {
+    let mut fields = Field::STANDARD.to_vec();
+
+    // Everything else is compared field by field
+    for field in before.raw_fields().into_iter().chain(after.raw_fields()) {
+        if !fields.contains(&field) {
+            fields.push(field);
+        }
+    }
+
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let (old, new) = (before.lookup(&field), after.lookup(&field));
+            (old != new).then_some((field, old, new))
+        })
+        .collect()
+}
+
+fn show(value: &Option<String>) -> String {
+    match value {
+        Some(value) => format!("\"{}\"", value),
+        None => "(none)".to_string(),
+    }
+}
+
+fn parse_field(name: &str) -> Result<Field, String> {
+    Field::parse(name).ok_or_else(|| format!("unknown field or frame `{}`", name))
+}
+
+pub fn parse_assignment(assignment: &str) -> Result<(Field, String), String> {
+    match assignment.split_once('=') {
+        Some((name, value)) => Ok((parse_field(name)?, value.to_string())),
+        None => Err("expected FRAME=VALUE, e.g. TPE3=Conductor".to_string()),
+    }
+}
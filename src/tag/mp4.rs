@@ -0,0 +1,124 @@
+//! Writing the metadata atoms of MP4 files
+use id3::Timestamp;
+use mp4ameta::ident::APPLE_ITUNES_MEAN;
+use mp4ameta::{Data, DataIdent, Tag};
+
+use super::TagError;
+use crate::metadata::Field;
+
+pub fn set(tag: &mut Tag, field: &Field, value: &str) -> Result<(), TagError> {
+    let invalid = || TagError::InvalidValue(field.clone(), value.to_string());
+    let number = |value: &str| value.trim().parse::<u16>().map_err(|_| invalid());
+
+    match field {
+        Field::Title => tag.set_title(value),
+        Field::Artist => tag.set_artist(value),
+        Field::Album => tag.set_album(value),
+        Field::AlbumArtist => tag.set_album_artist(value),
+        Field::Genre => tag.set_genre(value),
+        Field::Composer => tag.set_composer(value),
+        Field::Comment => tag.set_comment(value),
+        Field::Year => {
+            let timestamp: Timestamp = value.trim().parse().map_err(|_| invalid())?;
+            tag.set_year(timestamp.to_string());
+        }
+        Field::Track => match value.split_once('/') {
+            Some((value, total)) => tag.set_track(number(value)?, number(total)?),
+            None => tag.set_track_number(number(value)?),
+        },
+        Field::Disc => match value.split_once('/') {
+            Some((value, total)) => tag.set_disc(number(value)?, number(total)?),
+            None => tag.set_disc_number(number(value)?),
+        },
+        Field::TrackTotal => tag.set_total_tracks(number(value)?),
+        Field::DiscTotal => tag.set_total_discs(number(value)?),
+        Field::Raw { .. } => {
+            let key = field.raw_key().unwrap_or_default();
+            remove(tag, field);
+            tag.add_data(
+                DataIdent::freeform(APPLE_ITUNES_MEAN, key.to_string()),
+                Data::Utf8(value.to_string()),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub fn remove(tag: &mut Tag, field: &Field) {
+    match field {
+        Field::Title => tag.remove_title(),
+        Field::Artist => tag.remove_artists(),
+        Field::Album => tag.remove_album(),
+        Field::AlbumArtist => tag.remove_album_artists(),
+        Field::Year => tag.remove_year(),
+        Field::Track => tag.remove_track_number(),
+        Field::TrackTotal => tag.remove_total_tracks(),
+        Field::Disc => tag.remove_disc_number(),
+        Field::DiscTotal => tag.remove_total_discs(),
+        Field::Genre => tag.remove_genres(),
+        Field::Composer => tag.remove_composers(),
+        Field::Comment => tag.remove_comments(),
+        Field::Raw { .. } => {
+            let key = field.raw_key().unwrap_or_default();
+            // Matches the atoms the field is looked up in
+            tag.retain_data(|ident, _| match ident {
+                DataIdent::Fourcc(fourcc) => fourcc.to_string() != key,
+                DataIdent::Freeform { name, .. } => !name.eq_ignore_ascii_case(key),
+            });
+        }
+    }
+}
+
+/// The fields of the freeform atoms, which hold everything beyond the well-known fields
+pub fn raw_fields(tag: &Tag) -> Vec<Field> {
+    tag.data()
+        .filter_map(|(ident, _)| match ident {
+            DataIdent::Freeform { name, .. } => Some(name),
+            DataIdent::Fourcc(_) => None,
+        })
+        .map(|name| {
+            // Names that don't look like keys, e.g. `MusicBrainz Album Id`, are looked up
+            // by description
+            Field::parse(name).unwrap_or_else(|| Field::Raw {
+                key: "TXXX".to_string(),
+                description: Some(name.to_string()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Lookup;
+
+    #[test]
+    fn numbers_are_set_with_and_without_totals() {
+        let mut tag = Tag::default();
+        set(&mut tag, &Field::Track, "3/12").unwrap();
+        set(&mut tag, &Field::Track, "4").unwrap();
+        set(&mut tag, &Field::DiscTotal, "2").unwrap();
+
+        assert_eq!(tag.track(), (Some(4), Some(12)));
+        assert_eq!(tag.total_discs(), Some(2));
+        assert!(matches!(
+            set(&mut tag, &Field::Disc, "70000"),
+            Err(TagError::InvalidValue(..))
+        ));
+    }
+
+    #[test]
+    fn raw_fields_are_freeform_atoms() {
+        let mut tag = Tag::default();
+        let field = Field::parse("TXXX.MusicBrainz Album Id").unwrap();
+        set(&mut tag, &field, "first").unwrap();
+        set(&mut tag, &field, "second").unwrap();
+
+        assert_eq!(tag.lookup(&field).as_deref(), Some("second"));
+        assert_eq!(raw_fields(&tag), std::slice::from_ref(&field));
+
+        remove(&mut tag, &field);
+        assert_eq!(tag.lookup(&field), None);
+    }
+}
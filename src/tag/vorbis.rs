@@ -0,0 +1,176 @@
+//! Writing the Vorbis comments of FLAC files
+use id3::Timestamp;
+use metaflac::Tag;
+
+use super::TagError;
+use crate::metadata::{comment_keys, Field};
+use crate::template::Lookup;
+
+pub fn set(tag: &mut Tag, field: &Field, value: &str) -> Result<(), TagError> {
+    let invalid = || TagError::InvalidValue(field.clone(), value.to_string());
+    let number = |value: &str| value.trim().parse::<u32>().map_err(|_| invalid());
+
+    match field {
+        Field::Year => {
+            let timestamp: Timestamp = value.trim().parse().map_err(|_| invalid())?;
+            replace(tag, field, timestamp.to_string());
+        }
+        Field::Track | Field::Disc => {
+            let total_field = match field {
+                Field::Track => Field::TrackTotal,
+                _ => Field::DiscTotal,
+            };
+
+            // The total is kept in a comment of its own, even when it was part of the number
+            let (value, total) = match value.split_once('/') {
+                Some((value, total)) => (value, Some(number(total)?)),
+                None => (
+                    value,
+                    tag.lookup(&total_field)
+                        .and_then(|total| total.parse().ok()),
+                ),
+            };
+
+            replace(tag, field, number(value)?.to_string());
+            if let Some(total) = total {
+                replace(tag, &total_field, total.to_string());
+            }
+        }
+        Field::TrackTotal | Field::DiscTotal => replace(tag, field, number(value)?.to_string()),
+        Field::Raw { .. } => {
+            let key = field.raw_key().unwrap_or_default();
+            tag.set_vorbis(key, vec![value]);
+        }
+        _ => replace(tag, field, value.to_string()),
+    }
+
+    Ok(())
+}
+
+pub fn remove(tag: &mut Tag, field: &Field) {
+    match field {
+        Field::Raw { .. } => tag.remove_vorbis(field.raw_key().unwrap_or_default()),
+        _ => {
+            for key in comment_keys(field) {
+                tag.remove_vorbis(key);
+            }
+        }
+    }
+}
+
+/// Sets a well-known field under the first of its keys, dropping the others so that they
+/// can't contradict it
+fn replace(tag: &mut Tag, field: &Field, value: String) {
+    remove(tag, field);
+    tag.set_vorbis(comment_keys(field)[0], vec![value]);
+}
+
+/// The fields of the comments not covered by the well-known fields
+pub fn raw_fields(tag: &Tag) -> Vec<Field> {
+    let Some(comments) = tag.vorbis_comments() else {
+        return Vec::new();
+    };
+
+    let mut keys: Vec<&String> = comments
+        .comments
+        .keys()
+        .filter(|key| {
+            !Field::STANDARD
+                .iter()
+                .any(|field| comment_keys(field).contains(&key.as_str()))
+        })
+        .collect();
+    keys.sort();
+
+    keys.into_iter()
+        .map(|key| {
+            // Keys that don't look like one, e.g. with spaces, are looked up by description
+            Field::parse(key).unwrap_or_else(|| Field::Raw {
+                key: "TXXX".to_string(),
+                description: Some(key.clone()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(comments: &[(&str, &str)]) -> Tag {
+        let mut tag = Tag::new();
+        for (key, value) in comments {
+            tag.set_vorbis(*key, vec![*value]);
+        }
+        tag
+    }
+
+    #[test]
+    fn aliases_are_replaced() {
+        let mut tag = tag(&[("ALBUM ARTIST", "Old"), ("YEAR", "1999")]);
+        set(&mut tag, &Field::AlbumArtist, "New").unwrap();
+        set(&mut tag, &Field::Year, "2003-04-01").unwrap();
+
+        assert_eq!(
+            tag.get_vorbis("ALBUMARTIST").unwrap().collect::<Vec<_>>(),
+            ["New"]
+        );
+        assert!(tag.get_vorbis("ALBUM ARTIST").is_none());
+        assert_eq!(
+            tag.get_vorbis("DATE").unwrap().collect::<Vec<_>>(),
+            ["2003-04-01"]
+        );
+        assert!(tag.get_vorbis("YEAR").is_none());
+        assert_eq!(tag.lookup(&Field::Year).as_deref(), Some("2003"));
+    }
+
+    #[test]
+    fn totals_are_kept_apart() {
+        let mut tag = tag(&[("TRACKNUMBER", "3/12")]);
+        set(&mut tag, &Field::Track, "4").unwrap();
+        assert_eq!(tag.lookup(&Field::Track).as_deref(), Some("4"));
+        assert_eq!(tag.lookup(&Field::TrackTotal).as_deref(), Some("12"));
+
+        set(&mut tag, &Field::Disc, "1/2").unwrap();
+        assert_eq!(
+            tag.get_vorbis("DISCNUMBER").unwrap().collect::<Vec<_>>(),
+            ["1"]
+        );
+        assert_eq!(
+            tag.get_vorbis("DISCTOTAL").unwrap().collect::<Vec<_>>(),
+            ["2"]
+        );
+
+        assert!(matches!(
+            set(&mut tag, &Field::Track, "x/12"),
+            Err(TagError::InvalidValue(..))
+        ));
+    }
+
+    #[test]
+    fn raw_fields_are_set_by_key() {
+        let mut tag = tag(&[("TITLE", "Song")]);
+        set(&mut tag, &Field::parse("TXXX.Mood").unwrap(), "Calm").unwrap();
+        set(
+            &mut tag,
+            &Field::parse("REPLAYGAIN_TRACK_GAIN").unwrap(),
+            "-6 dB",
+        )
+        .unwrap();
+
+        assert_eq!(
+            tag.get_vorbis("MOOD").unwrap().collect::<Vec<_>>(),
+            ["Calm"]
+        );
+        assert_eq!(
+            raw_fields(&tag),
+            [
+                Field::parse("MOOD").unwrap(),
+                Field::parse("REPLAYGAIN_TRACK_GAIN").unwrap()
+            ]
+        );
+
+        remove(&mut tag, &Field::parse("TXXX.mood").unwrap());
+        assert!(tag.get_vorbis("MOOD").is_none());
+    }
+}
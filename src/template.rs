@@ -0,0 +1,927 @@
+//! Templates describing how to build a new file name out of metadata.
+//!
+//! A template is plain text with a few special constructs:
+//!
+//! - `{field}` is replaced by the value of a field, e.g. `{title}` or a raw key like `{TPE3}`
+//! - `{albumartist|artist|Unknown}` tries each field in turn and falls back to the
+//!   last entry as literal text when it is not a field name or a key shaped like one
+//!   (`"..."` always is a literal)
+//! - `{track:02}` formats the value: `[[fill]align][0][width][.max_length]` where align
+//!   is one of `<`, `>` or `^` and the width is at most [`MAX_WIDTH`]
+//! - `[...]` is a conditional segment that is left out entirely when one of the fields
+//!   inside of it is empty
+//! - `/` starts a new directory and `\` escapes the next character
+use thiserror::Error;
+
+use crate::metadata::Field;
+
+/// Widest a value can be padded to, the longest name most file systems allow
+const MAX_WIDTH: usize = 255;
+
+/// Template reproducing the original `NN-Title` file names
+pub const DEFAULT_TEMPLATE: &str = "[{track}-]{title}";
+
+/// Template used when organizing files below a library root
+pub const DEFAULT_LIBRARY_TEMPLATE: &str =
+    "{albumartist|artist|Unknown Artist}/{album|Unknown Album}/[{track}-]{title}";
+
+/// Anything that can provide values for the fields used in a template
+pub trait Lookup {
+    fn lookup(&self, field: &Field) -> Option<String>;
+
+    /// The value of `field` for a placeholder with a format of its own such as `{track:>3}`,
+    /// which takes the place of any padding `lookup` does
+    fn lookup_unpadded(&self, field: &Field) -> Option<String> {
+        self.lookup(field)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TemplateErrorKind {
+    #[error("unknown field `{0}`")]
+    UnknownField(String),
+
+    #[error("empty field name")]
+    EmptyField,
+
+    #[error("invalid format specifier `{0}`")]
+    InvalidSpec(String),
+
+    #[error("width `{0}` is larger than {MAX_WIDTH}")]
+    WidthTooLarge(String),
+
+    #[error("unexpected character `{0}` in field")]
+    UnexpectedChar(char),
+
+    #[error("unclosed `{{`")]
+    UnclosedBrace,
+
+    #[error("unmatched `}}`")]
+    UnmatchedBrace,
+
+    #[error("unclosed `[`")]
+    UnclosedBracket,
+
+    #[error("unmatched `]`")]
+    UnmatchedBracket,
+
+    #[error("unclosed `\"`")]
+    UnclosedQuote,
+
+    #[error("nothing left to escape")]
+    TrailingEscape,
+}
+
+/// An error found while parsing a template, pointing at the offending position
+#[derive(Error, Debug)]
+#[error("invalid template: {kind} at position {}\n    {template}\n    {:>width$}", .position + 1, "^", width = .position + 1)]
+pub struct TemplateError {
+    pub kind: TemplateErrorKind,
+    pub template: String,
+    /// Position of the error in characters
+    pub position: usize,
+}
+
+/// A parsed template
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Separator,
+    Placeholder(Placeholder),
+    Optional(Vec<Node>),
+}
+
+#[derive(Debug, Clone)]
+struct Placeholder {
+    alternatives: Vec<Alternative>,
+    spec: Spec,
+}
+
+#[derive(Debug, Clone)]
+enum Alternative {
+    Field(Field),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<Align>,
+    width: usize,
+    max_length: Option<usize>,
+}
+
+/// The result of rendering a template
+#[derive(Debug, Default)]
+pub struct Rendered {
+    /// Path components of the new name, never empty strings
+    pub components: Vec<String>,
+    /// Fields that were filled in from metadata, literal fallbacks don't count
+    pub fields: Vec<Field>,
+}
+
+enum Segment {
+    Text(String),
+    Separator,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, TemplateError> {
+        let mut parser = Parser {
+            template,
+            chars: template.chars().collect(),
+            pos: 0,
+        };
+
+        let nodes = parser.parse_nodes(None)?;
+
+        Ok(Template { nodes })
+    }
+
+    /// Fills in the template with values from `source`
+    pub fn render(&self, source: &(impl Lookup + ?Sized)) -> Rendered {
+        let mut segments = Vec::new();
+        let mut fields = Vec::new();
+
+        render_nodes(&self.nodes, source, &mut segments, &mut fields);
+
+        let mut components = vec![String::new()];
+        for segment in segments {
+            match segment {
+                Segment::Text(text) => components.last_mut().unwrap().push_str(&text),
+                Segment::Separator => components.push(String::new()),
+            }
+        }
+        components.retain(|component| !component.is_empty());
+
+        Rendered { components, fields }
+    }
+
+    /// Whether `field` is one of the alternatives of a placeholder anywhere in the template
+    pub fn uses(&self, field: &Field) -> bool {
+        fn uses(nodes: &[Node], field: &Field) -> bool {
+            nodes.iter().any(|node| match node {
+                Node::Placeholder(placeholder) => placeholder
+                    .alternatives
+                    .iter()
+                    .any(|alternative| matches!(alternative, Alternative::Field(f) if f == field)),
+                Node::Optional(nodes) => uses(nodes, field),
+                Node::Text(_) | Node::Separator => false,
+            })
+        }
+
+        uses(&self.nodes, field)
+    }
+
+    /// Puts `prefix` in front of the file name, after the last `/` of the template
+    pub fn insert_before_name(&mut self, prefix: Template) {
+        let position = self
+            .nodes
+            .iter()
+            .rposition(|node| matches!(node, Node::Separator))
+            .map_or(0, |position| position + 1);
+
+        self.nodes.splice(position..position, prefix.nodes);
+    }
+
+    /// Matches `name` against the template, the reverse of rendering it: the value of each
+    /// field is picked out of the name, e.g. from `Artist/Album/03 - Title` with
+    /// `{artist}/{album}/{track} - {title}`.
+    ///
+    /// Fields never take a `/`, text fields take as little as possible and number fields
+    /// as many digits as possible.
+    /// Returns `None` when the name doesn't match.
+    pub fn extract(&self, name: &str) -> Option<Vec<(Field, String)>> {
+        let nodes: Vec<&Node> = self.nodes.iter().collect();
+        let mut values = Vec::new();
+
+        match_nodes(&nodes, name, &mut values).then_some(values)
+    }
+}
+
+/// Matches `text` against `nodes`, pushing the values of fields onto `values`.
+///
+/// `nodes` holds references so that the contents of conditional segments can be tried in
+/// front of the nodes following them.
+fn match_nodes(nodes: &[&Node], text: &str, values: &mut Vec<(Field, String)>) -> bool {
+    let Some((node, rest)) = nodes.split_first() else {
+        return text.is_empty();
+    };
+
+    match node {
+        Node::Text(literal) => text
+            .strip_prefix(literal.as_str())
+            .is_some_and(|text| match_nodes(rest, text, values)),
+        Node::Separator => text
+            .strip_prefix('/')
+            .is_some_and(|text| match_nodes(rest, text, values)),
+        Node::Optional(inner) => {
+            let count = values.len();
+            let with: Vec<&Node> = inner.iter().chain(rest.iter().copied()).collect();
+            if match_nodes(&with, text, values) {
+                return true;
+            }
+
+            values.truncate(count);
+            match_nodes(rest, text, values)
+        }
+        Node::Placeholder(placeholder) => {
+            let field = placeholder
+                .alternatives
+                .iter()
+                .find_map(|alternative| match alternative {
+                    Alternative::Field(field) => Some(field),
+                    Alternative::Literal(_) => None,
+                });
+
+            let Some(field) = field else {
+                // Nothing but literals, e.g. `{"Various"}`
+                return placeholder.alternatives.iter().any(|alternative| {
+                    let Alternative::Literal(literal) = alternative else {
+                        return false;
+                    };
+                    text.strip_prefix(literal.as_str())
+                        .is_some_and(|text| match_nodes(rest, text, values))
+                });
+            };
+
+            let numeric = matches!(
+                field,
+                Field::Year | Field::Track | Field::TrackTotal | Field::Disc | Field::DiscTotal
+            );
+
+            // Numbers may be padded with the fill character of their format, e.g. `{track:>3}`
+            let fill = (placeholder.spec.width > 0).then(|| placeholder.spec.fill.unwrap_or(' '));
+            let mut ends: Vec<usize> = text
+                .char_indices()
+                .take_while(|&(_, c)| {
+                    c != '/' && (!numeric || c.is_ascii_digit() || Some(c) == fill)
+                })
+                .map(|(i, c)| i + c.len_utf8())
+                .collect();
+
+            // Text takes as little as possible, giving later fields the rest, while numbers
+            // take all digits so that `{track}{title}` splits `03Title` after the 3
+            if numeric {
+                ends.reverse();
+            }
+
+            for end in ends {
+                let count = values.len();
+                if let Some(value) = placeholder.unformat(&text[..end]) {
+                    values.push((field.clone(), value));
+                }
+
+                if match_nodes(rest, &text[end..], values) {
+                    return true;
+                }
+                values.truncate(count);
+            }
+
+            false
+        }
+    }
+}
+
+/// Whether a raw key is shaped like one rather than like a word: a four character ID3 frame
+/// such as `TPE3`, a key with a description such as `TXXX.Mood` or one with an underscore
+/// such as `MUSICBRAINZ_ALBUMID`
+fn is_key_shaped(name: &str) -> bool {
+    name.len() == 4 || name.contains(['.', '_'])
+}
+
+/// Renders `nodes` into `out`, returning `false` when one of the fields was empty
+fn render_nodes(
+    nodes: &[Node],
+    source: &(impl Lookup + ?Sized),
+    out: &mut Vec<Segment>,
+    fields: &mut Vec<Field>,
+) -> bool {
+    let mut complete = true;
+
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push(Segment::Text(text.clone())),
+            Node::Separator => out.push(Segment::Separator),
+            Node::Placeholder(placeholder) => match placeholder.resolve(source) {
+                Some((value, field)) => {
+                    fields.extend(field.cloned());
+                    out.push(Segment::Text(value));
+                }
+                None => complete = false,
+            },
+            Node::Optional(nodes) => {
+                let mut inner = Vec::new();
+                let mut inner_fields = Vec::new();
+
+                if render_nodes(nodes, source, &mut inner, &mut inner_fields) {
+                    out.extend(inner);
+                    fields.extend(inner_fields);
+                }
+            }
+        }
+    }
+
+    complete
+}
+
+impl Placeholder {
+    /// Returns the formatted value and the field it came from, `None` for literals
+    fn resolve(&self, source: &(impl Lookup + ?Sized)) -> Option<(String, Option<&Field>)> {
+        self.alternatives.iter().find_map(|alternative| {
+            let (value, field) = match alternative {
+                Alternative::Field(field) if self.spec.is_empty() => {
+                    (source.lookup(field)?, Some(field))
+                }
+                Alternative::Field(field) => (source.lookup_unpadded(field)?, Some(field)),
+                Alternative::Literal(literal) => (literal.clone(), None),
+            };
+
+            if value.is_empty() {
+                return None;
+            }
+
+            Some((self.spec.apply(&value), field))
+        })
+    }
+
+    /// Recovers the value of the field from text it was rendered to, `None` when the text
+    /// is a literal fallback rather than a value
+    fn unformat(&self, text: &str) -> Option<String> {
+        let value = match self.spec.width {
+            0 => text,
+            _ => text.trim_matches(self.spec.fill.unwrap_or(' ')),
+        };
+
+        let fallback = self.alternatives.iter().any(
+            |alternative| matches!(alternative, Alternative::Literal(literal) if literal == value),
+        );
+
+        (!value.is_empty() && !fallback).then(|| value.to_string())
+    }
+}
+
+impl Spec {
+    /// Whether the placeholder has no format, e.g. `{track}` rather than `{track:02}`
+    fn is_empty(&self) -> bool {
+        self.fill.is_none() && self.align.is_none() && self.width == 0 && self.max_length.is_none()
+    }
+
+    fn apply(&self, value: &str) -> String {
+        let value: String = match self.max_length {
+            Some(max_length) => value.chars().take(max_length).collect(),
+            None => value.to_string(),
+        };
+
+        let length = value.chars().count();
+        if length >= self.width {
+            return value;
+        }
+
+        let fill = self.fill.unwrap_or(' ');
+        // Like Rust's own formatting, numbers are right aligned and text left aligned
+        let align = self.align.unwrap_or_else(|| {
+            if value.chars().all(|c| c.is_ascii_digit()) {
+                Align::Right
+            } else {
+                Align::Left
+            }
+        });
+
+        let padding = self.width - length;
+        let (left, right) = match align {
+            Align::Left => (0, padding),
+            Align::Right => (padding, 0),
+            Align::Center => (padding / 2, padding - padding / 2),
+        };
+
+        let mut padded = String::with_capacity(value.len() + padding);
+        padded.extend(std::iter::repeat_n(fill, left));
+        padded.push_str(&value);
+        padded.extend(std::iter::repeat_n(fill, right));
+
+        padded
+    }
+}
+
+struct Parser<'a> {
+    template: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, kind: TemplateErrorKind, position: usize) -> TemplateError {
+        TemplateError {
+            kind,
+            template: self.template.to_string(),
+            position,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Parses nodes up to the end of the template or, inside of a conditional segment
+    /// opened at `optional_start`, up to the closing `]`
+    fn parse_nodes(&mut self, optional_start: Option<usize>) -> Result<Vec<Node>, TemplateError> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+
+        fn flush(text: &mut String, nodes: &mut Vec<Node>) {
+            if !text.is_empty() {
+                nodes.push(Node::Text(std::mem::take(text)));
+            }
+        }
+
+        while let Some(c) = self.peek() {
+            let start = self.pos;
+            self.pos += 1;
+
+            match c {
+                '\\' => match self.peek() {
+                    Some(escaped) => {
+                        text.push(escaped);
+                        self.pos += 1;
+                    }
+                    None => return Err(self.error(TemplateErrorKind::TrailingEscape, start)),
+                },
+                '{' => {
+                    flush(&mut text, &mut nodes);
+                    nodes.push(Node::Placeholder(self.parse_placeholder(start)?));
+                }
+                '}' => return Err(self.error(TemplateErrorKind::UnmatchedBrace, start)),
+                '[' => {
+                    flush(&mut text, &mut nodes);
+                    nodes.push(Node::Optional(self.parse_nodes(Some(start))?));
+                }
+                ']' => {
+                    if optional_start.is_none() {
+                        return Err(self.error(TemplateErrorKind::UnmatchedBracket, start));
+                    }
+                    flush(&mut text, &mut nodes);
+                    return Ok(nodes);
+                }
+                '/' => {
+                    flush(&mut text, &mut nodes);
+                    nodes.push(Node::Separator);
+                }
+                _ => text.push(c),
+            }
+        }
+
+        if let Some(start) = optional_start {
+            return Err(self.error(TemplateErrorKind::UnclosedBracket, start));
+        }
+
+        flush(&mut text, &mut nodes);
+
+        Ok(nodes)
+    }
+
+    /// Parses the inside of a `{...}` placeholder opened at `start`
+    fn parse_placeholder(&mut self, start: usize) -> Result<Placeholder, TemplateError> {
+        let mut names = Vec::new();
+        let mut spec = Spec::default();
+
+        loop {
+            self.skip_whitespace();
+            let alternative_start = self.pos;
+
+            if self.peek() == Some('"') {
+                self.pos += 1;
+                names.push((
+                    self.parse_quoted(alternative_start)?,
+                    alternative_start,
+                    true,
+                ));
+            } else {
+                let mut name = String::new();
+                while let Some(c) = self.peek() {
+                    if matches!(c, '|' | ':' | '}' | '{' | '[' | ']' | '"') {
+                        break;
+                    }
+                    name.push(c);
+                    self.pos += 1;
+                }
+
+                let name = name.trim_end().to_string();
+                if name.is_empty() {
+                    return Err(self.error(TemplateErrorKind::EmptyField, alternative_start));
+                }
+                names.push((name, alternative_start, false));
+            }
+
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some('|') => self.pos += 1,
+                Some(':') => {
+                    self.pos += 1;
+                    spec = self.parse_spec(start)?;
+                    break;
+                }
+                Some('}') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(c) => return Err(self.error(TemplateErrorKind::UnexpectedChar(c), self.pos)),
+                None => return Err(self.error(TemplateErrorKind::UnclosedBrace, start)),
+            }
+        }
+
+        let last = names.len() - 1;
+        let alternatives = names
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, position, quoted))| {
+                if quoted {
+                    return Ok(Alternative::Literal(name));
+                }
+
+                let fallback = index == last && last > 0;
+                match Field::parse(&name) {
+                    // A trailing word in capitals is a fallback value as well, e.g.
+                    // `{artist|UNKNOWN}`, unless it is shaped like a key
+                    Some(Field::Raw { .. }) if fallback && !is_key_shaped(&name) => {
+                        Ok(Alternative::Literal(name))
+                    }
+                    Some(field) => Ok(Alternative::Field(field)),
+                    // A trailing unknown name is a fallback value, e.g. `{artist|Unknown}`
+                    None if fallback => Ok(Alternative::Literal(name)),
+                    None => Err(self.error(TemplateErrorKind::UnknownField(name), position)),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Placeholder { alternatives, spec })
+    }
+
+    /// Parses a literal up to the closing quote; the opening one is already consumed
+    fn parse_quoted(&mut self, start: usize) -> Result<String, TemplateError> {
+        let mut literal = String::new();
+
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '"' => return Ok(literal),
+                '\\' => {
+                    if let Some(escaped) = self.peek() {
+                        literal.push(escaped);
+                        self.pos += 1;
+                    }
+                }
+                _ => literal.push(c),
+            }
+        }
+
+        Err(self.error(TemplateErrorKind::UnclosedQuote, start))
+    }
+
+    /// Parses a format specifier up to and including the closing `}`
+    fn parse_spec(&mut self, start: usize) -> Result<Spec, TemplateError> {
+        let spec_start = self.pos;
+        let mut raw = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some('}') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(c) => {
+                    raw.push(c);
+                    self.pos += 1;
+                }
+                None => return Err(self.error(TemplateErrorKind::UnclosedBrace, start)),
+            }
+        }
+
+        let invalid = |offset: usize| {
+            self.error(
+                TemplateErrorKind::InvalidSpec(raw.iter().collect()),
+                spec_start + offset,
+            )
+        };
+
+        fn align(c: char) -> Option<Align> {
+            match c {
+                '<' => Some(Align::Left),
+                '>' => Some(Align::Right),
+                '^' => Some(Align::Center),
+                _ => None,
+            }
+        }
+
+        let mut spec = Spec::default();
+        let mut i = 0;
+
+        if let Some(a) = raw.get(1).copied().and_then(align) {
+            spec.fill = Some(raw[0]);
+            spec.align = Some(a);
+            i = 2;
+        } else if let Some(a) = raw.first().copied().and_then(align) {
+            spec.align = Some(a);
+            i = 1;
+        }
+
+        if raw.get(i) == Some(&'0') && spec.align.is_none() {
+            spec.fill = Some('0');
+            spec.align = Some(Align::Right);
+            i += 1;
+        }
+
+        let digits = |i: &mut usize| {
+            let begin = *i;
+            while raw.get(*i).is_some_and(char::is_ascii_digit) {
+                *i += 1;
+            }
+            raw[begin..*i]
+                .iter()
+                .collect::<String>()
+                .parse::<usize>()
+                .ok()
+        };
+
+        let width_start = i;
+        let width = digits(&mut i);
+        if i > width_start && width.is_none_or(|width| width > MAX_WIDTH) {
+            return Err(self.error(
+                TemplateErrorKind::WidthTooLarge(raw[width_start..i].iter().collect()),
+                spec_start + width_start,
+            ));
+        }
+        spec.width = width.unwrap_or(0);
+
+        if raw.get(i) == Some(&'.') {
+            i += 1;
+            match digits(&mut i) {
+                Some(max_length) => spec.max_length = Some(max_length),
+                None => return Err(invalid(i)),
+            }
+        }
+
+        if i != raw.len() {
+            return Err(invalid(i));
+        }
+
+        Ok(spec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fields and their values, standing in for the metadata of a file
+    struct Values(Vec<(Field, &'static str)>);
+
+    impl Lookup for Values {
+        fn lookup(&self, field: &Field) -> Option<String> {
+            self.0
+                .iter()
+                .find(|(known, _)| known == field)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    fn raw(key: &str) -> Field {
+        Field::parse(key).unwrap()
+    }
+
+    fn render(template: &str, values: Values) -> String {
+        Template::parse(template)
+            .unwrap()
+            .render(&values)
+            .components
+            .join("/")
+    }
+
+    #[test]
+    fn trailing_words_in_capitals_are_fallbacks() {
+        assert_eq!(render("{artist|UNKNOWN}", Values(vec![])), "UNKNOWN");
+        assert_eq!(render("{artist|VARIOUS}", Values(vec![])), "VARIOUS");
+        assert_eq!(
+            render("{artist|UNKNOWN}", Values(vec![(Field::Artist, "Artist")])),
+            "Artist"
+        );
+    }
+
+    #[test]
+    fn trailing_keys_are_fields() {
+        let values = Values(vec![
+            (raw("TPE3"), "Conductor"),
+            (raw("MUSICBRAINZ_ALBUMID"), "id"),
+            (raw("TXXX.Mood"), "Calm"),
+        ]);
+
+        assert_eq!(
+            render(
+                "{artist|TPE3}-{album|MUSICBRAINZ_ALBUMID}-{genre|TXXX.Mood}",
+                values
+            ),
+            "Conductor-id-Calm"
+        );
+        assert_eq!(render("{artist|TPE3}", Values(vec![])), "");
+    }
+
+    #[test]
+    fn keys_on_their_own_are_fields() {
+        let values = Values(vec![(raw("LABEL"), "Label")]);
+        assert_eq!(render("{LABEL}", values), "Label");
+    }
+
+    fn error(template: &str) -> (TemplateErrorKind, usize) {
+        let error = Template::parse(template).unwrap_err();
+        (error.kind, error.position)
+    }
+
+    #[test]
+    fn fields_are_filled_in() {
+        let values = Values(vec![(Field::Artist, "Artist"), (Field::Title, "Title")]);
+        assert_eq!(render("{artist} - {TITLE}", values), "Artist - Title");
+    }
+
+    #[test]
+    fn separators_start_directories() {
+        let values = Values(vec![(Field::Artist, "Artist"), (Field::Title, "Title")]);
+        let rendered = Template::parse("{artist}/{album}/{title}")
+            .unwrap()
+            .render(&values);
+
+        // Empty components are left out rather than kept as empty directories
+        assert_eq!(rendered.components, ["Artist", "Title"]);
+        assert_eq!(rendered.fields, [Field::Artist, Field::Title]);
+    }
+
+    #[test]
+    fn conditional_segments_need_all_their_fields() {
+        let template = "[{track}-]{title}[ ({year})]";
+        let values = Values(vec![(Field::Track, "03"), (Field::Title, "Title")]);
+        assert_eq!(render(template, values), "03-Title");
+
+        let values = Values(vec![(Field::Title, "Title"), (Field::Year, "2003")]);
+        assert_eq!(render(template, values), "Title (2003)");
+
+        let values = Values(vec![(Field::Title, "Title")]);
+        assert_eq!(render("[{artist} - {album}/]{title}", values), "Title");
+    }
+
+    #[test]
+    fn alternatives_are_tried_in_turn() {
+        let template = "{albumartist|artist|\"Various Artists\"}";
+        let values = Values(vec![(Field::Artist, "Artist")]);
+        assert_eq!(render(template, values), "Artist");
+        assert_eq!(render(template, Values(vec![])), "Various Artists");
+
+        let rendered = Template::parse("{artist|Unknown}")
+            .unwrap()
+            .render(&Values(vec![]));
+        assert!(rendered.fields.is_empty());
+    }
+
+    #[test]
+    fn specs_format_values() {
+        let values = || Values(vec![(Field::Track, "7"), (Field::Title, "Title")]);
+        assert_eq!(render("{track:02}", values()), "07");
+        assert_eq!(render("{track:>3}", values()), "  7");
+        assert_eq!(render("{title:*^9}", values()), "**Title**");
+        assert_eq!(render("{title:8}|", values()), "Title   |");
+        assert_eq!(render("{title:.3}", values()), "Tit");
+        assert_eq!(render("{title:2}", values()), "Title");
+        assert_eq!(render("{track:0255}", values()).len(), MAX_WIDTH);
+    }
+
+    #[test]
+    fn escapes_keep_special_characters() {
+        let values = Values(vec![(Field::Title, "Title")]);
+        assert_eq!(render("\\[{title}\\]\\{x\\}", values), "[Title]{x}");
+    }
+
+    #[test]
+    fn errors_point_at_their_position() {
+        assert!(matches!(
+            error("{title"),
+            (TemplateErrorKind::UnclosedBrace, 0)
+        ));
+        assert!(matches!(
+            error("a}"),
+            (TemplateErrorKind::UnmatchedBrace, 1)
+        ));
+        assert!(matches!(
+            error("[{title}"),
+            (TemplateErrorKind::UnclosedBracket, 0)
+        ));
+        assert!(matches!(
+            error("{title}]"),
+            (TemplateErrorKind::UnmatchedBracket, 7)
+        ));
+        assert!(matches!(error("{}"), (TemplateErrorKind::EmptyField, _)));
+        assert!(matches!(
+            error("{bogus}"),
+            (TemplateErrorKind::UnknownField(_), _)
+        ));
+        assert!(matches!(
+            error("{track:x}"),
+            (TemplateErrorKind::InvalidSpec(_), _)
+        ));
+        assert!(matches!(
+            error("{title:>256}"),
+            (TemplateErrorKind::WidthTooLarge(_), 8)
+        ));
+        assert!(matches!(
+            error("{title:99999999999999999999999}"),
+            (TemplateErrorKind::WidthTooLarge(_), 7)
+        ));
+        assert!(matches!(
+            error("{\"open}"),
+            (TemplateErrorKind::UnclosedQuote, _)
+        ));
+        assert!(matches!(
+            error("end\\"),
+            (TemplateErrorKind::TrailingEscape, 3)
+        ));
+    }
+
+    fn extract(template: &str, name: &str) -> Option<Vec<(Field, String)>> {
+        Template::parse(template).unwrap().extract(name)
+    }
+
+    #[test]
+    fn extract_picks_fields_out_of_names() {
+        assert_eq!(
+            extract(
+                "{artist}/{album}/{track} - {title}",
+                "Artist/Album/03 - A - B"
+            ),
+            Some(vec![
+                (Field::Artist, "Artist".to_string()),
+                (Field::Album, "Album".to_string()),
+                (Field::Track, "03".to_string()),
+                (Field::Title, "A - B".to_string()),
+            ])
+        );
+        assert_eq!(extract("{artist}/{title}", "Title"), None);
+        assert_eq!(extract("{track} - {title}", "Intro - Title"), None);
+    }
+
+    #[test]
+    fn extract_numbers_take_all_digits() {
+        assert_eq!(
+            extract("{track}{title}", "103Title"),
+            Some(vec![
+                (Field::Track, "103".to_string()),
+                (Field::Title, "Title".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn extract_skips_conditional_segments_and_fallbacks() {
+        assert_eq!(
+            extract("[{track}-]{title}", "Title"),
+            Some(vec![(Field::Title, "Title".to_string())])
+        );
+        assert_eq!(
+            extract("[{track}-]{title}", "07-Title"),
+            Some(vec![
+                (Field::Track, "07".to_string()),
+                (Field::Title, "Title".to_string()),
+            ])
+        );
+        assert_eq!(
+            extract("{artist|Unknown}/{title}", "Unknown/Title"),
+            Some(vec![(Field::Title, "Title".to_string())])
+        );
+    }
+
+    #[test]
+    fn extract_undoes_padding() {
+        assert_eq!(
+            extract("{track:>3}. {title:_<8}", "  7. Title___"),
+            Some(vec![
+                (Field::Track, "7".to_string()),
+                (Field::Title, "Title".to_string()),
+            ])
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Padding track numbers so that the files of an album sort in order.
+//!
+//! The width comes from the number of tracks of the album: its track total when the files
+//! store one, otherwise the number of files of the album in the same run.
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::metadata::{self, Field, Metadata};
+use crate::template::Lookup;
+
+/// Width used for files that neither belong to an album nor store a track total
+const DEFAULT_WIDTH: usize = 2;
+
+/// Metadata with track numbers padded with zeros, and track 0 left out. Placeholders with
+/// a format of their own get the number as it is.
+pub struct Padded<'a, L: ?Sized> {
+    pub source: &'a L,
+    pub width: usize,
+}
+
+impl<L: Lookup + ?Sized> Lookup for Padded<'_, L> {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        match field {
+            Field::Track => match number(self.source, &Field::Track)? {
+                0 => None,
+                track => Some(format!("{:0width$}", track, width = self.width)),
+            },
+            _ => self.source.lookup(field),
+        }
+    }
+
+    fn lookup_unpadded(&self, field: &Field) -> Option<String> {
+        match field {
+            Field::Track => match number(self.source, &Field::Track)? {
+                0 => None,
+                track => Some(track.to_string()),
+            },
+            _ => self.source.lookup_unpadded(field),
+        }
+    }
+}
+
+/// Works out the track number width of each file, `fixed` overriding it for all files.
+///
+/// All files of an album get the same width, enough for the highest of its track total,
+/// its number of files and its track numbers.
+pub fn widths(files: &[(PathBuf, Box<dyn Metadata>)], fixed: Option<usize>) -> Vec<usize> {
+    if let Some(width) = fixed {
+        return vec![width; files.len()];
+    }
+
+    let mut counts: HashMap<(String, String), u32> = HashMap::new();
+    let mut highest: HashMap<(String, String), u32> = HashMap::new();
+
+    for (_, metadata) in files {
+        let metadata = metadata.as_ref();
+        let Some(album) = metadata::album(metadata) else {
+            continue;
+        };
+
+        *counts.entry(album.clone()).or_default() += 1;
+        let highest = highest.entry(album).or_default();
+        *highest = (*highest).max(tracks(metadata).unwrap_or(0));
+    }
+
+    files
+        .iter()
+        .map(|(_, metadata)| {
+            let metadata = metadata.as_ref();
+            match metadata::album(metadata) {
+                Some(album) => digits(counts[&album].max(highest[&album])),
+                None if number(metadata, &Field::TrackTotal).is_some() => {
+                    digits(tracks(metadata).unwrap_or(0))
+                }
+                None => digits(tracks(metadata).unwrap_or(0)).max(DEFAULT_WIDTH),
+            }
+        })
+        .collect()
+}
+
+/// The highest of the track total and the track number of a file
+fn tracks(metadata: &dyn Metadata) -> Option<u32> {
+    let total = number(metadata, &Field::TrackTotal);
+    let track = number(metadata, &Field::Track);
+
+    total.max(track)
+}
+
+fn number<L: Lookup + ?Sized>(source: &L, field: &Field) -> Option<u32> {
+    source.lookup(field)?.trim().parse().ok()
+}
+
+fn digits(number: u32) -> usize {
+    number.to_string().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Template;
+
+    struct Track(&'static str);
+
+    impl Lookup for Track {
+        fn lookup(&self, field: &Field) -> Option<String> {
+            (*field == Field::Track).then(|| self.0.to_string())
+        }
+    }
+
+    fn render(template: &str, track: &'static str, width: usize) -> String {
+        let padded = Padded {
+            source: &Track(track),
+            width,
+        };
+        Template::parse(template)
+            .unwrap()
+            .render(&padded)
+            .components
+            .join("/")
+    }
+
+    #[test]
+    fn tracks_are_padded_to_the_width() {
+        assert_eq!(render("{track}", "7", 1), "7");
+        assert_eq!(render("{track}", "7", 2), "07");
+        assert_eq!(render("{track}", "7", 3), "007");
+        assert_eq!(render("[{track}-]x", "0", 2), "x");
+    }
+
+    #[test]
+    fn formats_replace_the_padding() {
+        assert_eq!(render("{track:>3}", "7", 2), "  7");
+        assert_eq!(render("{track:02}", "7", 3), "07");
+        assert_eq!(render("{track:02}", "123", 3), "123");
+        assert_eq!(render("{track:<3}|{track}", "7", 2), "7  |07");
+    }
+}
@@ -0,0 +1,433 @@
+//! The state of the TUI: the albums loaded, what is selected and the changes that haven't
+//! been saved yet.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::error::FmmdError;
+use crate::metadata::{Field, Metadata};
+use crate::plan::ConflictPolicy;
+use crate::rename;
+use crate::sanitize::Sanitizer;
+use crate::tag;
+use crate::template::{Lookup, Template};
+use crate::tracks::Padded;
+
+/// Fields shown as columns of the track table, in order
+pub const COLUMNS: &[Field] = &[
+    Field::Track,
+    Field::Title,
+    Field::Artist,
+    Field::Album,
+    Field::AlbumArtist,
+    Field::Year,
+    Field::Genre,
+];
+
+pub struct Track {
+    pub file: PathBuf,
+    pub metadata: Box<dyn Metadata>,
+    /// Values changed in the TUI, an empty value removes the field
+    pub changes: Vec<(Field, String)>,
+}
+
+impl Track {
+    pub fn changed(&self, field: &Field) -> bool {
+        self.changes.iter().any(|(changed, _)| changed == field)
+    }
+
+    /// Changes a field, forgetting the change when it sets the value back to the original
+    pub fn set(&mut self, field: &Field, value: &str) {
+        self.changes.retain(|(changed, _)| changed != field);
+
+        if self.metadata.lookup(field).unwrap_or_default() != value {
+            self.changes.push((field.clone(), value.to_string()));
+        }
+    }
+}
+
+/// Values with the changes made in the TUI applied
+impl Lookup for Track {
+    fn lookup(&self, field: &Field) -> Option<String> {
+        match self.changes.iter().find(|(changed, _)| changed == field) {
+            Some((_, value)) => Some(value.clone()).filter(|value| !value.is_empty()),
+            None => self.metadata.lookup(field),
+        }
+    }
+}
+
+/// The files of a directory
+pub struct Album {
+    pub dir: PathBuf,
+    pub tracks: Vec<Track>,
+    /// Whether the files get renamed after the template on save
+    pub rename: bool,
+}
+
+impl Album {
+    pub fn unsaved(&self) -> bool {
+        self.rename || self.tracks.iter().any(|track| !track.changes.is_empty())
+    }
+
+    /// Track number width for the preview, see [`crate::tracks`]
+    fn track_width(&self) -> usize {
+        let number = |track: &Track, field: &Field| -> Option<u32> {
+            track.lookup(field)?.trim().parse().ok()
+        };
+
+        let highest = self
+            .tracks
+            .iter()
+            .filter_map(|track| number(track, &Field::TrackTotal).max(number(track, &Field::Track)))
+            .max()
+            .unwrap_or(0)
+            .max(self.tracks.len() as u32);
+
+        highest.to_string().len()
+    }
+}
+
+/// A line of the directory tree, which is an album when files were found in it
+pub struct TreeEntry {
+    pub depth: usize,
+    pub name: String,
+    pub album: Option<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    Albums,
+    Tracks,
+}
+
+pub enum Mode {
+    Normal,
+    /// Editing the selected cell
+    Edit {
+        input: String,
+    },
+    /// Asking for `field=value` to set on all tracks of the album
+    SetField {
+        input: String,
+    },
+}
+
+/// What the event loop has to do after a key press
+pub enum Command {
+    Save,
+    Quit,
+}
+
+pub struct App {
+    pub albums: Vec<Album>,
+    pub tree: Vec<TreeEntry>,
+    pub album: usize,
+    pub track: usize,
+    pub column: usize,
+    pub focus: Focus,
+    pub mode: Mode,
+    pub template: Template,
+    pub sanitizer: Sanitizer,
+    /// How renames to names that are taken are resolved
+    pub on_conflict: ConflictPolicy,
+    pub message: Option<String>,
+    /// Set by the first `q` when there are unsaved changes
+    quitting: bool,
+}
+
+impl App {
+    pub fn new(
+        files: Vec<(PathBuf, Box<dyn Metadata>)>,
+        template: Template,
+        sanitizer: Sanitizer,
+        on_conflict: ConflictPolicy,
+    ) -> App {
+        let mut albums: BTreeMap<PathBuf, Vec<Track>> = BTreeMap::new();
+        for (file, metadata) in files {
+            let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+            albums.entry(dir).or_default().push(Track {
+                file,
+                metadata,
+                changes: Vec::new(),
+            });
+        }
+
+        let albums: Vec<Album> = albums
+            .into_iter()
+            .map(|(dir, tracks)| Album {
+                dir,
+                tracks,
+                rename: false,
+            })
+            .collect();
+
+        App {
+            tree: tree(&albums),
+            albums,
+            album: 0,
+            track: 0,
+            column: 0,
+            focus: Focus::Albums,
+            mode: Mode::Normal,
+            template,
+            sanitizer,
+            on_conflict,
+            message: None,
+            quitting: false,
+        }
+    }
+
+    pub fn unsaved(&self) -> bool {
+        self.albums.iter().any(Album::unsaved)
+    }
+
+    /// The name the template gives a track, relative to its directory
+    pub fn preview(&self, album: &Album, track: &Track) -> Result<PathBuf, FmmdError> {
+        let padded = Padded {
+            source: track,
+            width: album.track_width(),
+        };
+
+        let (target, _) = rename::get_filename(
+            &padded,
+            &track.file,
+            &album.dir,
+            &self.template,
+            &self.sanitizer,
+        )?;
+
+        Ok(target)
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Command> {
+        let mode = std::mem::replace(&mut self.mode, Mode::Normal);
+
+        match mode {
+            Mode::Normal => return self.handle_normal(key),
+            Mode::Edit { mut input } => match key.code {
+                KeyCode::Enter => {
+                    let field = &COLUMNS[self.column];
+                    if let Some(track) = self.current_track_mut() {
+                        track.set(field, input.trim());
+                    }
+                }
+                KeyCode::Esc => {}
+                _ => {
+                    edit_input(&mut input, key);
+                    self.mode = Mode::Edit { input };
+                }
+            },
+            Mode::SetField { mut input } => match key.code {
+                KeyCode::Enter => self.set_album_field(&input),
+                KeyCode::Esc => {}
+                _ => {
+                    edit_input(&mut input, key);
+                    self.mode = Mode::SetField { input };
+                }
+            },
+        }
+
+        None
+    }
+
+    fn handle_normal(&mut self, key: KeyEvent) -> Option<Command> {
+        self.message = None;
+        if key.code != KeyCode::Char('q') {
+            self.quitting = false;
+        }
+
+        match key.code {
+            KeyCode::Char('q') if self.unsaved() && !self.quitting => {
+                self.quitting = true;
+                self.message = Some("Unsaved changes, press q again to quit anyway".to_string());
+            }
+            KeyCode::Char('q') => return Some(Command::Quit),
+            KeyCode::Char('s') => return Some(Command::Save),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(Command::Quit);
+            }
+            KeyCode::Tab | KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Albums => Focus::Tracks,
+                    Focus::Tracks => Focus::Albums,
+                };
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Left | KeyCode::Char('h') => self.column = self.column.saturating_sub(1),
+            KeyCode::Right | KeyCode::Char('l') => {
+                self.column = (self.column + 1).min(COLUMNS.len() - 1);
+            }
+            KeyCode::Char('K') => self.move_track(-1),
+            KeyCode::Char('J') => self.move_track(1),
+            KeyCode::Enter | KeyCode::Char('e') if self.focus == Focus::Tracks => {
+                if let Some(track) = self.current_track() {
+                    let input = track.lookup(&COLUMNS[self.column]).unwrap_or_default();
+                    self.mode = Mode::Edit { input };
+                }
+            }
+            KeyCode::Enter => self.focus = Focus::Tracks,
+            KeyCode::Char('r') => self.renumber(),
+            KeyCode::Char('f') => {
+                self.mode = Mode::SetField {
+                    input: String::new(),
+                }
+            }
+            KeyCode::Char('t') => {
+                if let Some(album) = self.albums.get_mut(self.album) {
+                    album.rename = !album.rename;
+                }
+            }
+            KeyCode::Char('x') => {
+                if let Some(album) = self.albums.get_mut(self.album) {
+                    album.rename = false;
+                    for track in &mut album.tracks {
+                        track.changes.clear();
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn current_track(&self) -> Option<&Track> {
+        self.albums.get(self.album)?.tracks.get(self.track)
+    }
+
+    fn current_track_mut(&mut self) -> Option<&mut Track> {
+        self.albums.get_mut(self.album)?.tracks.get_mut(self.track)
+    }
+
+    fn move_selection(&mut self, step: isize) {
+        match self.focus {
+            Focus::Albums => {
+                let albums = self.albums.len();
+                self.album = self
+                    .album
+                    .saturating_add_signed(step)
+                    .min(albums.saturating_sub(1));
+                self.track = 0;
+            }
+            Focus::Tracks => {
+                let tracks = self
+                    .albums
+                    .get(self.album)
+                    .map_or(0, |album| album.tracks.len());
+                self.track = self
+                    .track
+                    .saturating_add_signed(step)
+                    .min(tracks.saturating_sub(1));
+            }
+        }
+    }
+
+    /// Moves the selected track up or down, changing the order [`App::renumber`] uses
+    fn move_track(&mut self, step: isize) {
+        let Some(album) = self.albums.get_mut(self.album) else {
+            return;
+        };
+
+        let other = self.track.saturating_add_signed(step);
+        if self.focus == Focus::Tracks && other < album.tracks.len() && other != self.track {
+            album.tracks.swap(self.track, other);
+            self.track = other;
+        }
+    }
+
+    /// Numbers the tracks of the album in the order they are listed in
+    fn renumber(&mut self) {
+        let Some(album) = self.albums.get_mut(self.album) else {
+            return;
+        };
+
+        let total = album.tracks.len().to_string();
+        for (index, track) in album.tracks.iter_mut().enumerate() {
+            track.set(&Field::Track, &(index + 1).to_string());
+            track.set(&Field::TrackTotal, &total);
+        }
+    }
+
+    fn set_album_field(&mut self, input: &str) {
+        let (field, value) = match tag::parse_assignment(input.trim()) {
+            Ok(assignment) => assignment,
+            Err(error) => {
+                self.message = Some(error);
+                return;
+            }
+        };
+
+        if let Some(album) = self.albums.get_mut(self.album) {
+            for track in &mut album.tracks {
+                track.set(&field, &value);
+            }
+        }
+    }
+}
+
+fn edit_input(input: &mut String, key: KeyEvent) {
+    match key.code {
+        KeyCode::Char(c) => input.push(c),
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        _ => {}
+    }
+}
+
+/// Lays out the directories of the albums as a tree, including the directories between
+/// them that don't hold any files themselves
+fn tree(albums: &[Album]) -> Vec<TreeEntry> {
+    let root = common_root(albums.iter().map(|album| album.dir.as_path()));
+    let mut entries: Vec<TreeEntry> = Vec::new();
+    let mut shown: Vec<PathBuf> = Vec::new();
+
+    for (index, album) in albums.iter().enumerate() {
+        let relative = album.dir.strip_prefix(&root).unwrap_or(&album.dir);
+        let components: Vec<_> = relative.components().collect();
+
+        for depth in 0..components.len() {
+            let path: PathBuf = components[..=depth].iter().collect();
+            if shown.contains(&path) {
+                continue;
+            }
+
+            let last = depth + 1 == components.len();
+            entries.push(TreeEntry {
+                depth,
+                name: components[depth].as_os_str().to_string_lossy().to_string(),
+                album: last.then_some(index),
+            });
+            shown.push(path);
+        }
+
+        if components.is_empty() {
+            entries.push(TreeEntry {
+                depth: 0,
+                name: root.display().to_string(),
+                album: Some(index),
+            });
+        }
+    }
+
+    entries
+}
+
+fn common_root<'a>(mut dirs: impl Iterator<Item = &'a Path>) -> PathBuf {
+    let Some(first) = dirs.next() else {
+        return PathBuf::new();
+    };
+
+    let mut root = first.to_path_buf();
+    for dir in dirs {
+        while !dir.starts_with(&root) {
+            if !root.pop() {
+                return PathBuf::new();
+            }
+        }
+    }
+
+    root
+}
@@ -0,0 +1,172 @@
+//! Rendering the state of the TUI
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Cell, List, ListItem, ListState, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+
+use super::app::{Album, App, Focus, Mode, Track, COLUMNS};
+use crate::template::Lookup;
+
+const HELP: &str = "tab: switch pane  arrows/hjkl: move  enter/e: edit  J/K: move track  \
+r: renumber  f: set field  t: apply template  x: discard  s: save  q: quit";
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [main, status, help] = Layout::vertical([
+        Constraint::Min(3),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let [albums, tracks] =
+        Layout::horizontal([Constraint::Percentage(25), Constraint::Percentage(75)]).areas(main);
+
+    draw_albums(frame, app, albums);
+    draw_tracks(frame, app, tracks);
+    draw_status(frame, app, status);
+    frame.render_widget(Paragraph::new(HELP).dark_gray(), help);
+}
+
+fn block(title: &str, focused: bool) -> Block<'_> {
+    let block = Block::bordered().title(title);
+    match focused {
+        true => block.border_style(Style::new().cyan()),
+        false => block,
+    }
+}
+
+fn draw_albums(frame: &mut Frame, app: &App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .tree
+        .iter()
+        .map(|entry| {
+            let indent = "  ".repeat(entry.depth);
+            match entry.album.map(|index| &app.albums[index]) {
+                Some(album) => {
+                    let mut line =
+                        Line::from(format!("{}{} ({})", indent, entry.name, album.tracks.len()));
+                    if album.unsaved() {
+                        line.push_span(Span::from(" *").yellow());
+                    }
+                    ListItem::new(line)
+                }
+                None => ListItem::new(format!("{}{}/", indent, entry.name)).dark_gray(),
+            }
+        })
+        .collect();
+
+    let selected = app
+        .tree
+        .iter()
+        .position(|entry| entry.album == Some(app.album));
+
+    let list = List::new(items)
+        .block(block("Albums", app.focus == Focus::Albums))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+
+    frame.render_stateful_widget(
+        list,
+        area,
+        &mut ListState::default().with_selected(selected),
+    );
+}
+
+fn draw_tracks(frame: &mut Frame, app: &App, area: Rect) {
+    let Some(album) = app.albums.get(app.album) else {
+        frame.render_widget(block("Tracks", false), area);
+        return;
+    };
+
+    let header = COLUMNS
+        .iter()
+        .map(ToString::to_string)
+        .chain(["new name".to_string()])
+        .map(|name| Cell::from(name).bold());
+
+    let rows = album.tracks.iter().map(|track| row(app, album, track));
+
+    let widths = [
+        Constraint::Length(5),
+        Constraint::Fill(3),
+        Constraint::Fill(2),
+        Constraint::Fill(2),
+        Constraint::Fill(2),
+        Constraint::Length(10),
+        Constraint::Fill(1),
+        Constraint::Fill(3),
+    ];
+
+    let title = match album.rename {
+        true => format!("{} (renaming on save)", album.dir.display()),
+        false => album.dir.display().to_string(),
+    };
+
+    let focused = app.focus == Focus::Tracks;
+    let table = Table::new(rows, widths)
+        .header(Row::new(header))
+        .block(block(&title, focused))
+        .row_highlight_style(Style::new().bg(Color::DarkGray))
+        .cell_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+
+    let mut state = TableState::default();
+    if focused {
+        state.select(Some(app.track));
+        state.select_column(Some(app.column));
+    }
+
+    frame.render_stateful_widget(table, area, &mut state);
+}
+
+fn row<'a>(app: &App, album: &Album, track: &'a Track) -> Row<'a> {
+    let mut cells: Vec<Cell> = COLUMNS
+        .iter()
+        .map(|field| {
+            let value = track.lookup(field).unwrap_or_default();
+            match track.changed(field) {
+                true => Cell::from(value).yellow(),
+                false => Cell::from(value),
+            }
+        })
+        .collect();
+
+    let preview = match app.preview(album, track) {
+        Ok(target) => {
+            let name = target
+                .strip_prefix(&album.dir)
+                .unwrap_or(&target)
+                .display()
+                .to_string();
+            match (album.rename, target == track.file) {
+                (_, true) => Cell::from(name).dark_gray(),
+                (true, false) => Cell::from(name).green(),
+                (false, false) => Cell::from(name),
+            }
+        }
+        Err(error) => Cell::from(error.to_string()).red(),
+    };
+    cells.push(preview);
+
+    Row::new(cells)
+}
+
+fn draw_status(frame: &mut Frame, app: &App, area: Rect) {
+    let (prompt, input) = match &app.mode {
+        Mode::Edit { input } => (format!("{}: ", COLUMNS[app.column]), input),
+        Mode::SetField { input } => ("Set for the whole album (field=value): ".to_string(), input),
+        Mode::Normal => {
+            let line = match &app.message {
+                Some(message) => Line::from(message.as_str()).yellow(),
+                None if app.unsaved() => Line::from("Unsaved changes, press s to save"),
+                None => Line::default(),
+            };
+            frame.render_widget(Paragraph::new(line), area);
+            return;
+        }
+    };
+
+    let text = format!("{}{}", prompt, input);
+    let cursor = text.chars().count() as u16;
+    frame.render_widget(Paragraph::new(text), area);
+    frame.set_cursor_position(Position::new(area.x + cursor, area.y));
+}
@@ -0,0 +1,467 @@
+//! The `tui` subcommand, a full-screen view of a library for fixing tags and names.
+//!
+//! Albums are the directories files were found in. Changes made to tags and the renames
+//! of albums the template is applied to are only written when saving.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use ratatui::DefaultTerminal;
+
+use crate::config;
+use crate::error::FmmdError;
+use crate::files::{create_dirs, move_file};
+use crate::journal::{Journal, JournalError};
+use crate::metadata;
+use crate::plan::{execute, same_file, Action, ConflictPolicy, Plan, PlannedRename};
+use crate::report::{print_error, Status};
+use crate::sanitize::Sanitizer;
+use crate::tag::{self, WriteArgs};
+use crate::template::{Template, DEFAULT_TEMPLATE};
+
+mod app;
+mod draw;
+
+use app::{App, Command};
+
+#[derive(Args)]
+pub struct TuiArgs {
+    #[command(flatten)]
+    write: WriteArgs,
+
+    /// Template the new names are previewed and applied with, defaults to the configured one
+    #[arg(short, long)]
+    template: Option<String>,
+
+    /// Use the settings of this profile from the configuration files
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+}
+
+pub fn tui(args: &TuiArgs) -> Result<Status, FmmdError> {
+    let path = args.write.input.paths().first().map(PathBuf::as_path);
+    let settings = config::load(path, args.profile.as_deref())?;
+
+    let template = args.template.as_deref().or(settings.template.as_deref());
+    let template = Template::parse(template.unwrap_or(DEFAULT_TEMPLATE))?;
+    let sanitizer = Sanitizer::new(settings.sanitize.unwrap_or_default(), settings.replace);
+
+    let mut files = Vec::new();
+    let mut errors = 0;
+
+    let paths = args.write.input.collect_with(|file, error| {
+        print_error(file, error);
+        errors += 1;
+    });
+    for file in paths {
+        match metadata::read(&file) {
+            Ok(metadata) => files.push((file, metadata)),
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    let status = Status::from_counts(errors, files.len());
+    if files.is_empty() {
+        return Ok(status);
+    }
+
+    let on_conflict = settings.on_conflict.unwrap_or_default();
+    let mut app = App::new(files, template, sanitizer, on_conflict);
+    let mut terminal = ratatui::try_init().map_err(FmmdError::Terminal)?;
+    let result = run(&mut terminal, &mut app, args);
+    ratatui::restore();
+
+    result.map(|_| status)
+}
+
+fn run(terminal: &mut DefaultTerminal, app: &mut App, args: &TuiArgs) -> Result<(), FmmdError> {
+    loop {
+        terminal
+            .draw(|frame| draw::draw(frame, app))
+            .map_err(FmmdError::Terminal)?;
+
+        let Event::Key(key) = event::read().map_err(FmmdError::Terminal)? else {
+            continue;
+        };
+
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match app.handle_key(key) {
+            Some(Command::Save) => app.message = Some(save(app, &args.write)),
+            Some(Command::Quit) => return Ok(()),
+            None => {}
+        }
+    }
+}
+
+/// Writes the changed tags, then renames the files of albums the template is applied to.
+/// Returns what happened, to be shown in the status line.
+fn save(app: &mut App, args: &WriteArgs) -> String {
+    let changed: usize = app
+        .albums
+        .iter()
+        .flat_map(|album| &album.tracks)
+        .filter(|track| !track.changes.is_empty())
+        .count();
+
+    if args.dry_run {
+        let renames = renames(app);
+        return format!(
+            "Dry run, {} files would be tagged, {} renamed and {} skipped",
+            changed,
+            renames.count(Action::Rename),
+            renames.skipped
+        );
+    }
+
+    let mut errors = Vec::new();
+    let mut tagged = 0;
+
+    for track in app.albums.iter_mut().flat_map(|album| &mut album.tracks) {
+        if track.changes.is_empty() {
+            continue;
+        }
+
+        let result = tag::set_fields(&track.file, args, &track.changes, false)
+            .and_then(|_| metadata::read(&track.file));
+
+        match result {
+            Ok(metadata) => {
+                track.metadata = metadata;
+                track.changes.clear();
+                tagged += 1;
+            }
+            Err(error) => errors.push(format!("{}: \"{}\"", error, track.file.display())),
+        }
+    }
+
+    let (renamed, rename_errors) = rename(app, Journal::new);
+    errors.extend(rename_errors);
+
+    match errors.first() {
+        Some(error) => format!(
+            "{} tagged, {} renamed, {} errors, the first: {}",
+            tagged,
+            renamed,
+            errors.len(),
+            error
+        ),
+        None => format!("{} tagged, {} renamed", tagged, renamed),
+    }
+}
+
+/// The renames of the files of albums the template is applied to
+struct Renames {
+    plan: Plan,
+    /// The album and track of each rename of the plan
+    tracks: Vec<(usize, usize)>,
+    /// Why files are left where they are
+    errors: Vec<String>,
+    /// The number of files left where they are
+    skipped: usize,
+    /// Albums with files left where they are
+    pending: HashSet<usize>,
+}
+
+impl Renames {
+    fn count(&self, action: Action) -> usize {
+        let renames = self.plan.renames.iter();
+        renames.filter(|rename| rename.action == action).count()
+    }
+}
+
+/// Plans the renames of the albums the template is applied to, resolving conflicts after
+/// the configured policy. Files whose new name can't be built are left out.
+fn renames(app: &App) -> Renames {
+    let mut renames = Renames {
+        plan: Plan::default(),
+        tracks: Vec::new(),
+        errors: Vec::new(),
+        skipped: 0,
+        pending: HashSet::new(),
+    };
+
+    for (album_index, album) in app.albums.iter().enumerate() {
+        if !album.rename {
+            continue;
+        }
+
+        for (track_index, track) in album.tracks.iter().enumerate() {
+            match app.preview(album, track) {
+                Ok(target) => {
+                    // The tracks keep their tags, the plan only decides on the names
+                    let metadata = Box::new(id3::Tag::new());
+                    renames
+                        .plan
+                        .add(track.file.clone(), target, Vec::new(), metadata);
+                    renames.tracks.push((album_index, track_index));
+                }
+                Err(error) => {
+                    renames
+                        .errors
+                        .push(format!("{}: \"{}\"", error, track.file.display()));
+                    renames.skipped += 1;
+                    renames.pending.insert(album_index);
+                }
+            }
+        }
+    }
+
+    let conflicts = renames.plan.resolve_conflicts(app.on_conflict);
+    if app.on_conflict == ConflictPolicy::Fail && conflicts > 0 {
+        renames
+            .errors
+            .insert(0, FmmdError::Conflicts(conflicts).to_string());
+    }
+
+    for (rename, (album, _)) in renames.plan.renames.iter_mut().zip(&renames.tracks) {
+        if app.on_conflict == ConflictPolicy::Fail && conflicts > 0 {
+            rename.action = match rename.action {
+                Action::Unchanged => Action::Unchanged,
+                _ => Action::Skip,
+            };
+        }
+
+        if rename.action != Action::Skip {
+            continue;
+        }
+
+        if let Some(conflict) = &rename.conflict {
+            renames.errors.push(format!(
+                "Conflict, {}: \"{}\"",
+                conflict,
+                rename.source.display()
+            ));
+        }
+        renames.skipped += 1;
+        renames.pending.insert(*album);
+    }
+
+    renames
+}
+
+/// Renames the files, returning how many were renamed and the errors, including the files
+/// that were skipped. Albums with files left to rename stay marked for renaming.
+///
+/// The journal is only opened once there is something to rename.
+fn rename(
+    app: &mut App,
+    journal: impl FnOnce() -> Result<Journal, JournalError>,
+) -> (usize, Vec<String>) {
+    let Renames {
+        plan,
+        tracks,
+        mut errors,
+        mut pending,
+        ..
+    } = renames(app);
+
+    if !plan
+        .renames
+        .iter()
+        .any(|rename| rename.action == Action::Rename)
+    {
+        keep_pending(app, &pending);
+        return (0, errors);
+    }
+
+    let mut journal = match journal() {
+        Ok(journal) => journal,
+        Err(error) => {
+            errors.insert(0, error.to_string());
+            return (0, errors);
+        }
+    };
+
+    let mut renamed = Vec::new();
+
+    execute(
+        plan.schedule(),
+        false,
+        |index, from| {
+            let rename = &plan.renames[index];
+            let created = move_track(rename, from)?;
+            renamed.push(index);
+            journal.record(&rename.source, &rename.target, None, created)?;
+            Ok(())
+        },
+        |index, error| {
+            let (album, _) = tracks[index];
+            let file = &plan.renames[index].source;
+            errors.push(format!("{}: \"{}\"", error, file.display()));
+            pending.insert(album);
+        },
+    );
+
+    for &index in &renamed {
+        let (album, track) = tracks[index];
+        app.albums[album].tracks[track].file = plan.renames[index].target.clone();
+    }
+    keep_pending(app, &pending);
+
+    (renamed.len(), errors)
+}
+
+/// Moves the file of a track from `from`, which is where it currently is, to its new name.
+/// Returns the directories created for it.
+fn move_track(rename: &PlannedRename, from: &Path) -> Result<Vec<PathBuf>, FmmdError> {
+    // Guards against files showing up since planning and moves that failed earlier
+    if rename.target.exists() && !rename.overwrite && !same_file(from, &rename.target) {
+        return Err(FmmdError::TargetExists);
+    }
+
+    let created = match rename.target.parent() {
+        Some(parent) => create_dirs(parent)?,
+        None => Vec::new(),
+    };
+    move_file(from, &rename.target)?;
+    Ok(created)
+}
+
+/// Stops renaming the files of albums once none of them are left to rename
+fn keep_pending(app: &mut App, pending: &HashSet<usize>) {
+    for (index, album) in app.albums.iter_mut().enumerate() {
+        album.rename = album.rename && pending.contains(&index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use id3::TagLike;
+
+    use super::*;
+    use crate::fixtures;
+    use crate::metadata::Metadata;
+    use crate::sanitize::Profile;
+
+    /// An app renaming files in `dir` after their title, each holding its own name
+    fn app(dir: &Path, titles: &[(&str, &str)], on_conflict: ConflictPolicy) -> App {
+        let files = titles
+            .iter()
+            .map(|(name, title)| {
+                let file = dir.join(name);
+                fs::write(&file, name).unwrap();
+                let mut tag = id3::Tag::new();
+                tag.set_title(*title);
+                (file, Box::new(tag) as Box<dyn Metadata>)
+            })
+            .collect();
+
+        let template = Template::parse("{title}").unwrap();
+        let sanitizer = Sanitizer::new(Profile::Posix, Vec::new());
+        let mut app = App::new(files, template, sanitizer, on_conflict);
+        for album in &mut app.albums {
+            album.rename = true;
+        }
+        app
+    }
+
+    fn contents(file: PathBuf) -> String {
+        fs::read_to_string(file).unwrap()
+    }
+
+    #[test]
+    fn skipped_renames_keep_the_files_they_would_replace() {
+        let dir = fixtures::directory("tui-chain");
+        fs::write(dir.join("c.mp3"), "kept").unwrap();
+        let mut app = app(
+            &dir,
+            &[("a.mp3", "b"), ("b.mp3", "c")],
+            ConflictPolicy::Skip,
+        );
+
+        let journal = dir.join("journal");
+        let (renamed, errors) = rename(&mut app, || Ok(Journal::for_tests(&journal)));
+
+        // b.mp3 stays as c.mp3 is taken, which in turn keeps a.mp3 where it is
+        assert_eq!(renamed, 0);
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(contents(dir.join("a.mp3")), "a.mp3");
+        assert_eq!(contents(dir.join("b.mp3")), "b.mp3");
+        assert_eq!(contents(dir.join("c.mp3")), "kept");
+        assert!(app.albums[0].rename);
+        assert!(!journal.exists());
+    }
+
+    #[test]
+    fn conflicts_follow_the_configured_policy() {
+        let dir = fixtures::directory("tui-suffix");
+        fs::write(dir.join("c.mp3"), "kept").unwrap();
+        let mut app = app(
+            &dir,
+            &[("a.mp3", "b"), ("b.mp3", "c")],
+            ConflictPolicy::Suffix,
+        );
+
+        let journal = dir.join("journal");
+        let (renamed, errors) = rename(&mut app, || Ok(Journal::for_tests(&journal)));
+
+        assert_eq!((renamed, errors), (2, Vec::<String>::new()));
+        assert_eq!(contents(dir.join("b.mp3")), "a.mp3");
+        assert_eq!(contents(dir.join("c (2).mp3")), "b.mp3");
+        assert_eq!(contents(dir.join("c.mp3")), "kept");
+        assert_eq!(app.albums[0].tracks[1].file, dir.join("c (2).mp3"));
+        assert!(!app.albums[0].rename);
+        assert_eq!(fs::read_dir(&journal).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn conflicts_keep_everything_in_place_when_they_fail_the_save() {
+        let dir = fixtures::directory("tui-fail");
+        fs::write(dir.join("c.mp3"), "kept").unwrap();
+        let mut app = app(
+            &dir,
+            &[("a.mp3", "d"), ("b.mp3", "c")],
+            ConflictPolicy::Fail,
+        );
+
+        let journal = dir.join("journal");
+        let (renamed, errors) = rename(&mut app, || Ok(Journal::for_tests(&journal)));
+
+        assert_eq!(renamed, 0);
+        assert_eq!(errors[0], FmmdError::Conflicts(1).to_string());
+        assert!(dir.join("a.mp3").exists());
+        assert!(!dir.join("d.mp3").exists());
+        assert!(app.albums[0].rename);
+    }
+
+    #[test]
+    fn parked_files_never_replace_files_that_failed_to_move() {
+        let dir = fixtures::directory("tui-cycle");
+        let mut plan = Plan::default();
+        for (source, target) in [("a", "b"), ("b", "c"), ("c", "a")] {
+            fs::write(dir.join(source), source).unwrap();
+            let metadata = Box::new(id3::Tag::new());
+            plan.add(dir.join(source), dir.join(target), Vec::new(), metadata);
+        }
+        assert_eq!(plan.resolve_conflicts(ConflictPolicy::Skip), 0);
+
+        // a is parked, c takes its place and then moving b fails, so a can't take b's
+        let mut errors = Vec::new();
+        execute(
+            plan.schedule(),
+            false,
+            |index, from| match index {
+                1 => Err(FmmdError::TargetExists),
+                _ => move_track(&plan.renames[index], from).map(|_| ()),
+            },
+            |_, error| errors.push(error),
+        );
+
+        assert_eq!(contents(dir.join("a")), "c");
+        assert_eq!(contents(dir.join("b")), "b");
+        assert!(!dir.join("c").exists());
+        assert!(matches!(
+            errors.last(),
+            Some(FmmdError::LeftAtTemporaryName(temporary)) if contents(temporary.clone()) == "a"
+        ));
+    }
+}
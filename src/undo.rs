@@ -0,0 +1,315 @@
+//! The `undo` and `history` subcommands
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use owo_colors::OwoColorize;
+
+use crate::error::FmmdError;
+use crate::files::move_file;
+use crate::index::ChecksumMoves;
+use crate::journal::{self, Entry};
+use crate::plan::{execute, normalize, same_file, schedule};
+use crate::report::{print_error, Status};
+
+#[derive(Args)]
+pub struct UndoArgs {
+    /// ID of the run to undo (see `fmmd history`); defaults to the latest run
+    run_id: Option<String>,
+
+    /// Show what would be restored without renaming files
+    #[arg(short, long)]
+    dry_run: bool,
+
+    /// Restore files even if they were changed since the run
+    #[arg(short, long)]
+    force: bool,
+}
+
+/// Reverts the renames of a previous run
+pub fn undo(args: &UndoArgs) -> Result<Status, FmmdError> {
+    let mut run = journal::find(args.run_id.as_deref())?;
+
+    if let Some(at) = &run.undone {
+        println!(
+            "{}",
+            format!("Run {} was already undone at {}", run.id, at).yellow()
+        );
+        return Ok(Status::Success);
+    }
+
+    let mut failed = 0;
+    let mut entries = Vec::new();
+
+    for entry in run.renames.iter().rev() {
+        let new = entry.new.as_path();
+
+        // Already restored by an earlier, partially failed undo
+        if !new.exists() && entry.old.exists() {
+            continue;
+        }
+
+        let problem = if !new.exists() {
+            Some("File no longer exists")
+        } else if !entry.is_unchanged() && !args.force {
+            Some("File was changed since the run, use --force to restore it anyway")
+        } else {
+            None
+        };
+
+        match problem {
+            Some(problem) => {
+                print_error(new, problem);
+                failed += 1;
+            }
+            None => entries.push(entry),
+        }
+    }
+
+    // Original paths may only be taken by files that are restored themselves, as happens
+    // when a run swapped names around
+    let restored: HashSet<PathBuf> = entries.iter().map(|entry| normalize(&entry.new)).collect();
+    entries.retain(|entry| {
+        let taken = entry.old.exists() && !restored.contains(&normalize(&entry.old));
+        if taken {
+            print_error(&entry.new, "Original path is taken by another file");
+            failed += 1;
+        }
+        !taken
+    });
+
+    if args.dry_run {
+        for entry in &entries {
+            println!("{} -> {}", entry.new.display(), entry.old.display());
+        }
+        return Ok(Status::from_counts(failed, entries.len()));
+    }
+
+    let moves: Vec<(usize, &Path, &Path)> = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (index, entry.new.as_path(), entry.old.as_path()))
+        .collect();
+
+    let mut failed_moves = HashSet::new();
+    let mut checksums = ChecksumMoves::default();
+
+    execute(
+        schedule(&moves),
+        false,
+        |index, from| restore(entries[index], from, &mut checksums),
+        |index, error| {
+            print_error(&entries[index].new, error);
+            failed_moves.insert(index);
+        },
+    );
+
+    failed += failed_moves.len();
+    let restored: Vec<&Entry> = entries
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !failed_moves.contains(index))
+        .map(|(_, entry)| *entry)
+        .collect();
+    remove_created_dirs(&restored);
+    let restored = restored.len();
+    checksums.apply()?;
+
+    if failed == 0 {
+        run.mark_undone()?;
+    } else {
+        eprintln!(
+            "{}",
+            format!(
+                "{} of {} files could not be restored, run `fmmd undo {}` again to retry",
+                failed,
+                run.renames.len(),
+                run.id
+            )
+            .yellow()
+        );
+    }
+
+    Ok(Status::from_counts(failed, restored))
+}
+
+/// Moves a file back to its old name from `from`, which is where it currently is
+fn restore(entry: &Entry, from: &Path, checksums: &mut ChecksumMoves) -> Result<(), FmmdError> {
+    let (old, new) = (entry.old.as_path(), entry.new.as_path());
+
+    if old.exists() && !same_file(from, old) {
+        return Err(FmmdError::TargetExists);
+    }
+
+    println!("{} -> {}", new.display(), old.display());
+
+    if let Some(parent) = old.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    move_file(from, old)?;
+    checksums.moved(new, old)?;
+
+    Ok(())
+}
+
+/// Removes the directories the run created for the new names of `entries` once they are
+/// empty, innermost first. Directories that were there before the run are left alone, even
+/// when undoing it empties them.
+fn remove_created_dirs(entries: &[&Entry]) {
+    let mut dirs: Vec<&PathBuf> = entries.iter().flat_map(|entry| &entry.created).collect();
+    dirs.sort_by_key(|dir| (Reverse(dir.components().count()), *dir));
+    dirs.dedup();
+
+    for dir in dirs {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+/// Lists previous runs, oldest first
+pub fn history() -> Result<(), FmmdError> {
+    let runs = journal::runs()?;
+
+    if runs.is_empty() {
+        println!("No runs recorded yet");
+        return Ok(());
+    }
+
+    for run in runs {
+        let status = match &run.undone {
+            Some(at) => format!("undone {}", at).yellow().to_string(),
+            None => String::new(),
+        };
+
+        println!(
+            "{}  {}  {:>5} files  {}  {}",
+            run.id.bold(),
+            run.started,
+            run.renames.len(),
+            run.cwd.display(),
+            status
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    /// Renames `old` to `new` like a run would, returning its journal entry
+    fn renamed(old: &Path, new: &Path) -> Entry {
+        let created = crate::files::create_dirs(new.parent().unwrap()).unwrap();
+        move_file(old, new).unwrap();
+
+        let metadata = fs::metadata(new).unwrap();
+        Entry {
+            old: old.to_path_buf(),
+            new: new.to_path_buf(),
+            size: metadata.len(),
+            mtime: 0,
+            checksum: None,
+            created,
+        }
+    }
+
+    #[test]
+    fn files_are_restored_and_created_directories_removed() {
+        let library = fixtures::directory("undo-restore");
+        let old = library.join("Artist").join("Album").join("01.mp3");
+        fs::create_dir_all(old.parent().unwrap()).unwrap();
+        fs::write(&old, "audio").unwrap();
+
+        let new = library.join("New Artist").join("New Album").join("01.mp3");
+        let entry = renamed(&old, &new);
+        assert_eq!(
+            entry.created,
+            [
+                library.join("New Artist"),
+                new.parent().unwrap().to_path_buf()
+            ]
+        );
+
+        // Left empty by the run and removed with --remove-empty-dirs
+        fs::remove_dir_all(library.join("Artist")).unwrap();
+
+        let mut checksums = ChecksumMoves::with_index(None);
+        restore(&entry, &entry.new, &mut checksums).unwrap();
+        remove_created_dirs(&[&entry]);
+
+        assert_eq!(fs::read_to_string(&old).unwrap(), "audio");
+        assert!(!library.join("New Artist").exists());
+        assert!(library.exists());
+    }
+
+    #[test]
+    fn directories_that_were_there_before_are_kept() {
+        let library = fixtures::directory("undo-kept");
+        let old = library.join("Inbox").join("01.mp3");
+        let new = library.join("Artist").join("01.mp3");
+        fs::create_dir_all(old.parent().unwrap()).unwrap();
+        fs::create_dir_all(new.parent().unwrap()).unwrap();
+        fs::write(&old, "audio").unwrap();
+
+        let entry = renamed(&old, &new);
+        assert!(entry.created.is_empty());
+
+        restore(&entry, &entry.new, &mut ChecksumMoves::with_index(None)).unwrap();
+        remove_created_dirs(&[&entry]);
+
+        assert!(old.exists());
+        assert!(library.join("Artist").is_dir());
+    }
+
+    #[test]
+    fn directories_still_in_use_are_kept() {
+        let library = fixtures::directory("undo-in-use");
+        let new_dir = library.join("Artist");
+        let entries: Vec<Entry> = ["01.mp3", "02.mp3"]
+            .iter()
+            .map(|name| {
+                fs::write(library.join(name), "audio").unwrap();
+                renamed(&library.join(name), &new_dir.join(name))
+            })
+            .collect();
+        assert_eq!(entries[0].created, std::slice::from_ref(&new_dir));
+        assert!(entries[1].created.is_empty());
+
+        // Only the second file is restored, the first one keeps the directory in use
+        restore(
+            &entries[1],
+            &entries[1].new,
+            &mut ChecksumMoves::with_index(None),
+        )
+        .unwrap();
+        remove_created_dirs(&[&entries[1]]);
+        assert!(new_dir.join("01.mp3").exists());
+
+        restore(
+            &entries[0],
+            &entries[0].new,
+            &mut ChecksumMoves::with_index(None),
+        )
+        .unwrap();
+        remove_created_dirs(&[&entries[0]]);
+        assert!(!new_dir.exists());
+    }
+
+    #[test]
+    fn taken_names_are_not_overwritten() {
+        let library = fixtures::directory("undo-taken");
+        let (old, new) = (library.join("a.mp3"), library.join("b.mp3"));
+        fs::write(&old, "old").unwrap();
+        let entry = renamed(&old, &new);
+        fs::write(&old, "another file").unwrap();
+
+        let result = restore(&entry, &entry.new, &mut ChecksumMoves::with_index(None));
+        assert!(matches!(result, Err(FmmdError::TargetExists)));
+        assert_eq!(fs::read_to_string(&old).unwrap(), "another file");
+        assert_eq!(fs::read_to_string(&new).unwrap(), "old");
+    }
+}
@@ -0,0 +1,83 @@
+//! The `verify` subcommand, proving that writing tags left the audio of files untouched.
+//!
+//! The audio checksum of each file is compared against the one recorded in the index by
+//! `fmmd index build --checksums` or `fmmd index update --checksums`, or with `--run`,
+//! against the ones recorded in the journal for the files renamed in a run.
+use std::path::PathBuf;
+
+use clap::Args;
+use owo_colors::OwoColorize;
+
+use crate::checksum;
+use crate::error::FmmdError;
+use crate::index::Index;
+use crate::input::InputArgs;
+use crate::journal;
+use crate::report::{print_error, Status};
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Check the files renamed in this run (see `fmmd history`) against the checksums in
+    /// the journal instead
+    #[arg(long, value_name = "ID", conflicts_with = "files")]
+    run: Option<String>,
+}
+
+pub fn verify(args: &VerifyArgs) -> Result<Status, FmmdError> {
+    let mut errors = 0;
+    let mut verified = 0;
+    let mut changed = 0;
+    let mut unrecorded = 0;
+
+    let files: Vec<(PathBuf, Option<String>)> = match &args.run {
+        Some(id) => journal::find(Some(id))?
+            .renames
+            .into_iter()
+            .map(|entry| (entry.new, entry.checksum))
+            .collect(),
+        None => {
+            let index = Index::open()?;
+            let files = args.input.collect_with(|file, error| {
+                print_error(file, error);
+                errors += 1;
+            });
+
+            let mut recorded = Vec::new();
+            for file in files {
+                let checksum = index.checksum(&file)?;
+                recorded.push((file, checksum));
+            }
+            recorded
+        }
+    };
+
+    for (file, recorded) in files {
+        let Some(recorded) = recorded else {
+            println!("{} {}", "not recorded".yellow(), file.display());
+            unrecorded += 1;
+            continue;
+        };
+
+        match checksum::audio(&file) {
+            Ok(checksum) if checksum == recorded => verified += 1,
+            Ok(_) => {
+                println!("{} {}", "changed".red(), file.display());
+                changed += 1;
+            }
+            Err(error) => {
+                print_error(&file, error);
+                errors += 1;
+            }
+        }
+    }
+
+    println!(
+        "{} verified, {} changed, {} not recorded",
+        verified, changed, unrecorded
+    );
+
+    Ok(Status::from_counts(errors + changed + unrecorded, verified))
+}